@@ -0,0 +1,41 @@
+use crate::{
+    cycles_handler::CycleTransferResult,
+    data_storage::{ArchiveState, ArchivedPayload, ArchivedPayloadPage},
+    initialization::ArchiveInitArgs,
+    pagination::MsgPage,
+};
+use candid::{export_service, Principal};
+use canister_types::message::Message;
+use ic_cdk::query;
+
+/// Generates the Candid interface for this canister
+///
+/// # Returns
+/// A string containing the Candid interface definition
+#[query(name = "__get_candid_interface_tmp_hack")]
+fn generate_candid_interface() -> String {
+    export_service!();
+    __export_service()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    /// Test function to save the generated Candid interface to a file
+    #[test]
+    fn save_candid_interface() {
+        let output_dir = env::current_dir()
+            .expect("Failed to get current directory");
+
+        let candid_interface = generate_candid_interface();
+        let output_file = output_dir.join("canister_archive.did");
+
+        fs::write(&output_file, candid_interface)
+            .expect("Failed to write Candid interface to file");
+
+        assert!(output_file.exists(), "Candid interface file was not created");
+    }
+}
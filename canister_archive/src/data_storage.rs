@@ -0,0 +1,401 @@
+use candid::{CandidType, Principal};
+
+use canister_types::message::Message;
+use ic_stable_structures::{
+    memory_manager::{MemoryId, MemoryManager, VirtualMemory},
+    DefaultMemoryImpl, StableBTreeMap, StableCell,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    cell::RefCell,
+    collections::BTreeSet,
+    ops::Bound::{Excluded, Included},
+};
+
+use crate::{
+    msg_key::{MsgIdKey, MsgKey},
+    pagination::{MsgCursor, MsgPage},
+    storable::cbor_storable,
+    MAX_SCHEMA_SCAN,
+};
+
+type MemSpace = VirtualMemory<DefaultMemoryImpl>;
+
+/// Archive canister configuration and state
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug)]
+pub struct ArchiveState {
+    pub name: String,
+    /// The indexer canister that created this archive and may append to it
+    pub indexer: Principal,
+    pub created_at: u64,
+    pub message_count: u64,
+}
+
+impl Default for ArchiveState {
+    fn default() -> Self {
+        Self {
+            name: String::from("message_archive"),
+            indexer: Principal::anonymous(),
+            created_at: 0,
+            message_count: 0,
+        }
+    }
+}
+
+cbor_storable!(ArchiveState);
+
+impl ArchiveState {
+    /// Checks if the caller is the owning indexer
+    pub fn indexer_permission(&self, caller: Principal) -> Result<(), String> {
+        if caller == self.indexer {
+            Ok(())
+        } else {
+            Err("Unauthorized: only the indexer can write to this archive".to_string())
+        }
+    }
+}
+
+/// An archived message together with the principal that created it
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug)]
+pub struct MsgEntry {
+    pub message: Message,
+    pub principal: Principal,
+    /// Whether the message was hidden by a moderator of the indexer
+    #[serde(default)]
+    pub hidden: bool,
+    /// Schema version of the payload type the payload is encoded with
+    #[serde(default = "initial_schema_version")]
+    pub schema_version: u32,
+}
+
+// Messages were archived before schema versions existed, on the first version
+fn initial_schema_version() -> u32 {
+    INITIAL_SCHEMA_VERSION
+}
+
+/// Schema version of messages handed over without one
+pub const INITIAL_SCHEMA_VERSION: u32 = 1;
+
+/// Payload of an archived message with the schema version it is encoded with
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug)]
+pub struct ArchivedPayload {
+    pub msg_id: String,
+    pub schema_version: u32,
+    pub payload: Vec<u8>,
+}
+
+/// Payloads of a payload type on an older schema version, oldest first
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug)]
+pub struct ArchivedPayloadPage {
+    pub payloads: Vec<ArchivedPayload>,
+    /// Cursor to pass back to continue the scan, None once every message was scanned
+    pub next_cursor: Option<String>,
+}
+
+cbor_storable!(MsgEntry);
+
+// Memory management constants
+const STATE_MEM_ID: MemoryId = MemoryId::new(0);
+const MSG_MEM_ID: MemoryId = MemoryId::new(1);
+const MSG_ID_INDEX_MEM_ID: MemoryId = MemoryId::new(2);
+
+// Thread-local storage for canister state
+thread_local! {
+    static STATE: RefCell<ArchiveState> = RefCell::new(ArchiveState::default());
+
+    static MEM_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+
+    static STATE_STORE: RefCell<StableCell<ArchiveState, MemSpace>> = RefCell::new(
+        StableCell::init(
+            MEM_MANAGER.with_borrow(|m| m.get(STATE_MEM_ID)),
+            ArchiveState::default()
+        ).expect("failed to init STATE_STORE store")
+    );
+
+    static MSG_STORE: RefCell<StableBTreeMap<MsgKey, MsgEntry, MemSpace>> = RefCell::new(
+        StableBTreeMap::init(
+            MEM_MANAGER.with_borrow(|m| m.get(MSG_MEM_ID)),
+        )
+    );
+
+    static MSG_ID_INDEX: RefCell<StableBTreeMap<MsgIdKey, u64, MemSpace>> = RefCell::new(
+        StableBTreeMap::init(
+            MEM_MANAGER.with_borrow(|m| m.get(MSG_ID_INDEX_MEM_ID)),
+        )
+    );
+}
+
+/// State management module
+pub mod state {
+    use super::*;
+
+    /// Execute a function with immutable access to the archive state
+    pub fn with<R>(f: impl FnOnce(&ArchiveState) -> R) -> R {
+        STATE.with(|r| f(&r.borrow()))
+    }
+
+    /// Execute a function with mutable access to the archive state
+    pub fn with_mut<R>(f: impl FnOnce(&mut ArchiveState) -> R) -> R {
+        STATE.with(|r| f(&mut r.borrow_mut()))
+    }
+
+    /// Load archive state from stable storage
+    pub fn load() {
+        STATE_STORE.with(|r| {
+            let s = r.borrow().get().clone();
+            STATE.with(|h| {
+                *h.borrow_mut() = s;
+            });
+        });
+    }
+
+    /// Save archive state to stable storage
+    pub fn save() {
+        STATE.with(|h| {
+            STATE_STORE.with(|r| {
+                r.borrow_mut()
+                    .set(h.borrow().clone())
+                    .expect("failed to set STATE_STORE data");
+            });
+        });
+    }
+}
+
+/// Archived message module
+pub mod message {
+    use super::*;
+
+    /// Append messages handed over by the indexer
+    ///
+    /// Appending is idempotent: a message already archived under the same
+    /// payload type and id is overwritten rather than duplicated. Hidden
+    /// messages are kept but not served. `schema_versions` holds the schema
+    /// version of each message, messages without one are on
+    /// `INITIAL_SCHEMA_VERSION`.
+    ///
+    /// # Returns
+    /// The number of newly archived messages
+    pub fn append_messages(
+        messages: Vec<(Message, Principal)>,
+        hidden_msg_ids: &BTreeSet<String>,
+        schema_versions: &[u32],
+    ) -> u64 {
+        let mut added = 0;
+
+        for (index, (message, principal)) in messages.into_iter().enumerate() {
+            let key = MsgKey::of(&message);
+            let id_key = MsgIdKey::new(&key.payload_type, &key.msg_id);
+            let hidden = hidden_msg_ids.contains(&key.msg_id);
+            let schema_version =
+                schema_versions.get(index).copied().unwrap_or(INITIAL_SCHEMA_VERSION);
+
+            let previous = MSG_ID_INDEX.with_borrow_mut(|index| index.insert(id_key, key.timestamp));
+            MSG_STORE.with_borrow_mut(|store| {
+                if let Some(timestamp) = previous {
+                    store.remove(&MsgKey { timestamp, ..key.clone() });
+                }
+                store.insert(key, MsgEntry { message, principal, hidden, schema_version });
+            });
+            if previous.is_none() {
+                added += 1;
+            }
+        }
+
+        state::with_mut(|archive| archive.message_count += added);
+        added
+    }
+
+    /// Delete archived messages of a payload type
+    ///
+    /// Messages that are not archived are skipped.
+    ///
+    /// # Returns
+    /// The number of deleted messages
+    pub fn delete_messages(message_type: &str, msg_ids: &[String]) -> u64 {
+        let mut deleted = 0;
+
+        for msg_id in msg_ids {
+            let id_key = MsgIdKey::new(message_type, msg_id);
+            let Some(timestamp) = MSG_ID_INDEX.with_borrow_mut(|index| index.remove(&id_key))
+            else {
+                continue;
+            };
+            MSG_STORE.with_borrow_mut(|store| {
+                store.remove(&MsgKey {
+                    payload_type: id_key.payload_type,
+                    timestamp,
+                    msg_id: id_key.msg_id,
+                })
+            });
+            deleted += 1;
+        }
+
+        state::with_mut(|archive| {
+            archive.message_count = archive.message_count.saturating_sub(deleted)
+        });
+        deleted
+    }
+
+    /// Hide or unhide archived messages of a payload type
+    ///
+    /// Messages that are not archived are skipped.
+    ///
+    /// # Returns
+    /// The number of archived messages among `msg_ids`
+    pub fn set_hidden(message_type: &str, msg_ids: &[String], hidden: bool) -> u64 {
+        let mut updated = 0;
+
+        for msg_id in msg_ids {
+            let id_key = MsgIdKey::new(message_type, msg_id);
+            let Some(timestamp) = MSG_ID_INDEX.with_borrow(|index| index.get(&id_key)) else {
+                continue;
+            };
+            let key = MsgKey {
+                payload_type: id_key.payload_type,
+                timestamp,
+                msg_id: id_key.msg_id,
+            };
+            MSG_STORE.with_borrow_mut(|store| {
+                if let Some(mut entry) = store.get(&key) {
+                    entry.hidden = hidden;
+                    store.insert(key, entry);
+                    updated += 1;
+                }
+            });
+        }
+        updated
+    }
+
+    /// Retrieve an archived message by its type and ID, unless it is hidden
+    pub fn get_message(message_type: &str, message_id: &str) -> Option<(Message, Principal)> {
+        let id_key = MsgIdKey::new(message_type, message_id);
+        let timestamp = MSG_ID_INDEX.with_borrow(|index| index.get(&id_key))?;
+
+        MSG_STORE
+            .with_borrow(|store| {
+                store.get(&MsgKey {
+                    payload_type: id_key.payload_type,
+                    timestamp,
+                    msg_id: id_key.msg_id,
+                })
+            })
+            .filter(|entry| !entry.hidden)
+            .map(|entry| (entry.message, entry.principal))
+    }
+
+    /// Retrieve a page of archived messages older than the cursor, newest first
+    ///
+    /// Hidden messages are skipped.
+    pub fn get_message_page(
+        message_type: &str,
+        limit: usize,
+        before: Option<&MsgCursor>,
+    ) -> MsgPage {
+        let upper = match before {
+            Some(cursor) => MsgKey {
+                payload_type: message_type.to_string(),
+                timestamp: cursor.timestamp,
+                msg_id: cursor.msg_id.clone(),
+            },
+            None => MsgKey::type_upper_bound(message_type),
+        };
+
+        let fetched = MSG_STORE.with_borrow(|store| {
+            store
+                .range(MsgKey::lower_bound(message_type, 0)..upper)
+                .rev()
+                .filter(|(_, entry)| !entry.hidden)
+                .take(limit + 1)
+                .map(|(_, entry)| (entry.message, entry.principal))
+                .collect()
+        });
+        MsgPage::from_fetched(fetched, limit)
+    }
+
+    /// Scan the messages of a payload type after the cursor, oldest first, for
+    /// payloads on a schema version older than `current_version`
+    ///
+    /// At most `MAX_SCHEMA_SCAN` messages are scanned and `limit` payloads
+    /// returned per call; hidden messages are included.
+    pub fn get_outdated_payloads(
+        message_type: &str,
+        current_version: u32,
+        after: Option<&MsgCursor>,
+        limit: usize,
+    ) -> ArchivedPayloadPage {
+        let lower = match after {
+            Some(cursor) => Excluded(MsgKey {
+                payload_type: message_type.to_string(),
+                timestamp: cursor.timestamp,
+                msg_id: cursor.msg_id.clone(),
+            }),
+            None => Included(MsgKey::lower_bound(message_type, 0)),
+        };
+        let upper = Excluded(MsgKey::type_upper_bound(message_type));
+
+        let mut payloads = Vec::new();
+        let mut last_scanned = None;
+        let mut exhausted = true;
+        MSG_STORE.with_borrow(|store| {
+            for (scanned, (key, entry)) in store.range((lower, upper)).enumerate() {
+                if scanned == MAX_SCHEMA_SCAN || payloads.len() == limit {
+                    exhausted = false;
+                    break;
+                }
+                if entry.schema_version < current_version {
+                    payloads.push(ArchivedPayload {
+                        msg_id: key.msg_id.clone(),
+                        schema_version: entry.schema_version,
+                        payload: entry.message.payload.to_vec(),
+                    });
+                }
+                last_scanned = Some(key);
+            }
+        });
+
+        let next_cursor = match last_scanned {
+            Some(key) if !exhausted => Some(
+                MsgCursor {
+                    timestamp: key.timestamp,
+                    msg_id: key.msg_id,
+                    payload_type: Some(key.payload_type),
+                }
+                .encode(),
+            ),
+            _ => None,
+        };
+        ArchivedPayloadPage { payloads, next_cursor }
+    }
+
+    /// Replace the payloads of archived messages re-encoded by the indexer
+    ///
+    /// Messages that are no longer archived are skipped.
+    ///
+    /// # Returns
+    /// The number of replaced payloads
+    pub fn replace_payloads(message_type: &str, payloads: Vec<ArchivedPayload>) -> u64 {
+        let mut replaced = 0;
+
+        for archived in payloads {
+            let id_key = MsgIdKey::new(message_type, &archived.msg_id);
+            let Some(timestamp) = MSG_ID_INDEX.with_borrow(|index| index.get(&id_key)) else {
+                continue;
+            };
+            let key = MsgKey {
+                payload_type: id_key.payload_type,
+                timestamp,
+                msg_id: id_key.msg_id,
+            };
+            MSG_STORE.with_borrow_mut(|store| {
+                if let Some(mut entry) = store.get(&key) {
+                    entry.message.payload = archived.payload.into();
+                    entry.schema_version = archived.schema_version;
+                    store.insert(key, entry);
+                    replaced += 1;
+                }
+            });
+        }
+        replaced
+    }
+}
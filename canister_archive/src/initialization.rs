@@ -0,0 +1,41 @@
+use candid::{CandidType, Principal};
+use serde::Deserialize;
+
+use crate::data_storage;
+
+/// Initialization arguments passed by the indexer when it creates the archive
+#[derive(CandidType, Clone, Deserialize, Debug)]
+pub struct ArchiveInitArgs {
+    pub name: String,
+    pub indexer: Principal,
+}
+
+/// Initialize the archive canister
+///
+/// # Arguments
+/// * `args` - Name of the archive and the indexer allowed to append to it
+#[ic_cdk::init]
+fn initialize_system(args: ArchiveInitArgs) {
+    data_storage::state::with_mut(|archive| {
+        if !args.name.is_empty() {
+            archive.name = args.name;
+        }
+        archive.indexer = args.indexer;
+        archive.created_at = ic_cdk::api::time();
+    });
+
+    // Persist the initialized state
+    data_storage::state::save();
+}
+
+/// Pre-upgrade hook to save current state before upgrade
+#[ic_cdk::pre_upgrade]
+fn before_upgrade() {
+    data_storage::state::save();
+}
+
+/// Post-upgrade hook to restore state after upgrade
+#[ic_cdk::post_upgrade]
+fn after_upgrade() {
+    data_storage::state::load();
+}
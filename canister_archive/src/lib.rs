@@ -0,0 +1,24 @@
+use ic_cdk::export_candid;
+
+mod cycles_handler;
+mod initialization;
+mod query_operations;
+mod update_operations;
+pub mod candid_generator;
+mod data_storage;
+
+// Shared with the indexer, which stores and paginates messages the same way
+#[allow(dead_code)]
+#[path = "../../canister_indexer/src/msg_key.rs"]
+mod msg_key;
+#[allow(dead_code)]
+#[path = "../../canister_indexer/src/pagination.rs"]
+mod pagination;
+#[path = "../../canister_indexer/src/storable.rs"]
+mod storable;
+
+export_candid!();
+
+pub const MAX_MSG_COUNT: u64 = 2000;
+/// Maximum number of messages scanned per `fetch_outdated_payloads` call
+pub const MAX_SCHEMA_SCAN: usize = 10_000;
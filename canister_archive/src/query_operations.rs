@@ -0,0 +1,75 @@
+use candid::Principal;
+use canister_types::message::Message;
+use ic_cdk::query;
+
+use crate::{
+    data_storage::{self, ArchiveState, ArchivedPayloadPage},
+    pagination::{MsgCursor, MsgPage},
+    MAX_MSG_COUNT,
+};
+
+/// Query function to get the archive configuration and message count
+#[query]
+fn get_archive_info() -> ArchiveState {
+    data_storage::state::with(|archive| archive.clone())
+}
+
+/// Query function to fetch an archived message by its type and ID
+///
+/// # Arguments
+/// * `msg_type` - The type/category of the message
+/// * `msg_id` - The unique identifier of the message
+#[query]
+fn fetch_msg(msg_type: String, msg_id: String) -> Option<(Message, Principal)> {
+    data_storage::message::get_message(&msg_type, &msg_id)
+}
+
+/// Query function to fetch a page of archived messages using cursor pagination
+///
+/// # Arguments
+/// * `msg_type` - The type/category of messages to retrieve
+/// * `max_count` - Maximum number of messages to return (capped at `MAX_MSG_COUNT`)
+/// * `cursor` - `next_cursor` of the previous page, or None for the first page
+///
+/// # Note
+/// Messages are returned newest first; cursors are compatible with the indexer's
+#[query]
+fn fetch_msg_page(
+    msg_type: String,
+    max_count: usize,
+    cursor: Option<String>,
+) -> Result<MsgPage, String> {
+    let cursor = MsgCursor::decode_opt(cursor)?;
+    let limit = max_count.min(MAX_MSG_COUNT as usize);
+    Ok(data_storage::message::get_message_page(&msg_type, limit, cursor.as_ref()))
+}
+
+/// Query function to scan for payloads on an older schema version
+///
+/// Used by the indexer to re-encode archived messages after the schema
+/// version of their payload type was raised.
+///
+/// # Arguments
+/// * `msg_type` - The type/category of messages to scan
+/// * `current_version` - Schema version the payloads should be encoded with
+/// * `max_count` - Maximum number of payloads to return (capped at `MAX_MSG_COUNT`)
+/// * `cursor` - `next_cursor` of the previous page, or None to scan from the oldest message
+///
+/// # Note
+/// Messages are scanned oldest first, at most `MAX_SCHEMA_SCAN` per call
+#[query]
+fn fetch_outdated_payloads(
+    msg_type: String,
+    current_version: u32,
+    max_count: usize,
+    cursor: Option<String>,
+) -> Result<ArchivedPayloadPage, String> {
+    let cursor = MsgCursor::decode_opt(cursor)?;
+    let limit = max_count.min(MAX_MSG_COUNT as usize);
+    Ok(data_storage::message::get_outdated_payloads(
+        &msg_type,
+        current_version,
+        cursor.as_ref(),
+        limit,
+    ))
+}
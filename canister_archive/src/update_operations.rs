@@ -0,0 +1,83 @@
+use candid::Principal;
+use canister_types::message::Message;
+use ic_cdk::update;
+use std::collections::BTreeSet;
+
+use crate::data_storage::{self, ArchivedPayload};
+
+/// Append a batch of messages moved out of the indexer
+///
+/// Only the indexer that created this archive may call this method.
+///
+/// # Arguments
+/// * `messages` - Messages with the principals that created them
+/// * `hidden_msg_ids` - Ids of the messages hidden by a moderator of the indexer,
+///   which are archived but not served
+/// * `schema_versions` - Schema version of the payload of each message, in
+///   the order of `messages`; messages without one are on the first version
+///
+/// # Returns
+/// * `Result<u64, String>` - Number of newly archived messages or error
+#[update]
+fn append_msgs(
+    messages: Vec<(Message, Principal)>,
+    hidden_msg_ids: Option<Vec<String>>,
+    schema_versions: Option<Vec<u32>>,
+) -> Result<u64, String> {
+    data_storage::state::with(|archive| archive.indexer_permission(ic_cdk::caller()))?;
+    let hidden_msg_ids: BTreeSet<String> = hidden_msg_ids.unwrap_or_default().into_iter().collect();
+    Ok(data_storage::message::append_messages(
+        messages,
+        &hidden_msg_ids,
+        &schema_versions.unwrap_or_default(),
+    ))
+}
+
+/// Replace the payloads of archived messages re-encoded to a newer schema version
+///
+/// Only the indexer that created this archive may call this method.
+///
+/// # Arguments
+/// * `msg_type` - The type/category of the messages
+/// * `payloads` - The re-encoded payloads with their new schema version
+///
+/// # Returns
+/// * `Result<u64, String>` - Number of replaced payloads or error
+#[update]
+fn replace_payloads(msg_type: String, payloads: Vec<ArchivedPayload>) -> Result<u64, String> {
+    data_storage::state::with(|archive| archive.indexer_permission(ic_cdk::caller()))?;
+    Ok(data_storage::message::replace_payloads(&msg_type, payloads))
+}
+
+/// Delete archived messages deleted in the indexer
+///
+/// Only the indexer that created this archive may call this method.
+///
+/// # Arguments
+/// * `msg_type` - The type/category of the messages
+/// * `msg_ids` - Ids of the messages to delete; messages that are not archived are skipped
+///
+/// # Returns
+/// * `Result<u64, String>` - Number of deleted messages or error
+#[update]
+fn delete_msgs(msg_type: String, msg_ids: Vec<String>) -> Result<u64, String> {
+    data_storage::state::with(|archive| archive.indexer_permission(ic_cdk::caller()))?;
+    Ok(data_storage::message::delete_messages(&msg_type, &msg_ids))
+}
+
+/// Hide or unhide archived messages moderated in the indexer
+///
+/// Only the indexer that created this archive may call this method.
+///
+/// # Arguments
+/// * `msg_type` - The type/category of the messages
+/// * `msg_ids` - Ids of the messages; messages that are not archived are skipped
+/// * `hidden` - Whether the messages are hidden, and no longer served
+///
+/// # Returns
+/// * `Result<u64, String>` - Number of updated messages or error
+#[update]
+fn set_hidden(msg_type: String, msg_ids: Vec<String>, hidden: bool) -> Result<u64, String> {
+    data_storage::state::with(|archive| archive.indexer_permission(ic_cdk::caller()))?;
+    Ok(data_storage::message::set_hidden(&msg_type, &msg_ids, hidden))
+}
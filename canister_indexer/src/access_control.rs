@@ -0,0 +1,45 @@
+use candid::Principal;
+
+use crate::data_storage;
+
+/// Guard allowing only the configured controller or a canister controller
+#[inline(always)]
+pub fn controller_guard() -> Result<(), String> {
+    data_storage::state::with(|processor| processor.controller_permission(ic_cdk::caller()))
+}
+
+/// Guard allowing only moderators, admins and controllers
+#[inline(always)]
+pub fn moderator_guard() -> Result<(), String> {
+    let caller = ic_cdk::caller();
+    data_storage::state::with(|processor| {
+        if processor.is_moderator(&caller) {
+            Ok(())
+        } else {
+            processor.controller_permission(caller)
+        }
+    })
+}
+
+/// Guard allowing only allowed subscriber canisters, admins and controllers
+#[inline(always)]
+pub fn subscriber_guard() -> Result<(), String> {
+    let caller = ic_cdk::caller();
+    data_storage::state::with(|processor| {
+        if processor.is_subscriber(&caller) {
+            Ok(())
+        } else {
+            processor.controller_permission(caller)
+        }
+    })
+}
+
+/// Guard rejecting the anonymous principal
+#[inline(always)]
+pub fn anonymous_guard() -> Result<(), String> {
+    if ic_cdk::caller() == Principal::anonymous() {
+        Err(String::from("Error: Anonymous principal is not allowed"))
+    } else {
+        Ok(())
+    }
+}
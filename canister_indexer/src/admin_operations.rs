@@ -0,0 +1,502 @@
+use candid::Principal;
+use ic_cdk::{query, update};
+use serde_bytes::ByteBuf;
+use std::time::Duration;
+
+use crate::{
+    access_control::{controller_guard, moderator_guard},
+    data_storage::{
+        self, archive_registry, moderation, subscriptions, transfer, PublisherRights,
+        Subscription,
+    },
+    data_transfer::{ExportChunk, ExportCursor, ImportProgress},
+    jobs::{Job, JobState},
+    moderation::{ModerationAction, ModerationQueuePage, ModerationRecord, MAX_QUEUE_PAGE_SIZE},
+    namespace::{NamespaceConfig, NamespaceId, NAMESPACE_SEPARATOR},
+    payload_registry::PayloadTypeConfig,
+    payload_schema::{self, INITIAL_SCHEMA_VERSION},
+    rate_limit::{RateLimitConfig, RateLimitUsage},
+    subscription_manager,
+};
+
+/// Register a publisher or replace the rights of an existing one
+///
+/// Only registered publishers may submit messages through `process_single_msg`
+/// and `process_multiple_msgs`, and only for the payload and message types
+/// listed in their rights.
+///
+/// # Arguments
+/// * `publisher` - The principal allowed to publish messages
+/// * `rights` - Payload types and message types the publisher may submit
+///
+/// # Errors
+/// * Returns error if the publisher is the anonymous principal
+#[update(guard = "controller_guard")]
+fn register_publisher(publisher: Principal, rights: PublisherRights) -> Result<(), String> {
+    if publisher == Principal::anonymous() {
+        return Err("Anonymous principal cannot be registered as a publisher".to_string());
+    }
+
+    data_storage::state::with_mut(|processor| {
+        processor.publishers.insert(publisher, rights);
+    });
+    Ok(())
+}
+
+/// Remove a publisher from the registry
+///
+/// # Arguments
+/// * `publisher` - The principal to remove
+///
+/// # Returns
+/// * `bool` - Whether the publisher was registered
+#[update(guard = "controller_guard")]
+fn unregister_publisher(publisher: Principal) -> bool {
+    data_storage::state::with_mut(|processor| processor.publishers.remove(&publisher).is_some())
+}
+
+/// List all registered publishers with their rights
+#[query(guard = "controller_guard")]
+fn list_publishers() -> Vec<(Principal, PublisherRights)> {
+    data_storage::state::with(|processor| {
+        processor
+            .publishers
+            .iter()
+            .map(|(publisher, rights)| (*publisher, rights.clone()))
+            .collect()
+    })
+}
+
+/// Grant admin rights, allowing the principal to update or delete any message
+///
+/// # Arguments
+/// * `admin` - The principal to grant admin rights to
+#[update(guard = "controller_guard")]
+fn add_admin(admin: Principal) -> Result<(), String> {
+    if admin == Principal::anonymous() {
+        return Err("Anonymous principal cannot be an admin".to_string());
+    }
+
+    data_storage::state::with_mut(|processor| {
+        processor.admins.insert(admin);
+    });
+    Ok(())
+}
+
+/// Revoke admin rights from a principal
+///
+/// # Returns
+/// * `bool` - Whether the principal was an admin
+#[update(guard = "controller_guard")]
+fn remove_admin(admin: Principal) -> bool {
+    data_storage::state::with_mut(|processor| processor.admins.remove(&admin))
+}
+
+/// List all admin principals
+#[query(guard = "controller_guard")]
+fn list_admins() -> Vec<Principal> {
+    data_storage::state::with(|processor| processor.admins.iter().cloned().collect())
+}
+
+/// Grant moderator rights, allowing the principal to review reports and moderate messages
+///
+/// # Arguments
+/// * `moderator` - The principal to grant moderator rights to
+#[update(guard = "controller_guard")]
+fn add_moderator(moderator: Principal) -> Result<(), String> {
+    if moderator == Principal::anonymous() {
+        return Err("Anonymous principal cannot be a moderator".to_string());
+    }
+
+    data_storage::state::with_mut(|processor| {
+        processor.moderators.insert(moderator);
+    });
+    Ok(())
+}
+
+/// Revoke moderator rights from a principal
+///
+/// # Returns
+/// * `bool` - Whether the principal was a moderator
+#[update(guard = "controller_guard")]
+fn remove_moderator(moderator: Principal) -> bool {
+    data_storage::state::with_mut(|processor| processor.moderators.remove(&moderator))
+}
+
+/// List all moderator principals
+#[query(guard = "controller_guard")]
+fn list_moderators() -> Vec<Principal> {
+    data_storage::state::with(|processor| processor.moderators.iter().cloned().collect())
+}
+
+/// Set the wasm module installed into archive canisters created by the indexer
+///
+/// The module is the build output of `canister_archive`. Archive canisters
+/// are only created once a wasm module has been set.
+///
+/// # Arguments
+/// * `wasm_module` - The archive canister wasm module
+#[update(guard = "controller_guard")]
+fn set_archive_wasm(wasm_module: ByteBuf) -> Result<(), String> {
+    if wasm_module.is_empty() {
+        return Err("Archive wasm module cannot be empty".to_string());
+    }
+
+    archive_registry::set_wasm(wasm_module.into_vec());
+    Ok(())
+}
+
+/// Set the user canister the trusted keys of message authors are fetched from
+///
+/// Signed messages are rejected until a user canister is set.
+#[update(guard = "controller_guard")]
+fn set_user_canister(user_canister: Principal) {
+    data_storage::state::with_mut(|processor| processor.user_canister = Some(user_canister));
+}
+
+/// Drop the cached trusted keys of a user, so that they are fetched again
+///
+/// # Returns
+/// * `bool` - Whether keys of the user were cached
+#[update(guard = "controller_guard")]
+fn forget_trusted_keys(user: Principal) -> bool {
+    data_storage::signatures::forget_keys(&user)
+}
+
+/// Register a payload type or replace the configuration of an existing one
+///
+/// New message categories can be enabled at runtime without redeploying the
+/// indexer. Messages of unregistered payload types are rejected. Making a
+/// type `searchable` only indexes the messages stored from then on. Raising
+/// the schema version re-encodes the stored messages in the background.
+///
+/// # Arguments
+/// * `config` - Name, size limit, allowed message types, retention policy,
+///   decoding requirement and schema version of the payload type
+///
+/// # Errors
+/// * Returns error if the name is empty or namespaced, the size limit is zero
+///   or the retention rules are inconsistent
+/// * Returns error if the schema version is lowered, or raised without a
+///   registered migration for every version in between
+#[update(guard = "controller_guard")]
+fn register_payload_type(config: PayloadTypeConfig) -> Result<(), String> {
+    if config.name.trim().is_empty() {
+        return Err("Payload type name cannot be empty".to_string());
+    }
+    if config.name.contains(NAMESPACE_SEPARATOR) {
+        return Err(format!(
+            "Payload type name cannot contain '{}', register a namespace instead",
+            NAMESPACE_SEPARATOR
+        ));
+    }
+    if config.max_payload_size == 0 {
+        return Err("Maximum payload size must be greater than zero".to_string());
+    }
+    config.retention.validate()?;
+    if config.schema_version < INITIAL_SCHEMA_VERSION {
+        return Err(format!("Schema version must be at least {}", INITIAL_SCHEMA_VERSION));
+    }
+
+    let raised = data_storage::state::with_mut(|processor| {
+        let stored_version = processor
+            .payload_types
+            .get(&config.name)
+            .map(|stored| stored.schema_version);
+        if let Some(stored_version) = stored_version {
+            if config.schema_version < stored_version {
+                return Err(format!(
+                    "Schema version of {} cannot be lowered from {}",
+                    config.name, stored_version
+                ));
+            }
+            payload_schema::check_migration_path(
+                &config.name,
+                stored_version,
+                config.schema_version,
+            )?;
+        }
+        let raised = stored_version.is_some_and(|version| config.schema_version > version);
+        processor.payload_types.insert(config.name.clone(), config);
+        Ok(raised)
+    })?;
+    if raised {
+        data_storage::schema::schedule_if_outdated();
+    }
+    Ok(())
+}
+
+/// Remove a payload type from the registry
+///
+/// Already stored messages of the type are kept and stay queryable, but no
+/// new messages of the type are accepted.
+///
+/// # Returns
+/// * `bool` - Whether the payload type was registered
+#[update(guard = "controller_guard")]
+fn remove_payload_type(name: String) -> bool {
+    data_storage::state::with_mut(|processor| processor.payload_types.remove(&name).is_some())
+}
+
+/// Register a namespace or replace the settings of an existing one
+///
+/// Messages whose payload type is qualified with the namespace key, e.g.
+/// `game:42/MsgUserPost`, are accepted once the namespace is registered and
+/// stored apart from the messages of every other namespace. Publishers need
+/// rights for the qualified payload types.
+///
+/// # Arguments
+/// * `config` - Game id or tenant name, retention overrides and rate limits
+///   of the namespace
+///
+/// # Returns
+/// * `String` - The key qualifying the payload types of the namespace
+///
+/// # Errors
+/// * Returns error if the tenant name, a retention policy or a limit is
+///   invalid, or if an override names an unregistered payload type
+#[update(guard = "controller_guard")]
+fn register_namespace(config: NamespaceConfig) -> Result<String, String> {
+    config.validate()?;
+    data_storage::state::with_mut(|processor| {
+        let unknown = config.retention.keys().chain(
+            config
+                .rate_limits
+                .iter()
+                .flat_map(|rate_limits| rate_limits.per_payload_type.keys()),
+        );
+        for payload_type in unknown {
+            if !processor.payload_types.contains_key(payload_type) {
+                return Err(format!("Unknown payload type: {}", payload_type));
+            }
+        }
+        let key = config.id.key();
+        processor.namespaces.insert(key.clone(), config);
+        Ok(key)
+    })
+}
+
+/// Remove a namespace
+///
+/// Already stored messages of the namespace are kept and stay queryable, but
+/// no new messages are accepted and retention is no longer enforced on them.
+///
+/// # Returns
+/// * `bool` - Whether the namespace was registered
+#[update(guard = "controller_guard")]
+fn remove_namespace(id: NamespaceId) -> bool {
+    data_storage::state::with_mut(|processor| processor.namespaces.remove(&id.key()).is_some())
+}
+
+/// Set the rate limits and daily quotas applied to publishers, and the
+/// limit on the interactions of every caller
+///
+/// Admins and controllers are not limited. Usage already charged is kept
+/// when the limits change.
+///
+/// These limits apply to the global messages and to the messages of
+/// namespaces registered without limits of their own.
+///
+/// # Errors
+/// * Returns error if a limit is invalid or names an unregistered payload type
+#[update(guard = "controller_guard")]
+fn set_rate_limits(config: RateLimitConfig) -> Result<(), String> {
+    data_storage::state::with_mut(|processor| processor.set_rate_limits(config))
+}
+
+/// Get the rate limits and daily quotas applied to publishers and callers
+#[query(guard = "controller_guard")]
+fn get_rate_limits() -> RateLimitConfig {
+    data_storage::state::with(|processor| processor.rate_limits.clone())
+}
+
+/// Get the rate limit usage of a publisher
+///
+/// # Arguments
+/// * `publisher` - The publisher to report on
+/// * `namespace` - Key of a namespace with its own limits, or None for the global limits
+#[query(guard = "controller_guard")]
+fn get_publisher_usage(publisher: Principal, namespace: Option<String>) -> RateLimitUsage {
+    data_storage::rate_limits::usage(publisher, namespace.as_deref())
+}
+
+/// Export messages of both tiers, archive canisters and the processor state in chunks
+///
+/// Pass `next_cursor` of each chunk back until it is None; the chunks are
+/// imported in the same order with `import_data_chunk`. Stop the writes to
+/// this canister first for a consistent export.
+///
+/// # Arguments
+/// * `cursor` - `next_cursor` of the previous chunk, or None to start a new export
+///
+/// # Errors
+/// * Returns error if the cursor is invalid
+#[query(guard = "controller_guard")]
+fn export_data_chunk(cursor: Option<String>) -> Result<ExportChunk, String> {
+    let cursor = match cursor {
+        Some(token) => ExportCursor::decode(&token)?,
+        None => ExportCursor::start(ic_cdk::api::time()),
+    };
+    Ok(transfer::export_chunk(&cursor))
+}
+
+/// Import the next chunk of an export produced by `export_data_chunk`
+///
+/// The first chunk is only accepted by an indexer without messages and
+/// archive canisters. Resending an imported chunk is a no-op, so an
+/// interrupted import resumes at `next_sequence` of the returned progress.
+/// Message writes are rejected until the last chunk is imported.
+///
+/// # Arguments
+/// * `chunk` - The next chunk of the export
+/// * `replace_access_control` - Whether the controller, admins, publishers,
+///   moderators and subscriber canisters of the export replace the ones of
+///   this canister (defaults to false); only read with the first chunk
+///
+/// # Errors
+/// * Returns error if the checksum does not match, the format version is not
+///   supported or the chunk is out of sequence
+#[update(guard = "controller_guard")]
+fn import_data_chunk(
+    chunk: ExportChunk,
+    replace_access_control: Option<bool>,
+) -> Result<ImportProgress, String> {
+    transfer::import_chunk(&chunk, replace_access_control.unwrap_or(false))
+}
+
+/// Get the progress of the import into this canister, if one was started
+#[query(guard = "controller_guard")]
+fn get_import_progress() -> Option<ImportProgress> {
+    data_storage::state::with(|processor| processor.import.clone())
+}
+
+/// List the background jobs with their next run and the outcome of their last run
+#[query(guard = "controller_guard")]
+fn list_jobs() -> Vec<JobState> {
+    data_storage::scheduler::list()
+}
+
+/// Run a background job now
+///
+/// A job that is already running is run again once the current run finishes.
+#[update(guard = "controller_guard")]
+fn run_job(job: Job) {
+    data_storage::scheduler::schedule(job, Duration::ZERO);
+}
+
+/// Allow a canister to subscribe to changes of the stored messages
+///
+/// # Arguments
+/// * `subscriber` - The canister to allow
+#[update(guard = "controller_guard")]
+fn allow_subscriber(subscriber: Principal) -> Result<(), String> {
+    if subscriber == Principal::anonymous() {
+        return Err("Anonymous principal cannot be a subscriber".to_string());
+    }
+
+    data_storage::state::with_mut(|processor| {
+        processor.subscriber_canisters.insert(subscriber);
+    });
+    Ok(())
+}
+
+/// Disallow a subscriber canister and remove its subscription
+///
+/// # Returns
+/// * `bool` - Whether the canister was allowed to subscribe
+#[update(guard = "controller_guard")]
+fn revoke_subscriber(subscriber: Principal) -> bool {
+    let allowed =
+        data_storage::state::with_mut(|processor| processor.subscriber_canisters.remove(&subscriber));
+    subscriptions::unsubscribe(&subscriber);
+    allowed
+}
+
+/// List all canisters allowed to subscribe
+#[query(guard = "controller_guard")]
+fn list_subscriber_canisters() -> Vec<Principal> {
+    data_storage::state::with(|processor| processor.subscriber_canisters.iter().cloned().collect())
+}
+
+/// List all subscriptions with their delivery progress
+#[query(guard = "controller_guard")]
+fn list_subscriptions() -> Vec<Subscription> {
+    subscriptions::list()
+}
+
+/// List the subscriptions whose delivery was stopped after repeated failures
+#[query(guard = "controller_guard")]
+fn list_dead_letters() -> Vec<Subscription> {
+    subscriptions::dead_letters()
+}
+
+/// Resume delivery to a dead-lettered subscriber
+///
+/// # Returns
+/// * `bool` - Whether the subscriber was registered
+#[update(guard = "controller_guard")]
+fn reactivate_subscription(subscriber: Principal) -> bool {
+    let reactivated = subscriptions::reactivate(&subscriber);
+    if reactivated {
+        subscription_manager::schedule_delivery();
+    }
+    reactivated
+}
+
+/// Remove the subscription of a subscriber
+///
+/// # Returns
+/// * `bool` - Whether the subscriber was registered
+#[update(guard = "controller_guard")]
+fn remove_subscription(subscriber: Principal) -> bool {
+    subscriptions::unsubscribe(&subscriber)
+}
+
+/// Fetch reported messages awaiting review, oldest report first
+///
+/// Hidden messages are included, with their open reports.
+///
+/// # Arguments
+/// * `max_count` - Maximum number of messages to return (capped at `MAX_QUEUE_PAGE_SIZE`)
+/// * `cursor` - `next_cursor` of the previous page, or None for the first page
+#[query(guard = "moderator_guard")]
+fn fetch_moderation_queue(
+    max_count: usize,
+    cursor: Option<String>,
+) -> Result<ModerationQueuePage, String> {
+    let offset = match cursor {
+        Some(cursor) => cursor.parse().map_err(|_| format!("Invalid cursor: {}", cursor))?,
+        None => 0,
+    };
+    Ok(moderation::queue_page(offset, max_count.min(MAX_QUEUE_PAGE_SIZE)))
+}
+
+/// Get the moderation record of a message: report counts and actions taken
+#[query(guard = "moderator_guard")]
+fn get_moderation_record(msg_type: String, msg_id: String) -> Option<ModerationRecord> {
+    moderation::record(&msg_type, &msg_id)
+}
+
+/// Hide, unhide or delete a message, recording the reason
+///
+/// Hidden messages are excluded from every list query. Deleting a message
+/// also deletes its comments, as a Delete sent by its creator would. Any
+/// action closes the open reports of the message.
+///
+/// # Arguments
+/// * `msg_type` - The type/category of the message
+/// * `msg_id` - The ID of the message
+/// * `action` - The action to take
+/// * `reason` - Why the action is taken
+///
+/// # Errors
+/// * Returns error if the message does not exist or the reason is empty or too long
+#[update(guard = "moderator_guard")]
+fn moderate_msg(
+    msg_type: String,
+    msg_id: String,
+    action: ModerationAction,
+    reason: String,
+) -> Result<(), String> {
+    moderation::moderate(&msg_type, &msg_id, action, ic_cdk::caller(), reason)
+}
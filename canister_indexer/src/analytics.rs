@@ -0,0 +1,148 @@
+use candid::{CandidType, Principal};
+use canister_types::message::Message;
+use serde::{Deserialize, Serialize};
+
+/// Maximum number of buckets a trending or activity query may cover
+pub const MAX_WINDOW_BUCKETS: u64 = 7 * 24;
+/// Maximum number of ranked subjects returned by a trending query
+pub const MAX_TRENDING_COUNT: usize = 100;
+
+const NANOS_PER_HOUR: u64 = 60 * 60 * 1_000_000_000;
+const NANOS_PER_DAY: u64 = 24 * NANOS_PER_HOUR;
+
+/// Size of the time buckets counters are kept in
+#[derive(CandidType, Clone, Copy, Deserialize, Serialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CounterGranularity {
+    /// Kept for 7 days
+    Hour,
+    /// Kept for 90 days
+    Day,
+}
+
+impl CounterGranularity {
+    pub const ALL: [CounterGranularity; 2] = [CounterGranularity::Hour, CounterGranularity::Day];
+
+    /// Length of a bucket, in nanoseconds
+    pub fn bucket_nanos(self) -> u64 {
+        match self {
+            CounterGranularity::Hour => NANOS_PER_HOUR,
+            CounterGranularity::Day => NANOS_PER_DAY,
+        }
+    }
+
+    /// How long buckets are kept, in nanoseconds
+    pub fn retention_nanos(self) -> u64 {
+        match self {
+            CounterGranularity::Hour => 7 * NANOS_PER_DAY,
+            CounterGranularity::Day => 90 * NANOS_PER_DAY,
+        }
+    }
+
+    /// Start of the bucket containing a timestamp
+    pub fn bucket_start(self, timestamp: u64) -> u64 {
+        timestamp - timestamp % self.bucket_nanos()
+    }
+}
+
+/// A resource messages refer to, see `MessageSource`
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ResourceRef {
+    pub canister_id: Principal,
+    pub resource_type: String,
+    pub resource_id: u64,
+}
+
+/// What a counter counts the messages of
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CounterSubject {
+    /// All messages of the payload type
+    Total,
+    /// Messages created by a principal
+    Principal(Principal),
+    /// Messages about a resource
+    Resource(ResourceRef),
+}
+
+/// Kind of subject ranked by a trending query
+#[derive(CandidType, Clone, Copy, Deserialize, Serialize, Debug, PartialEq, Eq)]
+pub enum CounterDimension {
+    Principal,
+    Resource,
+}
+
+impl CounterSubject {
+    /// Subjects a message is counted for
+    pub fn of(message: &Message, principal: Principal) -> Vec<Self> {
+        let mut subjects = vec![CounterSubject::Total, CounterSubject::Principal(principal)];
+        if let Some(resource) = &message.msg_resource {
+            subjects.push(CounterSubject::Resource(ResourceRef {
+                canister_id: resource.canister_id,
+                resource_type: resource.resource_type.clone(),
+                resource_id: resource.resource_id,
+            }));
+        }
+        subjects
+    }
+
+    /// Whether this subject is ranked by trending queries of a dimension
+    pub fn is_in(&self, dimension: CounterDimension) -> bool {
+        matches!(
+            (self, dimension),
+            (CounterSubject::Principal(_), CounterDimension::Principal)
+                | (CounterSubject::Resource(_), CounterDimension::Resource)
+        )
+    }
+}
+
+/// Time window of a counter query, `[from_timestamp, to_timestamp)`
+///
+/// Buckets overlapping the window are counted in full.
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug)]
+pub struct CounterWindow {
+    pub granularity: CounterGranularity,
+    pub from_timestamp: u64,
+    pub to_timestamp: u64,
+}
+
+impl CounterWindow {
+    /// Starts of the first and the last bucket overlapping the window
+    pub fn bucket_range(&self) -> Result<(u64, u64), String> {
+        if self.from_timestamp >= self.to_timestamp {
+            return Err("from_timestamp must be lower than to_timestamp".to_string());
+        }
+
+        let first = self.granularity.bucket_start(self.from_timestamp);
+        let last = self.granularity.bucket_start(self.to_timestamp - 1);
+        if (last - first) / self.granularity.bucket_nanos() >= MAX_WINDOW_BUCKETS {
+            return Err(format!(
+                "A window may cover at most {} buckets",
+                MAX_WINDOW_BUCKETS
+            ));
+        }
+        Ok((first, last))
+    }
+}
+
+/// Top-N ranking of principals or resources by message count
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug)]
+pub struct TrendingQuery {
+    pub payload_type: String,
+    pub dimension: CounterDimension,
+    pub window: CounterWindow,
+    /// Number of subjects to return (capped at `MAX_TRENDING_COUNT`)
+    pub top_n: usize,
+}
+
+/// A ranked subject with its message count over the window
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug)]
+pub struct RankedSubject {
+    pub subject: CounterSubject,
+    pub count: u64,
+}
+
+/// Message count of one bucket
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug)]
+pub struct ActivityBucket {
+    pub bucket_start: u64,
+    pub count: u64,
+}
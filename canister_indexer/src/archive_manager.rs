@@ -0,0 +1,410 @@
+use candid::{CandidType, Encode, Principal};
+use canister_types::message::Message;
+use ic_cdk::{
+    call::Call,
+    management_canister::{
+        create_canister_with_extra_cycles, install_code, CanisterInstallMode, CanisterSettings,
+        CreateCanisterArgs, InstallCodeArgs,
+    },
+};
+use ic_stable_structures::Storable;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+use crate::{
+    data_storage::{
+        self, archive_registry, moderation, schema, spill, store, ArchiveOp, ArchiveOpKey,
+        MsgEntry, StoreTier,
+    },
+    msg_key::MsgKey,
+    namespace,
+    payload_schema::{self, INITIAL_SCHEMA_VERSION},
+    ARCHIVE_CANISTER_CAPACITY, ARCHIVE_MSG_DEFAULT_CYCLES, ARCHIVE_MSG_MIGRATION_SIZE,
+    ARCHIVE_SPILL_THRESHOLD,
+};
+
+/// Init arguments of the archive canister, see `canister_archive`
+#[derive(CandidType, Clone, Deserialize, Debug)]
+struct ArchiveInitArgs {
+    name: String,
+    indexer: Principal,
+}
+
+/// Payload of an archived message, see `canister_archive`
+#[derive(CandidType, Clone, Deserialize, Debug)]
+struct ArchivedPayload {
+    msg_id: String,
+    schema_version: u32,
+    payload: Vec<u8>,
+}
+
+/// Payloads of an archive canister on an older schema version, see `canister_archive`
+#[derive(CandidType, Clone, Deserialize, Debug)]
+struct ArchivedPayloadPage {
+    payloads: Vec<ArchivedPayload>,
+    next_cursor: Option<String>,
+}
+
+/// Move the oldest locally archived messages into child archive canisters
+///
+/// Runs from the cleanup scheduler. For every payload type whose local
+/// archive exceeds `ARCHIVE_SPILL_THRESHOLD`, the oldest
+/// `ARCHIVE_MSG_MIGRATION_SIZE` messages are appended to the current archive
+/// canister and only removed locally once the archive acknowledged them,
+/// unless they changed meanwhile; the archive copies of changed messages
+/// are deleted again. Spilled messages are recorded in `data_storage::spill`
+/// with the archive canister holding them. A new archive canister is
+/// created and funded whenever the current one is full. Hidden messages are handed over with their hidden flag, so the
+/// archive keeps them but does not serve them.
+///
+/// # Returns
+/// The number of messages moved, or the error that stopped the spill
+pub async fn spill_local_archive() -> Result<u64, String> {
+    let payload_types: Vec<String> = store::all_type_stats()
+        .into_iter()
+        .filter(|(_, stats)| stats.archived > ARCHIVE_SPILL_THRESHOLD)
+        .map(|(payload_type, _)| payload_type)
+        .collect();
+
+    let mut spilled = 0;
+    for payload_type in payload_types {
+        spilled += spill_payload_type(&payload_type)
+            .await
+            .map_err(|e| format!("failed to spill key {}: {}", payload_type, e))?;
+    }
+    Ok(spilled)
+}
+
+/// Hand the oldest archived messages of one payload type over to an archive canister
+///
+/// # Returns
+/// The number of messages handed over
+async fn spill_payload_type(payload_type: &str) -> Result<u64, String> {
+    let entries = store::oldest_entries(StoreTier::Archive, payload_type, ARCHIVE_MSG_MIGRATION_SIZE);
+    let (Some(first), Some(last)) = (entries.first(), entries.last()) else {
+        return Ok(0);
+    };
+    let (from_timestamp, to_timestamp) = (first.message.timestamp, last.message.timestamp);
+    let count = entries.len() as u64;
+
+    let canister_id = match archive_registry::current_with_room(ARCHIVE_CANISTER_CAPACITY, count) {
+        Some(canister_id) => canister_id,
+        None => provision_archive_canister().await?,
+    };
+
+    let schema_versions: Vec<u32> = entries.iter().map(|entry| entry.schema_version).collect();
+    let oldest_version = schema_versions.iter().copied().min().unwrap_or(INITIAL_SCHEMA_VERSION);
+    let hidden_msg_ids: Vec<String> = entries
+        .iter()
+        .map(|entry| &entry.message.msg_id)
+        .filter(|msg_id| moderation::is_hidden(payload_type, msg_id))
+        .cloned()
+        .collect();
+    // Entries may be rewritten, moved or hidden while the archive is called
+    let handed_over: Vec<(MsgKey, [u8; 32])> = entries
+        .iter()
+        .map(|entry| (MsgKey::of(&entry.message), content_hash(entry)))
+        .collect();
+    let batch: Vec<(Message, Principal)> = entries.into_iter().map(MsgEntry::into_pair).collect();
+
+    let result: Result<u64, String> = Call::unbounded_wait(canister_id, "append_msgs")
+        .with_args(&(batch, Some(hidden_msg_ids.clone()), Some(schema_versions)))
+        .await
+        .map_err(|err| format!("append_msgs failed: {}", err))?
+        .candid()
+        .map_err(|err| format!("append_msgs failed: {}", err))?;
+    result?;
+
+    // The archive acknowledged the batch; drop the local copies that are
+    // still archived unchanged, the archive copies of the others are stale
+    for (key, hash) in &handed_over {
+        let unchanged = match store::get_entry(payload_type, &key.msg_id) {
+            Some((entry, StoreTier::Archive)) => {
+                MsgKey::of(&entry.message) == *key && content_hash(&entry) == *hash
+            }
+            _ => false,
+        };
+        if !unchanged {
+            spill::discard(canister_id, payload_type, &key.msg_id);
+            continue;
+        }
+
+        if let Some((entry, _)) = store::remove_entry(payload_type, &key.msg_id) {
+            spill::record(canister_id, &entry);
+        }
+        let hidden = moderation::is_hidden(payload_type, &key.msg_id);
+        if hidden != hidden_msg_ids.contains(&key.msg_id) {
+            spill::set_hidden(payload_type, &key.msg_id, hidden);
+        }
+    }
+    archive_registry::record(
+        canister_id,
+        payload_type,
+        from_timestamp,
+        to_timestamp,
+        count,
+        oldest_version,
+    );
+
+    ic_cdk::println!(
+        "spill_local_archive: moved {} messages of key {} to archive canister {}",
+        count, payload_type, canister_id
+    );
+    Ok(count)
+}
+
+/// Digest of a stored entry, telling whether it changed
+fn content_hash(entry: &MsgEntry) -> [u8; 32] {
+    Sha256::digest(entry.to_bytes()).into()
+}
+
+/// Send the pending changes of spilled messages to their archive canisters
+///
+/// Runs from the cleanup scheduler before the spill, for at most
+/// `ARCHIVE_MSG_MIGRATION_SIZE` changes. A change is only dropped once its
+/// archive acknowledged it, so failed changes are sent again by a later run.
+///
+/// # Returns
+/// The number of changes sent, or the first error
+pub async fn flush_archive_ops() -> Result<u64, String> {
+    let mut batches: BTreeMap<(Principal, String, ArchiveOp), Vec<String>> = BTreeMap::new();
+    for (key, op) in spill::pending_ops(ARCHIVE_MSG_MIGRATION_SIZE) {
+        batches
+            .entry((key.canister_id, key.payload_type, op))
+            .or_default()
+            .push(key.msg_id);
+    }
+
+    let mut sent = 0;
+    let mut first_error = None;
+    for ((canister_id, payload_type, op), msg_ids) in batches {
+        let result = match op {
+            ArchiveOp::Delete => send_deletion(canister_id, &payload_type, &msg_ids).await,
+            ArchiveOp::SetHidden(hidden) => {
+                send_hidden(canister_id, &payload_type, &msg_ids, hidden).await
+            }
+        };
+        if let Err(err) = result {
+            first_error.get_or_insert(format!("archive {}: {}", canister_id, err));
+            continue;
+        }
+
+        sent += msg_ids.len() as u64;
+        for msg_id in msg_ids {
+            let key = ArchiveOpKey { canister_id, payload_type: payload_type.clone(), msg_id };
+            spill::complete_op(&key, op);
+        }
+    }
+    match first_error {
+        Some(err) => Err(err),
+        None => Ok(sent),
+    }
+}
+
+async fn send_deletion(
+    canister_id: Principal,
+    payload_type: &str,
+    msg_ids: &[String],
+) -> Result<u64, String> {
+    let result: Result<u64, String> = Call::unbounded_wait(canister_id, "delete_msgs")
+        .with_args(&(payload_type, msg_ids))
+        .await
+        .map_err(|err| format!("delete_msgs failed: {}", err))?
+        .candid()
+        .map_err(|err| format!("delete_msgs failed: {}", err))?;
+    result
+}
+
+async fn send_hidden(
+    canister_id: Principal,
+    payload_type: &str,
+    msg_ids: &[String],
+    hidden: bool,
+) -> Result<u64, String> {
+    let result: Result<u64, String> = Call::unbounded_wait(canister_id, "set_hidden")
+        .with_args(&(payload_type, msg_ids, hidden))
+        .await
+        .map_err(|err| format!("set_hidden failed: {}", err))?
+        .candid()
+        .map_err(|err| format!("set_hidden failed: {}", err))?;
+    result
+}
+
+/// Re-encode a batch of the messages spilled to archive canisters that are
+/// on an older schema version of their payload type
+///
+/// Runs from the schema migration job once the stored messages are
+/// re-encoded. Every outdated payload type of every archive canister is
+/// scanned from where its previous batch stopped; the payloads are
+/// re-encoded here and written back to the archive.
+///
+/// # Returns
+/// The number of re-encoded messages and the errors of the failed ones
+pub async fn migrate_archived_payloads() -> (usize, Vec<String>) {
+    let mut migrated = 0;
+    let mut errors = Vec::new();
+
+    for (canister_id, payload_type, range, current_version) in schema::outdated_archive_ranges() {
+        let result = migrate_archived_batch(
+            canister_id,
+            &payload_type,
+            range.schema_cursor,
+            current_version,
+        )
+        .await;
+        match result {
+            Ok((count, batch_errors)) => {
+                migrated += count;
+                errors.extend(batch_errors);
+            }
+            Err(err) => errors.push(format!("{} in archive {}: {}", payload_type, canister_id, err)),
+        }
+    }
+    (migrated, errors)
+}
+
+/// Re-encode one page of outdated payloads of an archive canister
+///
+/// The scan only moves on once the page was written back.
+async fn migrate_archived_batch(
+    canister_id: Principal,
+    payload_type: &str,
+    cursor: Option<String>,
+    current_version: u32,
+) -> Result<(usize, Vec<String>), String> {
+    let page: Result<ArchivedPayloadPage, String> =
+        Call::unbounded_wait(canister_id, "fetch_outdated_payloads")
+            .with_args(&(payload_type, current_version, ARCHIVE_MSG_MIGRATION_SIZE, cursor))
+            .await
+            .map_err(|err| format!("fetch_outdated_payloads failed: {}", err))?
+            .candid()
+            .map_err(|err| format!("fetch_outdated_payloads failed: {}", err))?;
+    let page = page?;
+
+    let mut errors = Vec::new();
+    let payloads: Vec<ArchivedPayload> = page
+        .payloads
+        .into_iter()
+        .filter_map(|archived| {
+            let migrated = payload_schema::migrate_payload(
+                namespace::base_type(payload_type),
+                archived.schema_version,
+                current_version,
+                &archived.payload,
+            );
+            match migrated {
+                Ok(payload) => Some(ArchivedPayload {
+                    msg_id: archived.msg_id,
+                    schema_version: current_version,
+                    payload,
+                }),
+                Err(err) => {
+                    errors.push(format!("{} {}: {}", payload_type, archived.msg_id, err));
+                    None
+                }
+            }
+        })
+        .collect();
+    let count = payloads.len();
+
+    if !payloads.is_empty() {
+        let result: Result<u64, String> = Call::unbounded_wait(canister_id, "replace_payloads")
+            .with_args(&(payload_type, payloads))
+            .await
+            .map_err(|err| format!("replace_payloads failed: {}", err))?
+            .candid()
+            .map_err(|err| format!("replace_payloads failed: {}", err))?;
+        result?;
+    }
+
+    // Payloads that failed to migrate keep the range outdated, so they are scanned again
+    let completed_version =
+        (page.next_cursor.is_none() && errors.is_empty()).then_some(current_version);
+    archive_registry::record_schema_progress(
+        canister_id,
+        payload_type,
+        page.next_cursor,
+        completed_version,
+    );
+    Ok((count, errors))
+}
+
+/// An installed archive canister with room for new messages
+///
+/// A previously created canister whose installation failed is installed
+/// again rather than creating and funding another one.
+async fn provision_archive_canister() -> Result<Principal, String> {
+    let (canister_id, mode) = match archive_registry::pending_install() {
+        // Reinstalling also covers an installation that succeeded but was not recorded
+        Some(canister_id) => (canister_id, CanisterInstallMode::Reinstall),
+        None => (create_archive_canister().await?, CanisterInstallMode::Install),
+    };
+    install_archive(canister_id, mode).await?;
+
+    archive_registry::mark_installed(canister_id);
+    ic_cdk::println!("provision_archive_canister: installed archive canister {}", canister_id);
+    Ok(canister_id)
+}
+
+/// Create and fund a new archive canister and register it right away
+async fn create_archive_canister() -> Result<Principal, String> {
+    if archive_registry::wasm().is_empty() {
+        return Err("Archive wasm module has not been set".to_string());
+    }
+
+    let balance = ic_cdk::api::canister_balance128();
+    if balance < ARCHIVE_MSG_DEFAULT_CYCLES * 2 {
+        return Err(format!(
+            "Insufficient cycles to create an archive canister: balance {}",
+            balance
+        ));
+    }
+
+    let mut controllers = vec![ic_cdk::id()];
+    // The anonymous default controller must not control the archive
+    let controller = data_storage::state::with(|processor| processor.controller);
+    if controller != Principal::anonymous() {
+        controllers.push(controller);
+    }
+    let settings = CanisterSettings {
+        controllers: Some(controllers),
+        ..Default::default()
+    };
+
+    let record = create_canister_with_extra_cycles(
+        &CreateCanisterArgs {
+            settings: Some(settings),
+        },
+        ARCHIVE_MSG_DEFAULT_CYCLES,
+    )
+    .await
+    .map_err(|err| format!("create_canister failed: {}", err))?;
+    let canister_id = record.canister_id;
+
+    archive_registry::register(canister_id);
+    ic_cdk::println!("create_archive_canister: created archive canister {}", canister_id);
+    Ok(canister_id)
+}
+
+/// Install the archive wasm module into an archive canister
+async fn install_archive(canister_id: Principal, mode: CanisterInstallMode) -> Result<(), String> {
+    let wasm_module = archive_registry::wasm();
+    if wasm_module.is_empty() {
+        return Err("Archive wasm module has not been set".to_string());
+    }
+
+    let identifier = data_storage::state::with(|processor| processor.identifier.clone());
+    let init_args = ArchiveInitArgs {
+        name: format!("{}_archive", identifier),
+        indexer: ic_cdk::id(),
+    };
+    install_code(&InstallCodeArgs {
+        mode,
+        canister_id,
+        wasm_module,
+        arg: Encode!(&init_args).map_err(|e| e.to_string())?,
+    })
+    .await
+    .map_err(|err| format!("install_code failed: {}", err))
+}
@@ -1,6 +1,31 @@
-use crate::cycles_handler::CycleTransferResult;
+use crate::{
+    analytics::{ActivityBucket, CounterWindow, RankedSubject, TrendingQuery},
+    certification::{CertifiedMsg, CertifiedMsgPage},
+    cycles_handler::CycleTransferResult,
+    data_storage::{
+        ArchiveCanisterInfo, FollowCounts, FollowPage, MsgReceipt, PublisherRights, Subscription,
+        SubscriptionFilter,
+    },
+    data_transfer::{ExportChunk, ImportProgress},
+    discussion::{ReactionSummary, ThreadNode},
+    http_gateway::{HttpRequest, HttpResponse},
+    indexer_error::IndexerError,
+    jobs::{Job, JobState},
+    moderation::{ModerationAction, ModerationQueuePage, ModerationRecord},
+    namespace::{NamespaceConfig, NamespaceId},
+    pagination::{MsgPage, TimelineQuery},
+    payload_registry::PayloadTypeConfig,
+    payload_schema::SchemaVersionStats,
+    rate_limit::{RateLimitConfig, RateLimitUsage},
+    signatures::{MsgSignature, MsgVerification},
+    text_search::{SearchPage, SearchQuery},
+};
+use serde_bytes::ByteBuf;
 use candid::{export_service, Principal};
-use canister_types::{indexer::CanisterArgs, message::Message};
+use canister_types::{
+    indexer::CanisterArgs,
+    message::{Message, MessageSource},
+};
 use ic_cdk::query;
 
 /// Generates the Candid interface for this canister
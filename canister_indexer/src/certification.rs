@@ -0,0 +1,298 @@
+use candid::{CandidType, Principal};
+use canister_types::message::Message;
+use ic_certification::{merge_hash_trees, pruned, AsHashTree, HashTree, NestedTree};
+use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf;
+use sha2::{Digest, Sha256};
+use std::cell::{Cell, RefCell};
+
+use crate::pagination::MsgPage;
+
+/// Label of the certified HTTP response hashes, see `http_gateway`
+pub const HTTP_ASSETS_LABEL: &[u8] = b"http_assets";
+/// Label of the certified message entries
+pub const MSGS_LABEL: &[u8] = b"msgs";
+/// Maximum number of messages of a certified page, bounding the witness size
+pub const MAX_CERTIFIED_PAGE_SIZE: usize = 100;
+
+/// Path of a node of the certified tree
+pub type CertifiedPath = Vec<Vec<u8>>;
+
+/// A message with the proof that it is stored by the indexer
+///
+/// The witness is the CBOR encoding of a hash tree pruned to the path
+/// `msgs/<payload_type>/<msg_id>`; the leaf holds `entry_hash` of the
+/// message. For a missing message, it proves that no such path exists.
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug)]
+pub struct CertifiedMsg {
+    pub message: Option<(Message, Principal)>,
+    /// Certificate of the canister's certified data, None outside query calls
+    /// and while the certified tree is rebuilt after an upgrade
+    pub certificate: Option<ByteBuf>,
+    pub witness: ByteBuf,
+}
+
+/// A page of messages with one witness covering every message of the page
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug)]
+pub struct CertifiedMsgPage {
+    pub page: MsgPage,
+    /// Certificate of the canister's certified data, None outside query calls
+    /// and while the certified tree is rebuilt after an upgrade
+    pub certificate: Option<ByteBuf>,
+    pub witness: ByteBuf,
+}
+
+thread_local! {
+    // Rebuilt from the message stores after an upgrade, see `data_storage::store`
+    static CERTIFIED_TREE: RefCell<NestedTree<Vec<u8>, Vec<u8>>> = RefCell::new(NestedTree::default());
+
+    // Whether the tree is missing stored messages, see `data_storage::migration::certify_batch`
+    static REBUILDING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Mark the certified tree as incomplete until `finish_rebuild`
+pub fn begin_rebuild() {
+    REBUILDING.set(true);
+}
+
+/// Mark the certified tree as complete and publish its root hash
+pub fn finish_rebuild() {
+    REBUILDING.set(false);
+    publish();
+}
+
+/// Checks if the certified tree is being rebuilt, and may miss stored messages
+pub fn rebuilding() -> bool {
+    REBUILDING.get()
+}
+
+/// Publish the root hash of the certified tree as the canister's certified data
+///
+/// Must only be called from update calls, timers and the init and upgrade hooks.
+pub fn publish() {
+    CERTIFIED_TREE.with_borrow(|tree| set_certified_data(&tree.root_hash()));
+}
+
+#[cfg(not(test))]
+fn set_certified_data(root_hash: &[u8]) {
+    ic_cdk::api::set_certified_data(root_hash);
+}
+
+/// Unit tests run outside a canister, where there is no certified data to set
+#[cfg(test)]
+fn set_certified_data(_root_hash: &[u8]) {}
+
+/// Drop the tree left by an earlier unit test on this thread
+#[cfg(test)]
+pub fn reset() {
+    CERTIFIED_TREE.with_borrow_mut(|tree| *tree = NestedTree::default());
+    REBUILDING.set(false);
+}
+
+/// Set a leaf of the certified tree and publish the new root hash
+pub fn insert(path: &[Vec<u8>], value: Vec<u8>) {
+    CERTIFIED_TREE.with_borrow_mut(|tree| tree.insert(path, value));
+    publish();
+}
+
+/// Remove a leaf or a whole subtree of the certified tree and publish the new root hash
+pub fn delete(path: &[Vec<u8>]) {
+    CERTIFIED_TREE.with_borrow_mut(|tree| tree.delete(path));
+    publish();
+}
+
+/// Path of a message entry in the certified tree
+pub fn msg_path(payload_type: &str, msg_id: &str) -> CertifiedPath {
+    vec![
+        MSGS_LABEL.to_vec(),
+        payload_type.as_bytes().to_vec(),
+        msg_id.as_bytes().to_vec(),
+    ]
+}
+
+/// Certify a stored message
+pub fn certify_message(message: &Message, principal: Principal) {
+    insert(
+        &msg_path(&message.payload_type, &message.msg_id),
+        entry_hash(message, principal),
+    );
+}
+
+/// Certify many stored messages, publishing the root hash once
+pub fn certify_messages(entries: impl Iterator<Item = (Message, Principal)>) {
+    CERTIFIED_TREE.with_borrow_mut(|tree| {
+        for (message, principal) in entries {
+            tree.insert(
+                &msg_path(&message.payload_type, &message.msg_id),
+                entry_hash(&message, principal),
+            );
+        }
+    });
+    publish();
+}
+
+/// Drop a removed message from the certified tree
+pub fn uncertify_message(payload_type: &str, msg_id: &str) {
+    delete(&msg_path(payload_type, msg_id));
+}
+
+/// Hash of a message entry as stored in the certified tree
+///
+/// SHA-256 over the following fields, each prefixed with its length as a
+/// 4-byte big-endian integer: payload type, message id, message type name,
+/// timestamp (8 bytes big-endian), caller, creator principal, resource
+/// canister id, resource type, resource id (8 bytes big-endian) and payload.
+/// The resource fields are empty for messages without a resource.
+pub fn entry_hash(message: &Message, principal: Principal) -> Vec<u8> {
+    let msg_type = format!("{:?}", message.msg_type);
+    let timestamp = message.timestamp.to_be_bytes();
+    let (canister_id, resource_type, resource_id) = match &message.msg_resource {
+        Some(resource) => (
+            resource.canister_id.as_slice().to_vec(),
+            resource.resource_type.as_bytes().to_vec(),
+            resource.resource_id.to_be_bytes().to_vec(),
+        ),
+        None => (Vec::new(), Vec::new(), Vec::new()),
+    };
+
+    let mut hasher = Sha256::new();
+    for field in [
+        message.payload_type.as_bytes(),
+        message.msg_id.as_bytes(),
+        msg_type.as_bytes(),
+        timestamp.as_slice(),
+        message.caller.as_slice(),
+        principal.as_slice(),
+        canister_id.as_slice(),
+        resource_type.as_slice(),
+        resource_id.as_slice(),
+        message.payload.as_slice(),
+    ] {
+        hasher.update((field.len() as u32).to_be_bytes());
+        hasher.update(field);
+    }
+    hasher.finalize().to_vec()
+}
+
+/// Witness covering the given paths of the certified tree
+pub fn witness(paths: &[CertifiedPath]) -> HashTree {
+    CERTIFIED_TREE.with_borrow(|tree| {
+        paths
+            .iter()
+            .map(|path| tree.witness(path))
+            .reduce(merge_hash_trees)
+            .unwrap_or_else(|| pruned(tree.root_hash()))
+    })
+}
+
+/// Self-describing CBOR encoding of a witness
+pub fn encode_witness(witness: &HashTree) -> Vec<u8> {
+    let mut serialized = vec![0xd9, 0xd9, 0xf7];
+    ciborium::into_writer(witness, &mut serialized).expect("failed to encode witness");
+    serialized
+}
+
+/// Certificate of the canister's certified data, only available in query calls
+///
+/// None while the tree is rebuilt, as a witness of a missing message would
+/// prove its absence.
+pub fn certificate() -> Option<ByteBuf> {
+    if rebuilding() {
+        return None;
+    }
+    ic_cdk::api::data_certificate().map(ByteBuf::from)
+}
+
+/// Attach a certificate and a witness to a message lookup
+pub fn certified_msg(
+    payload_type: &str,
+    msg_id: &str,
+    message: Option<(Message, Principal)>,
+) -> CertifiedMsg {
+    CertifiedMsg {
+        message,
+        certificate: certificate(),
+        witness: ByteBuf::from(encode_witness(&witness(&[msg_path(payload_type, msg_id)]))),
+    }
+}
+
+/// Attach a certificate and a witness covering every message to a page
+pub fn certified_page(page: MsgPage) -> CertifiedMsgPage {
+    let paths: Vec<CertifiedPath> = page
+        .messages
+        .iter()
+        .map(|(message, _)| msg_path(&message.payload_type, &message.msg_id))
+        .collect();
+
+    CertifiedMsgPage {
+        certificate: certificate(),
+        witness: ByteBuf::from(encode_witness(&witness(&paths))),
+        page,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{post, principal, reset};
+    use canister_types::message::MessageType;
+    use ic_certification::LookupResult;
+
+    fn root_hash() -> Vec<u8> {
+        CERTIFIED_TREE.with_borrow(|tree| tree.root_hash().to_vec())
+    }
+
+    /// Every field is hashed and field boundaries cannot shift
+    #[test]
+    fn entry_hashes_separate_the_fields() {
+        let message = post("bc", MessageType::Create, principal(1));
+        let hash = entry_hash(&message, principal(1));
+        assert_eq!(hash.len(), 32);
+        assert_ne!(hash, entry_hash(&message, principal(2)));
+
+        let shifted = Message {
+            payload_type: "MsgUserPostb".to_string(),
+            msg_id: "c".to_string(),
+            ..message.clone()
+        };
+        assert_ne!(hash, entry_hash(&shifted, principal(1)));
+        let updated = Message { msg_type: MessageType::Update, ..message.clone() };
+        assert_ne!(hash, entry_hash(&updated, principal(1)));
+        let later = Message { timestamp: 2, ..message };
+        assert_ne!(hash, entry_hash(&later, principal(1)));
+    }
+
+    /// Witnesses reveal the certified entries and hash to the root of the tree
+    #[test]
+    fn witnesses_prove_presence_and_absence() {
+        reset();
+        let stored = post("a", MessageType::Create, principal(1));
+        let other = post("b", MessageType::Create, principal(1));
+        certify_messages([(stored.clone(), principal(1)), (other, principal(1))].into_iter());
+
+        let present = msg_path("MsgUserPost", "a");
+        let missing = msg_path("MsgUserPost", "c");
+        let tree = witness(&[present.clone(), missing.clone()]);
+        assert_eq!(tree.digest().to_vec(), root_hash());
+        assert!(matches!(
+            tree.lookup_path(&present),
+            LookupResult::Found(leaf) if leaf == entry_hash(&stored, principal(1)).as_slice()
+        ));
+        assert!(matches!(tree.lookup_path(&missing), LookupResult::Absent));
+
+        uncertify_message("MsgUserPost", "a");
+        assert!(matches!(witness(&[present.clone()]).lookup_path(&present), LookupResult::Absent));
+        assert_eq!(witness(&[]).digest().to_vec(), root_hash());
+    }
+
+    /// No certificate is handed out while the tree misses stored messages
+    #[test]
+    fn rebuilds_withhold_the_certificate() {
+        reset();
+        begin_rebuild();
+        assert!(rebuilding());
+        assert!(certificate().is_none());
+        finish_rebuild();
+        assert!(!rebuilding());
+    }
+}
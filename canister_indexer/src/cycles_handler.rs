@@ -23,7 +23,7 @@ pub fn get_cycle_balance() -> candid::Nat {
 #[update]
 pub fn accept_cycles() -> CycleTransferResult {
     // Get the number of cycles available for acceptance
-    let available_cycles = ic_cdk::api::call::msg_cycles_available128();
+    let available_cycles = ic_cdk::api::msg_cycles_available();
 
     // If no cycles are available, return early with 0 received
     if available_cycles == 0 {
@@ -31,7 +31,7 @@ pub fn accept_cycles() -> CycleTransferResult {
     }
 
     // Accept all available cycles
-    let accepted_cycles = ic_cdk::api::call::msg_cycles_accept128(available_cycles);
+    let accepted_cycles = ic_cdk::api::msg_cycles_accept(available_cycles);
     
     // Verify that we accepted the expected number of cycles
     // This assertion ensures the cycle acceptance worked correctly
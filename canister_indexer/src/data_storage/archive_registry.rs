@@ -0,0 +1,119 @@
+//! Registry of child archive canisters and the wasm used to create them
+
+use super::*;
+
+/// Replace the wasm module installed into newly created archive canisters
+pub fn set_wasm(wasm: Vec<u8>) {
+    ARCHIVE_WASM.with_borrow_mut(|cell| {
+        cell.set(wasm).expect("failed to set ARCHIVE_WASM data");
+    });
+}
+
+/// The wasm module installed into newly created archive canisters
+pub fn wasm() -> Vec<u8> {
+    ARCHIVE_WASM.with_borrow(|cell| cell.get().clone())
+}
+
+/// Register a newly created archive canister, before its wasm module is installed
+///
+/// Registering right after creation keeps track of the funded canister
+/// if the installation fails, so that it is installed again instead of
+/// being replaced by a new one.
+pub fn register(canister_id: Principal) {
+    ARCHIVE_CANISTERS.with_borrow_mut(|registry| {
+        registry.insert(
+            canister_id,
+            ArchiveCanisterInfo {
+                canister_id,
+                created_at: ic_cdk::api::time(),
+                message_count: 0,
+                ranges: BTreeMap::new(),
+                installed: false,
+            },
+        )
+    });
+}
+
+/// Record that the archive wasm module was installed into an archive canister
+pub fn mark_installed(canister_id: Principal) {
+    ARCHIVE_CANISTERS.with_borrow_mut(|registry| {
+        if let Some(mut info) = registry.get(&canister_id) {
+            info.installed = true;
+            registry.insert(canister_id, info);
+        }
+    });
+}
+
+/// An archive canister that was created but whose installation failed
+pub fn pending_install() -> Option<Principal> {
+    ARCHIVE_CANISTERS.with_borrow(|registry| {
+        registry
+            .iter()
+            .map(|(_, info)| info)
+            .find(|info| !info.installed)
+            .map(|info| info.canister_id)
+    })
+}
+
+/// Record messages of a payload type handed over to an archive canister
+///
+/// `schema_version` is the oldest schema version of the handed over messages.
+pub fn record(
+    canister_id: Principal,
+    payload_type: &str,
+    from_timestamp: u64,
+    to_timestamp: u64,
+    count: u64,
+    schema_version: u32,
+) {
+    ARCHIVE_CANISTERS.with_borrow_mut(|registry| {
+        if let Some(mut info) = registry.get(&canister_id) {
+            info.record(payload_type, from_timestamp, to_timestamp, count, schema_version);
+            registry.insert(canister_id, info);
+        }
+    });
+}
+
+/// Record the progress of the re-encoding of a payload type in an archive canister
+///
+/// `completed_version` is the schema version all archived messages of the
+/// type are on once the re-encoding completed.
+pub fn record_schema_progress(
+    canister_id: Principal,
+    payload_type: &str,
+    next_cursor: Option<String>,
+    completed_version: Option<u32>,
+) {
+    ARCHIVE_CANISTERS.with_borrow_mut(|registry| {
+        let Some(mut info) = registry.get(&canister_id) else {
+            return;
+        };
+        if let Some(range) = info.ranges.get_mut(payload_type) {
+            range.schema_cursor = next_cursor;
+            if let Some(version) = completed_version {
+                range.schema_version = version;
+            }
+            registry.insert(canister_id, info);
+        }
+    });
+}
+
+/// The most recently created archive canister that still has room for `count` messages
+pub fn current_with_room(capacity: u64, count: u64) -> Option<Principal> {
+    ARCHIVE_CANISTERS.with_borrow(|registry| {
+        registry
+            .iter()
+            .map(|(_, info)| info)
+            .filter(|info| info.installed && info.message_count + count <= capacity)
+            .max_by_key(|info| info.created_at)
+            .map(|info| info.canister_id)
+    })
+}
+
+/// All archive canisters, oldest first
+pub fn list() -> Vec<ArchiveCanisterInfo> {
+    let mut archives: Vec<ArchiveCanisterInfo> =
+        ARCHIVE_CANISTERS.with_borrow(|registry| registry.iter().map(|(_, info)| info).collect());
+    archives.sort_by_key(|info| info.created_at);
+    archives
+}
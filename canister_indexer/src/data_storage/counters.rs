@@ -0,0 +1,225 @@
+//! Rolling message counters per payload type, principal and resource
+//!
+//! Counters are bucketed by hour and by day of the message timestamp and
+//! count created messages; deleting a message decrements its buckets.
+//! Buckets older than the retention of their granularity are pruned.
+
+use super::*;
+
+/// Count a message in or out of its buckets
+pub fn record(message: &Message, principal: Principal, added: bool) {
+    let subjects = CounterSubject::of(message, principal);
+
+    COUNTERS.with_borrow_mut(|counters| {
+        for granularity in CounterGranularity::ALL {
+            let bucket_start = granularity.bucket_start(message.timestamp);
+            for subject in &subjects {
+                let key = CounterKey {
+                    payload_type: message.payload_type.clone(),
+                    granularity,
+                    bucket_start,
+                    subject: subject.clone(),
+                };
+                let count = counters.get(&key).unwrap_or(0);
+                match (added, count) {
+                    (true, _) => {
+                        counters.insert(key, count + 1);
+                    }
+                    (false, 0) => {}
+                    (false, 1) => {
+                        counters.remove(&key);
+                    }
+                    (false, _) => {
+                        counters.insert(key, count - 1);
+                    }
+                }
+            }
+        }
+    });
+}
+
+/// Visit the counters of the buckets overlapping a window
+fn for_each_in_window(
+    payload_type: &str,
+    window: &CounterWindow,
+    mut f: impl FnMut(CounterKey, u64),
+) -> Result<(), String> {
+    let (first, last) = window.bucket_range()?;
+    let lower = CounterKey::lower_bound(payload_type, window.granularity, first);
+    let upper = CounterKey::lower_bound(
+        payload_type,
+        window.granularity,
+        last + window.granularity.bucket_nanos(),
+    );
+
+    COUNTERS.with_borrow(|counters| {
+        for (key, count) in counters.range(lower..upper) {
+            f(key, count);
+        }
+    });
+    Ok(())
+}
+
+/// Rank the principals or resources of a payload type by message count
+pub fn top(query: &TrendingQuery) -> Result<Vec<RankedSubject>, String> {
+    let mut totals: BTreeMap<CounterSubject, u64> = BTreeMap::new();
+    for_each_in_window(&query.payload_type, &query.window, |key, count| {
+        if key.subject.is_in(query.dimension) {
+            *totals.entry(key.subject).or_insert(0) += count;
+        }
+    })?;
+
+    let mut ranked: Vec<RankedSubject> = totals
+        .into_iter()
+        .map(|(subject, count)| RankedSubject { subject, count })
+        .collect();
+    // Highest count first; the stable sort keeps ties in subject order
+    ranked.sort_by(|a, b| b.count.cmp(&a.count));
+    ranked.truncate(query.top_n.min(MAX_TRENDING_COUNT));
+    Ok(ranked)
+}
+
+/// Message count of a payload type per bucket, oldest first
+pub fn activity(payload_type: &str, window: &CounterWindow) -> Result<Vec<ActivityBucket>, String> {
+    let mut buckets = Vec::new();
+    for_each_in_window(payload_type, window, |key, count| {
+        if key.subject == CounterSubject::Total {
+            buckets.push(ActivityBucket { bucket_start: key.bucket_start, count });
+        }
+    })?;
+    Ok(buckets)
+}
+
+/// Drop the buckets older than the retention of their granularity
+pub fn prune(now: u64) {
+    let expired: Vec<CounterKey> = COUNTERS.with_borrow(|counters| {
+        counters
+            .iter()
+            .map(|(key, _)| key)
+            .filter(|key| key.bucket_start.saturating_add(key.granularity.retention_nanos()) < now)
+            .collect()
+    });
+
+    COUNTERS.with_borrow_mut(|counters| {
+        for key in &expired {
+            counters.remove(key);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analytics::{CounterDimension, ResourceRef, MAX_WINDOW_BUCKETS};
+    use crate::test_fixtures::{post, principal, reset};
+
+    const HOUR: u64 = 60 * 60 * 1_000_000_000;
+
+    fn message(msg_id: &str, timestamp: u64, resource_id: Option<u64>) -> Message {
+        Message {
+            timestamp,
+            msg_resource: resource_id.map(|resource_id| MessageSource {
+                canister_id: principal(3),
+                resource_type: "game".to_string(),
+                resource_id,
+            }),
+            ..post(msg_id, MessageType::Create, principal(1))
+        }
+    }
+
+    fn hours(from: u64, to: u64) -> CounterWindow {
+        CounterWindow {
+            granularity: CounterGranularity::Hour,
+            from_timestamp: from * HOUR,
+            to_timestamp: to * HOUR,
+        }
+    }
+
+    fn counts(buckets: Vec<ActivityBucket>) -> Vec<(u64, u64)> {
+        buckets.into_iter().map(|bucket| (bucket.bucket_start / HOUR, bucket.count)).collect()
+    }
+
+    /// Windows cover the buckets they overlap, up to `MAX_WINDOW_BUCKETS`
+    #[test]
+    fn windows_cover_the_overlapping_buckets() {
+        let window = CounterWindow { from_timestamp: HOUR + 1, ..hours(0, 3) };
+        assert_eq!(window.bucket_range(), Ok((HOUR, 2 * HOUR)));
+        assert!(hours(3, 3).bucket_range().is_err());
+        assert!(hours(0, MAX_WINDOW_BUCKETS).bucket_range().is_ok());
+        assert!(hours(0, MAX_WINDOW_BUCKETS + 1).bucket_range().is_err());
+    }
+
+    /// Created messages count in their buckets and deleted ones count out
+    #[test]
+    fn activity_follows_created_and_deleted_messages() {
+        reset();
+        let first = message("a", HOUR, None);
+        record(&first, principal(1), true);
+        record(&message("b", HOUR + 5, None), principal(1), true);
+        record(&message("c", 3 * HOUR, None), principal(1), true);
+        assert_eq!(counts(activity("MsgUserPost", &hours(0, 4)).unwrap()), [(1, 2), (3, 1)]);
+
+        record(&first, principal(1), false);
+        record(&first, principal(1), false);
+        assert_eq!(counts(activity("MsgUserPost", &hours(0, 2)).unwrap()), [(1, 1)]);
+        assert!(activity("MsgUserPost", &hours(2, 1)).is_err());
+    }
+
+    /// Trending queries rank the subjects of one dimension, highest count first
+    #[test]
+    fn trending_ranks_one_dimension() {
+        reset();
+        let sent = [("a", 1, 1), ("b", 2, 2), ("c", 2, 2), ("d", 2, 1)];
+        for (msg_id, creator, resource_id) in sent {
+            record(&message(msg_id, HOUR, Some(resource_id)), principal(creator), true);
+        }
+        record(&message("e", HOUR, Some(2)), principal(1), true);
+
+        let query = |dimension, top_n| TrendingQuery {
+            payload_type: "MsgUserPost".to_string(),
+            dimension,
+            window: hours(0, 2),
+            top_n,
+        };
+        let ranked = |dimension, top_n| -> Vec<(CounterSubject, u64)> {
+            top(&query(dimension, top_n))
+                .unwrap()
+                .into_iter()
+                .map(|ranked| (ranked.subject, ranked.count))
+                .collect()
+        };
+        assert_eq!(
+            ranked(CounterDimension::Principal, 10),
+            [
+                (CounterSubject::Principal(principal(2)), 3),
+                (CounterSubject::Principal(principal(1)), 2),
+            ]
+        );
+        let resource = |resource_id| ResourceRef {
+            canister_id: principal(3),
+            resource_type: "game".to_string(),
+            resource_id,
+        };
+        assert_eq!(
+            ranked(CounterDimension::Resource, 1),
+            [(CounterSubject::Resource(resource(2)), 3)]
+        );
+    }
+
+    /// Pruning drops the buckets past the retention of their granularity
+    #[test]
+    fn prune_follows_the_retention() {
+        reset();
+        record(&message("a", HOUR, None), principal(1), true);
+        let now = HOUR + CounterGranularity::Hour.retention_nanos() + 1;
+        prune(now);
+
+        let day = CounterWindow {
+            granularity: CounterGranularity::Day,
+            from_timestamp: 0,
+            to_timestamp: 1,
+        };
+        assert_eq!(activity("MsgUserPost", &hours(0, 2)).unwrap().len(), 0);
+        assert_eq!(activity("MsgUserPost", &day).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,338 @@
+//! Comments, reactions and the aggregated engagement of messages
+
+use super::*;
+
+/// Change the aggregated counts of a message
+pub fn adjust(payload_type: &str, msg_id: &str, f: impl FnOnce(&mut Engagement)) {
+    let id_key = MsgIdKey::new(payload_type, msg_id);
+    ENGAGEMENT.with_borrow_mut(|engagement| {
+        let mut stats = engagement.get(&id_key).unwrap_or_default();
+        f(&mut stats);
+        if stats.is_empty() {
+            engagement.remove(&id_key);
+        } else {
+            engagement.insert(id_key, stats);
+        }
+    });
+}
+
+/// Aggregated counts of a message
+pub fn get(payload_type: &str, msg_id: &str) -> Engagement {
+    ENGAGEMENT.with_borrow(|engagement| {
+        engagement.get(&MsgIdKey::new(payload_type, msg_id)).unwrap_or_default()
+    })
+}
+
+/// Reject a comment whose parent message does not exist
+///
+/// `parent_exists` tells whether a message is stored, see `message::BatchCheck`.
+pub fn check_parent(
+    msg: &Message,
+    parent_exists: impl Fn(&str, &str) -> bool,
+) -> Result<(), IndexerError> {
+    match discussion::comment_parent(msg) {
+        Some((parent_payload_type, _))
+            if namespace::namespace_of(&parent_payload_type)
+                != namespace::namespace_of(&msg.payload_type) =>
+        {
+            Err(IndexerError::InvalidMessage(format!(
+                "A comment of type {} cannot reply to a message of type {}",
+                msg.payload_type, parent_payload_type
+            )))
+        }
+        Some((parent_payload_type, parent_msg_id))
+            if !parent_exists(&parent_payload_type, &parent_msg_id) =>
+        {
+            Err(IndexerError::MessageNotFound {
+                payload_type: parent_payload_type,
+                msg_id: parent_msg_id,
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Set, replace or (with None) remove the reaction of a principal to a message
+///
+/// # Returns
+/// The previous reaction of the principal
+pub fn react(
+    payload_type: &str,
+    msg_id: &str,
+    principal: Principal,
+    reaction: Option<String>,
+) -> Result<Option<String>, String> {
+    if store::locate(payload_type, msg_id).is_none() {
+        return Err(format!("Message {} not found in store for type '{}'", msg_id, payload_type));
+    }
+
+    let key = ReactionKey {
+        payload_type: payload_type.to_string(),
+        msg_id: msg_id.to_string(),
+        principal,
+    };
+    let previous = REACTIONS.with_borrow_mut(|reactions| match &reaction {
+        Some(reaction) => reactions.insert(key, reaction.clone()),
+        None => reactions.remove(&key),
+    });
+
+    if previous != reaction {
+        adjust(payload_type, msg_id, |stats| {
+            if let Some(previous) = &previous {
+                if let Some(count) = stats.reactions.get_mut(previous) {
+                    *count = count.saturating_sub(1);
+                    if *count == 0 {
+                        stats.reactions.remove(previous);
+                    }
+                }
+            }
+            if let Some(reaction) = &reaction {
+                *stats.reactions.entry(reaction.clone()).or_insert(0) += 1;
+            }
+        });
+    }
+    Ok(previous)
+}
+
+/// Restore an imported reaction, also of a message handed over to an archive canister
+pub fn restore_reaction(key: ReactionKey, reaction: String) {
+    let (payload_type, msg_id) = (key.payload_type.clone(), key.msg_id.clone());
+    if REACTIONS.with_borrow_mut(|reactions| reactions.insert(key, reaction.clone())).is_none() {
+        adjust(&payload_type, &msg_id, |stats| {
+            *stats.reactions.entry(reaction).or_insert(0) += 1;
+        });
+    }
+}
+
+/// Reaction of a principal to a message
+pub fn reaction_of(payload_type: &str, msg_id: &str, principal: Principal) -> Option<String> {
+    REACTIONS.with_borrow(|reactions| {
+        reactions.get(&ReactionKey {
+            payload_type: payload_type.to_string(),
+            msg_id: msg_id.to_string(),
+            principal,
+        })
+    })
+}
+
+/// The oldest `limit` comments on a message, skipping hidden ones
+fn replies(payload_type: &str, msg_id: &str, limit: usize) -> Vec<MsgEntry> {
+    let comment_type = discussion::comment_payload_type(payload_type);
+    let keys: Vec<(CommentKey, StoreTier)> = COMMENT_INDEX.with_borrow(|index| {
+        let range = CommentKey::lower_bound(payload_type, msg_id)
+            ..CommentKey::upper_bound(payload_type, msg_id);
+        index
+            .range(range)
+            .filter(|(key, _)| !moderation::is_hidden(&comment_type, &key.msg_id))
+            .take(limit)
+            .collect()
+    });
+
+    keys.into_iter()
+        .filter_map(|(key, tier)| {
+            store::with_tier(tier, |store| {
+                store.get(&MsgKey {
+                    payload_type: comment_type.clone(),
+                    timestamp: key.timestamp,
+                    msg_id: key.msg_id,
+                })
+            })
+        })
+        .collect()
+}
+
+/// Build the thread below a message, down to `depth` levels of replies
+pub fn thread(
+    payload_type: &str,
+    msg_id: &str,
+    depth: u32,
+    max_replies: usize,
+) -> Option<ThreadNode> {
+    let (entry, _) = store::get_entry(payload_type, msg_id)?;
+    Some(thread_node(entry, depth, max_replies))
+}
+
+fn thread_node(entry: MsgEntry, depth: u32, max_replies: usize) -> ThreadNode {
+    let replies = if depth == 0 {
+        Vec::new()
+    } else {
+        replies(&entry.message.payload_type, &entry.message.msg_id, max_replies)
+            .into_iter()
+            .map(|reply| thread_node(reply, depth - 1, max_replies))
+            .collect()
+    };
+
+    ThreadNode {
+        engagement: get(&entry.message.payload_type, &entry.message.msg_id),
+        message: entry.message,
+        principal: entry.principal,
+        replies,
+    }
+}
+
+/// Drop the reactions and counts of a removed message
+fn purge(payload_type: &str, msg_id: &str) {
+    let keys: Vec<ReactionKey> = REACTIONS.with_borrow(|reactions| {
+        let lower = ReactionKey {
+            payload_type: payload_type.to_string(),
+            msg_id: msg_id.to_string(),
+            principal: Principal::management_canister(),
+        };
+        reactions
+            .range(lower..)
+            .map(|(key, _)| key)
+            .take_while(|key| key.payload_type == payload_type && key.msg_id == msg_id)
+            .collect()
+    });
+    REACTIONS.with_borrow_mut(|reactions| {
+        for key in &keys {
+            reactions.remove(key);
+        }
+    });
+    ENGAGEMENT.with_borrow_mut(|engagement| {
+        engagement.remove(&MsgIdKey::new(payload_type, msg_id))
+    });
+}
+
+/// Remove the whole thread below a deleted message
+///
+/// The counters and subscribers are updated for every removed comment, and
+/// the reactions and counts of the message and of every removed comment
+/// are dropped. At most `ARCHIVE_MSG_MIGRATION_SIZE` comments are removed
+/// right away; the rest of a larger thread is queued and removed by
+/// `Job::ThreadDeletion`.
+pub fn delete_thread(payload_type: &str, msg_id: &str) {
+    let mut pending = vec![MsgIdKey::new(payload_type, msg_id)];
+    delete_threads(&mut pending, ARCHIVE_MSG_MIGRATION_SIZE);
+    if pending.is_empty() {
+        return;
+    }
+
+    let now = ic_cdk::api::time();
+    THREAD_DELETIONS.with_borrow_mut(|queue| {
+        for node in pending {
+            queue.insert(node, now);
+        }
+    });
+    scheduler::schedule(Job::ThreadDeletion, Duration::ZERO);
+}
+
+/// Remove up to `limit` comments of the queued threads
+///
+/// # Returns
+/// Whether comments are left to remove
+pub fn delete_queued_threads(limit: usize) -> bool {
+    let mut pending: Vec<MsgIdKey> = THREAD_DELETIONS.with_borrow_mut(|queue| {
+        let nodes: Vec<MsgIdKey> = queue.iter().take(limit).map(|(node, _)| node).collect();
+        for node in &nodes {
+            queue.remove(node);
+        }
+        nodes
+    });
+    delete_threads(&mut pending, limit);
+
+    let now = ic_cdk::api::time();
+    THREAD_DELETIONS.with_borrow_mut(|queue| {
+        for node in pending {
+            queue.insert(node, now);
+        }
+        !queue.is_empty()
+    })
+}
+
+/// Remove the comments below the pending messages, depth first, until
+/// `limit` comments are removed
+///
+/// A message leaves `pending` once no comment is left below it.
+fn delete_threads(pending: &mut Vec<MsgIdKey>, limit: usize) {
+    let mut removed = 0;
+
+    while let Some(node) = pending.last().cloned() {
+        if removed >= limit {
+            break;
+        }
+        let comment_type = discussion::comment_payload_type(&node.payload_type);
+        let children: Vec<CommentKey> = COMMENT_INDEX.with_borrow(|index| {
+            let range = CommentKey::lower_bound(&node.payload_type, &node.msg_id)
+                ..CommentKey::upper_bound(&node.payload_type, &node.msg_id);
+            index.range(range).take(limit - removed).map(|(key, _)| key).collect()
+        });
+
+        if children.is_empty() {
+            purge(&node.payload_type, &node.msg_id);
+            moderation::forget(&node.payload_type, &node.msg_id);
+            pending.pop();
+            continue;
+        }
+
+        for child in children {
+            match store::remove_entry(&comment_type, &child.msg_id) {
+                Some((entry, _)) => {
+                    counters::record(&entry.message, entry.principal, false);
+                    subscriptions::publish(MsgOutcome::Deleted, &entry.message, entry.principal);
+                }
+                // A stale index entry must not stop the thread from being removed
+                None => {
+                    COMMENT_INDEX.with_borrow_mut(|index| index.remove(&child));
+                }
+            }
+            pending.push(MsgIdKey::new(&comment_type, &child.msg_id));
+            removed += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{comment, controller, post, store};
+
+    /// Large threads are removed a few comments at a time, resuming where the last batch stopped
+    #[test]
+    fn delete_threads_stops_at_the_limit() {
+        let caller = controller();
+        let parent = post("a", MessageType::Create, caller);
+        let reply = comment("c1", &parent, 2, caller);
+        let nested = comment("c2", &reply, 3, caller);
+        let sibling = comment("c3", &parent, 4, caller);
+        for message in [&reply, &nested, &sibling] {
+            store(message, caller);
+        }
+
+        let mut pending = vec![MsgIdKey::new("MsgUserPost", "a")];
+        delete_threads(&mut pending, 2);
+        assert!(!pending.is_empty());
+        assert!(store::locate(&reply.payload_type, "c1").is_none());
+        assert!(store::locate(&sibling.payload_type, "c3").is_none());
+        assert!(store::locate(&nested.payload_type, "c2").is_some());
+
+        delete_threads(&mut pending, 2);
+        assert!(pending.is_empty());
+        assert!(store::locate(&nested.payload_type, "c2").is_none());
+        assert!(get("MsgUserPost", "a").is_empty());
+    }
+
+    /// A comment must reply to an existing message of its own namespace
+    #[test]
+    fn check_parent_requires_a_parent_in_the_namespace() {
+        let caller = controller();
+        let parent = post("a", MessageType::Create, caller);
+        let reply = comment("c1", &parent, 2, caller);
+        let exists =
+            |payload_type: &str, msg_id: &str| store::locate(payload_type, msg_id).is_some();
+
+        assert!(matches!(
+            check_parent(&reply, exists),
+            Err(IndexerError::MessageNotFound { .. })
+        ));
+        store(&parent, caller);
+        assert_eq!(check_parent(&reply, exists), Ok(()));
+
+        let foreign = Message { payload_type: "game:1/MsgComment".to_string(), ..reply };
+        assert!(matches!(
+            check_parent(&foreign, exists),
+            Err(IndexerError::InvalidMessage(_))
+        ));
+        assert_eq!(check_parent(&parent, exists), Ok(()));
+    }
+}
@@ -0,0 +1,26 @@
+//! History management module for archived messages
+
+use super::*;
+use crate::pagination::MsgPage;
+
+/// Retrieve a page of archived messages older than the cursor, newest first
+pub fn get_history_message_page(
+    message_type: &str,
+    limit: usize,
+    before: Option<&MsgCursor>,
+) -> MsgPage {
+    let fetched = store::page_entries(StoreTier::Archive, message_type, limit + 1, before);
+    MsgPage::from_fetched(fetched.into_iter().map(MsgEntry::into_pair).collect(), limit)
+}
+
+/// Retrieve archived messages for a given message type with pagination, oldest first
+pub fn get_history_message_list(
+    message_type: &str,
+    limit: usize,
+    offset: usize,
+) -> Vec<(Message, Principal)> {
+    store::list_entries(StoreTier::Archive, message_type, false, limit, offset)
+        .into_iter()
+        .map(MsgEntry::into_pair)
+        .collect()
+}
@@ -0,0 +1,731 @@
+//! Message management module
+
+use super::*;
+use crate::pagination::{MsgPage, TimelineQuery};
+use crate::text_search::{SearchHit, SearchPage};
+
+/// Get message size statistics for all message types
+pub fn get_message_size(namespace: Option<&str>) -> (Vec<(String, usize)>, usize) {
+    let mut set_sizes = Vec::new();
+    let mut total_size = 0;
+
+    for (key, stats) in store::all_type_stats() {
+        if stats.live == 0 || namespace::namespace_of(&key) != namespace {
+            continue;
+        }
+        let set_size = stats.live as usize;
+        total_size += set_size;
+        set_sizes.push((key, set_size));
+    }
+
+    (set_sizes, total_size)
+}
+
+/// Retrieve a live message by its message ID and type
+pub fn get_message(message_type: &str, message_id: &str) -> Option<(Message, Principal)> {
+    match store::get_entry(message_type, message_id)? {
+        (entry, StoreTier::Live) => Some(entry.into_pair()),
+        (_, StoreTier::Archive) => None,
+    }
+}
+
+/// Retrieve a live or archived message by its message ID and type
+pub fn get_stored_message(message_type: &str, message_id: &str) -> Option<(Message, Principal)> {
+    store::get_entry(message_type, message_id).map(|(entry, _)| entry.into_pair())
+}
+
+/// Get the message type keys of a namespace that have live messages
+pub fn get_message_keys(namespace: Option<&str>) -> Vec<String> {
+    store::all_type_stats()
+        .into_iter()
+        .filter(|(key, stats)| stats.live > 0 && namespace::namespace_of(key) == namespace)
+        .map(|(key, _)| key)
+        .collect()
+}
+
+/// Add a new message to the store, on the current schema version of its payload type
+pub fn create_message(message_type: &str, message: Message, principal: Principal) {
+    store_message(StoreTier::Live, message_type, message, principal);
+}
+
+/// Store a message in a tier, on the current schema version of its payload type
+fn store_message(tier: StoreTier, message_type: &str, message: Message, principal: Principal) {
+    debug_assert_eq!(message_type, message.payload_type);
+    let schema_version = state::with(|processor| {
+        processor
+            .payload_config(message_type)
+            .map_or(INITIAL_SCHEMA_VERSION, |config| config.schema_version)
+    });
+    store::insert_entry(tier, MsgEntry { message, principal, schema_version });
+    if tier == StoreTier::Archive {
+        return;
+    }
+
+    // Schedule a cleanup run if message count exceeds the retention threshold
+    let threshold = state::with(|processor| processor.archive_threshold(message_type));
+    if threshold.is_some_and(|threshold| store::type_stats(message_type).live > threshold) {
+        scheduler::schedule(Job::Cleanup, Duration::from_secs(CLEANUP_DELAY_SECS));
+    }
+}
+
+/// Retrieve a list of messages for a given message type with pagination
+pub fn get_message_list(
+    message_type: &str,
+    limit: usize,
+    offset: usize,
+) -> Vec<(Message, Principal)> {
+    store::list_entries(StoreTier::Live, message_type, true, limit, offset)
+        .into_iter()
+        .map(MsgEntry::into_pair)
+        .collect()
+}
+
+/// Retrieve messages created by a principal with pagination, newest first
+///
+/// Uses the principal index, so the results span both the live and the
+/// archive store.
+pub fn get_message_list_by_pid(
+    message_type: &str,
+    pid: Principal,
+    limit: usize,
+    offset: usize,
+) -> Vec<(Message, Principal)> {
+    store::list_principal_entries(pid, message_type, limit, offset)
+        .into_iter()
+        .map(MsgEntry::into_pair)
+        .collect()
+}
+
+/// Retrieve a page of live messages older than the cursor, newest first
+pub fn get_message_page(
+    message_type: &str,
+    limit: usize,
+    before: Option<&MsgCursor>,
+) -> MsgPage {
+    let fetched = store::page_entries(StoreTier::Live, message_type, limit + 1, before);
+    MsgPage::from_fetched(fetched.into_iter().map(MsgEntry::into_pair).collect(), limit)
+}
+
+/// Retrieve a page of a principal's messages older than the cursor, newest first
+pub fn get_message_page_by_pid(
+    message_type: &str,
+    pid: Principal,
+    limit: usize,
+    before: Option<&MsgCursor>,
+) -> MsgPage {
+    let fetched = store::page_principal_entries(pid, message_type, limit + 1, before);
+    MsgPage::from_fetched(fetched.into_iter().map(MsgEntry::into_pair).collect(), limit)
+}
+
+/// Search the messages of searchable payload types, best match first
+///
+/// Without a payload type, the searchable types of one namespace are
+/// searched, or the global ones for None.
+pub fn search_messages(
+    terms: &[String],
+    payload_type: Option<&str>,
+    namespace: Option<&str>,
+    offset: usize,
+    limit: usize,
+) -> SearchPage {
+    let (hits, has_more) = store::search_entries(terms, payload_type, namespace, offset, limit);
+    let next_cursor = has_more.then(|| (offset + limit).to_string());
+
+    SearchPage {
+        hits: hits
+            .into_iter()
+            .map(|(entry, score)| SearchHit {
+                message: entry.message,
+                principal: entry.principal,
+                score,
+            })
+            .collect(),
+        next_cursor,
+    }
+}
+
+/// Retrieve a page of the messages about a resource, newest first
+pub fn get_message_page_by_resource(
+    namespace: Option<&str>,
+    canister_id: Principal,
+    resource_type: &str,
+    resource_id: Option<u64>,
+    limit: usize,
+    before: Option<&MsgCursor>,
+) -> MsgPage {
+    let fetched = store::page_resource_entries(
+        namespace,
+        canister_id,
+        resource_type,
+        resource_id,
+        limit + 1,
+        before,
+    );
+    MsgPage::from_fetched(fetched.into_iter().map(MsgEntry::into_pair).collect(), limit)
+}
+
+/// Retrieve a merged page of several payload types within a time window, newest first
+pub fn get_timeline_page(
+    query: &TimelineQuery,
+    limit: usize,
+    before: Option<&MsgCursor>,
+) -> MsgPage {
+    let fetched = store::timeline_entries(
+        &query.payload_types,
+        query.principal,
+        query.from_timestamp,
+        query.to_timestamp,
+        before,
+        limit + 1,
+    );
+    MsgPage::from_fetched(fetched.into_iter().map(MsgEntry::into_pair).collect(), limit)
+}
+
+/// Delete a message by its message ID and message type from either store,
+/// or from the archive canister it was spilled to
+pub fn delete_message(message_type: &str, message_id: &str) -> Result<(), String> {
+    if store::remove_entry(message_type, message_id).is_some()
+        || spill::remove(message_type, message_id).is_some()
+    {
+        return Ok(());
+    }
+    Err(format!("Message {} not found in store for type '{}'", message_id, message_type))
+}
+
+/// Delete a stored or spilled message together with its comments
+///
+/// Counters and subscribers are updated for the message and every removed
+/// comment, see `engagement::delete_thread`.
+fn delete_with_thread(stored: &Message, creator: Principal) -> Result<(), String> {
+    delete_message(&stored.payload_type, &stored.msg_id)?;
+    counters::record(stored, creator, false);
+    subscriptions::publish(MsgOutcome::Deleted, stored, creator);
+
+    // Comments go with the message they reply to
+    engagement::delete_thread(&stored.payload_type, &stored.msg_id);
+    Ok(())
+}
+
+/// Delete up to `count` messages of a payload type older than `before`,
+/// from both tiers and the archive canisters
+///
+/// Comments on the expired messages are deleted with them, and counters
+/// and subscribers are updated as for a Delete.
+///
+/// # Returns
+/// The number of messages deleted
+pub fn expire_before(payload_type: &str, before: u64, count: usize) -> usize {
+    // Spilled messages are the oldest; their archive deletes them on the next cleanup
+    let mut msg_ids = spill::older_than(payload_type, before, count);
+    for tier in [StoreTier::Archive, StoreTier::Live] {
+        let left = count - msg_ids.len();
+        msg_ids.extend(store::ids_before(tier, payload_type, before, left));
+    }
+
+    let mut expired = 0;
+    for msg_id in msg_ids {
+        // Comments of an earlier expired message may be gone already
+        let Some((stored, creator)) = find_any_message(payload_type, &msg_id)
+            .or_else(|| find_spilled_message(payload_type, &msg_id))
+        else {
+            continue;
+        };
+        if delete_with_thread(&stored, creator).is_ok() {
+            expired += 1;
+        }
+    }
+    if expired > 0 {
+        crate::subscription_manager::schedule_delivery();
+    }
+    expired
+}
+
+/// Delete a message on behalf of a moderator, together with its comments
+///
+/// Counters and subscribers are updated as for a Delete sent by the creator.
+pub fn delete_moderated_message(
+    message_type: &str,
+    message_id: &str,
+) -> Result<(), IndexerError> {
+    if migration::in_progress() {
+        return Err(IndexerError::MigrationInProgress);
+    }
+    let (stored, creator) = find_any_message(message_type, message_id)
+        .or_else(|| find_spilled_message(message_type, message_id))
+        .ok_or_else(|| IndexerError::MessageNotFound {
+            payload_type: message_type.to_string(),
+            msg_id: message_id.to_string(),
+        })?;
+    let action = MsgAction::Delete { stored: stored.clone(), creator };
+    apply_message_action(&stored, creator, action)?;
+    crate::subscription_manager::schedule_delivery();
+    Ok(())
+}
+
+/// Find a message in the live store, falling back to the archive store
+pub fn find_any_message(message_type: &str, message_id: &str) -> Option<(Message, Principal)> {
+    store::get_entry(message_type, message_id).map(|(entry, _)| entry.into_pair())
+}
+
+/// Find a message spilled to an archive canister, without its payload
+fn find_spilled_message(message_type: &str, message_id: &str) -> Option<(Message, Principal)> {
+    spill::get(message_type, message_id).map(|spilled| (spilled.message, spilled.creator))
+}
+
+/// Ensure the caller is the creator of a stored message or an admin
+fn ensure_message_owner(
+    message_type: &str,
+    message_id: &str,
+    creator: Principal,
+    caller: Principal,
+) -> Result<(), IndexerError> {
+    if creator == caller || state::with(|processor| processor.is_admin(&caller)) {
+        Ok(())
+    } else {
+        Err(IndexerError::NotMessageOwner {
+            payload_type: message_type.to_string(),
+            msg_id: message_id.to_string(),
+        })
+    }
+}
+
+/// Whether two versions of a message carry the same content
+///
+/// The message type is ignored so that a retried Update or Replace of an
+/// already applied change is recognised as a no-op.
+fn same_content(stored: &Message, incoming: &Message) -> bool {
+    stored.payload == incoming.payload
+        && stored.msg_resource == incoming.msg_resource
+        && stored.timestamp == incoming.timestamp
+        && stored.caller == incoming.caller
+}
+
+/// Change a message makes to the stored data, decided before anything is written
+enum MsgAction {
+    /// Store the message as a new message of the caller
+    Create,
+    /// Replace the stored version, keeping its creator
+    Rewrite { stored: Message, creator: Principal },
+    /// Remove the stored message
+    Delete { stored: Message, creator: Principal },
+    /// The message is already applied
+    Skip,
+}
+
+/// Check that a message would be accepted, without storing anything
+///
+/// Runs the same authorization, registry validation (including payload
+/// decoding) and existence checks as `process_message`.
+pub fn check_message(msg: &Message, caller: Principal) -> Result<(), IndexerError> {
+    plan_message(msg, caller, &BatchCheck::default()).map(|_| ())
+}
+
+/// Checks the messages of a batch in order without storing anything, each
+/// against the stored data as the earlier messages of the batch leave it
+///
+/// A message may thus update or delete a message created earlier in the
+/// batch, or reply to it; deleting a message also removes its thread.
+#[derive(Default)]
+pub struct BatchCheck {
+    /// Messages written by the batch so far, None for the ones it deleted
+    written: BTreeMap<MsgIdKey, Option<(Message, Principal)>>,
+    /// Messages deleted by the batch, with their threads
+    deleted: BTreeSet<MsgIdKey>,
+}
+
+impl BatchCheck {
+    /// Check the next message of the batch, as `check_message` does
+    pub fn check(&mut self, msg: &Message, caller: Principal) -> Result<(), IndexerError> {
+        let action = plan_message(msg, caller, self)?;
+        self.record(msg, caller, &action);
+        Ok(())
+    }
+
+    /// Whether the batch created, rewrote or deleted a message
+    fn touches(&self, payload_type: &str, msg_id: &str) -> bool {
+        self.written.contains_key(&MsgIdKey::new(payload_type, msg_id))
+    }
+
+    /// A local message as the batch leaves it
+    fn find(&self, payload_type: &str, msg_id: &str) -> Option<(Message, Principal)> {
+        match self.written.get(&MsgIdKey::new(payload_type, msg_id)) {
+            Some(written) => written.clone(),
+            None => find_any_message(payload_type, msg_id).filter(|(message, _)| {
+                self.deleted.is_empty()
+                    || !self.ancestors(message).iter().any(|key| self.deleted.contains(key))
+            }),
+        }
+    }
+
+    /// Messages a comment replies to, directly or through other comments
+    fn ancestors(&self, message: &Message) -> Vec<MsgIdKey> {
+        let mut ancestors: Vec<MsgIdKey> = Vec::new();
+        let mut parent = discussion::comment_parent(message);
+        while let Some((payload_type, msg_id)) = parent {
+            let key = MsgIdKey::new(&payload_type, &msg_id);
+            // An update may have moved a comment below one of its replies
+            if ancestors.contains(&key) {
+                break;
+            }
+            parent = match self.written.get(&key) {
+                Some(written) => {
+                    written.as_ref().and_then(|(message, _)| discussion::comment_parent(message))
+                }
+                None => find_any_message(&payload_type, &msg_id)
+                    .and_then(|(message, _)| discussion::comment_parent(&message)),
+            };
+            ancestors.push(key);
+        }
+        ancestors
+    }
+
+    /// Record what a checked message changes, as `apply_message_action` would
+    fn record(&mut self, msg: &Message, caller: Principal, action: &MsgAction) {
+        let key = MsgIdKey::new(&msg.payload_type, &msg.msg_id);
+        match action {
+            MsgAction::Create => {
+                self.written.insert(key, Some((msg.clone(), caller)));
+            }
+            MsgAction::Rewrite { creator, .. } => {
+                self.written.insert(key, Some((msg.clone(), *creator)));
+            }
+            MsgAction::Delete { .. } => {
+                // Comments written by the batch go with the thread, see `delete_with_thread`
+                let thread: Vec<MsgIdKey> = self
+                    .written
+                    .iter()
+                    .filter_map(|(written_key, written)| {
+                        let (message, _) = written.as_ref()?;
+                        self.ancestors(message).contains(&key).then(|| written_key.clone())
+                    })
+                    .collect();
+                for deleted in thread.into_iter().chain(std::iter::once(key)) {
+                    self.written.insert(deleted.clone(), None);
+                    self.deleted.insert(deleted);
+                }
+            }
+            MsgAction::Skip => {}
+        }
+    }
+}
+
+/// Process incoming message based on its payload type and message type
+///
+/// The payload type must be registered; the message is validated against
+/// its registry entry (allowed message types, payload size and decoding).
+pub async fn process_message(msg: Message, caller: Principal) -> Result<MsgReceipt, IndexerError> {
+    process_verified_message(msg, caller, None)
+}
+
+/// Process a message as `process_message` does, recording the verification
+/// of its signature once it is stored
+///
+/// A message stored without a verification loses the one of its previous version.
+/// A verified message older than the stored version is rejected, as its
+/// signature may be replayed from before a later change.
+pub fn process_verified_message(
+    msg: Message,
+    caller: Principal,
+    verification: Option<MsgVerification>,
+) -> Result<MsgReceipt, IndexerError> {
+    let action = plan_message(&msg, caller, &BatchCheck::default())?;
+    if verification.is_some() {
+        check_signed_version(&msg, &action)?;
+    }
+    let outcome = apply_message_action(&msg, caller, action)?;
+    // An unchanged message keeps the verification it was stored with, if any
+    if let Some(verification) = verification {
+        if matches!(outcome, MsgOutcome::Created | MsgOutcome::Updated) {
+            signatures::record_verification(&msg.payload_type, &msg.msg_id, verification);
+        }
+    }
+    if outcome != MsgOutcome::Unchanged {
+        crate::subscription_manager::schedule_delivery();
+    }
+    Ok(MsgReceipt { msg_id: msg.msg_id, outcome })
+}
+
+/// Reject a signed message older than the stored version it changes
+///
+/// Signed digests cover the timestamp, see `signatures::signed_digest`.
+fn check_signed_version(msg: &Message, action: &MsgAction) -> Result<(), IndexerError> {
+    match action {
+        MsgAction::Rewrite { stored, .. } | MsgAction::Delete { stored, .. }
+            if msg.timestamp < stored.timestamp =>
+        {
+            Err(IndexerError::InvalidSignature(format!(
+                "Signed version {} of message {} is older than the stored version {}",
+                msg.timestamp, msg.msg_id, stored.timestamp
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Authorize and validate a message and decide what it changes
+///
+/// `batch` holds the changes of the earlier messages of a checked batch.
+fn plan_message(
+    msg: &Message,
+    caller: Principal,
+    batch: &BatchCheck,
+) -> Result<MsgAction, IndexerError> {
+    // Imported messages would otherwise race with new writes
+    if state::with(|processor| processor.import_in_progress()) {
+        return Err(IndexerError::ImportInProgress);
+    }
+    // Messages written during the index backfill could be indexed twice
+    if migration::in_progress() {
+        return Err(IndexerError::MigrationInProgress);
+    }
+
+    // Reject callers that are not registered for this payload and message type
+    state::with(|processor| {
+        processor.authorize_publisher(caller, &msg.payload_type, &msg.msg_type)
+    })?;
+
+    // Look up and apply the payload type's registry entry
+    let config = state::with(|processor| processor.payload_config(&msg.payload_type))
+        .ok_or_else(|| IndexerError::UnknownPayloadType(msg.payload_type.clone()))?;
+    config.validate(msg)?;
+    state::with(|processor| {
+        processor
+            .rate_limit_config(processor.rate_limit_scope(&msg.payload_type))
+            .check_payload_size(msg)
+    })?;
+    engagement::check_parent(msg, |payload_type, msg_id| {
+        batch.find(payload_type, msg_id).is_some()
+    })?;
+
+    plan_message_operation(msg, caller, batch)
+}
+
+/// Decide what a message operation (Create, Delete, Update, Replace) changes
+///
+/// All operations are idempotent so that retried deliveries are safe:
+/// * `Create` of an existing id with identical content is `Unchanged`;
+///   with different content it fails with `DuplicateMessage`
+/// * `Update` rewrites an existing message and fails if it does not exist
+/// * `Replace` upserts: it creates a missing message or rewrites an existing one
+/// * `Delete` of a missing message is `Unchanged`
+///
+/// Messages spilled to archive canisters are planned by `plan_spilled_operation`.
+fn plan_message_operation(
+    msg: &Message,
+    caller: Principal,
+    batch: &BatchCheck,
+) -> Result<MsgAction, IndexerError> {
+    let msg_name = msg.payload_type.as_str();
+    let msg_id = &msg.msg_id;
+    let existing = batch.find(msg_name, msg_id);
+    if existing.is_none() && !batch.touches(msg_name, msg_id) {
+        if let Some(spilled) = find_spilled_message(msg_name, msg_id) {
+            return plan_spilled_operation(msg, caller, spilled);
+        }
+    }
+
+    match (&msg.msg_type, existing) {
+        (MessageType::Create, None) | (MessageType::Replace, None) => Ok(MsgAction::Create),
+        (MessageType::Create, Some((stored, creator))) => {
+            if creator == caller && same_content(&stored, msg) {
+                Ok(MsgAction::Skip)
+            } else {
+                Err(IndexerError::DuplicateMessage {
+                    payload_type: msg_name.to_string(),
+                    msg_id: msg_id.clone(),
+                })
+            }
+        }
+        (MessageType::Update, None) => Err(IndexerError::MessageNotFound {
+            payload_type: msg_name.to_string(),
+            msg_id: msg_id.clone(),
+        }),
+        (MessageType::Update, Some((stored, creator)))
+        | (MessageType::Replace, Some((stored, creator))) => {
+            // Only the creator (or an admin) may rewrite a message; the creator is preserved
+            ensure_message_owner(msg_name, msg_id, creator, caller)?;
+            if same_content(&stored, msg) {
+                Ok(MsgAction::Skip)
+            } else {
+                Ok(MsgAction::Rewrite { stored, creator })
+            }
+        }
+        (MessageType::Delete, None) => Ok(MsgAction::Skip),
+        (MessageType::Delete, Some((stored, creator))) => {
+            ensure_message_owner(msg_name, msg_id, creator, caller)?;
+            Ok(MsgAction::Delete { stored, creator })
+        }
+    }
+}
+
+/// Decide what a message operation changes for a message spilled to an archive canister
+///
+/// The payload of a spilled message is not kept here, so a Create of its id
+/// always fails with `DuplicateMessage`, and an Update or Replace always
+/// rewrites it; the new version is stored here and the archive copy deleted.
+fn plan_spilled_operation(
+    msg: &Message,
+    caller: Principal,
+    (stored, creator): (Message, Principal),
+) -> Result<MsgAction, IndexerError> {
+    let msg_name = msg.payload_type.as_str();
+    let msg_id = &msg.msg_id;
+
+    match msg.msg_type {
+        MessageType::Create => Err(IndexerError::DuplicateMessage {
+            payload_type: msg_name.to_string(),
+            msg_id: msg_id.clone(),
+        }),
+        MessageType::Update | MessageType::Replace => {
+            ensure_message_owner(msg_name, msg_id, creator, caller)?;
+            Ok(MsgAction::Rewrite { stored, creator })
+        }
+        MessageType::Delete => {
+            ensure_message_owner(msg_name, msg_id, creator, caller)?;
+            Ok(MsgAction::Delete { stored, creator })
+        }
+    }
+}
+
+/// Write the change decided by `plan_message_operation`
+fn apply_message_action(
+    msg: &Message,
+    caller: Principal,
+    action: MsgAction,
+) -> Result<MsgOutcome, IndexerError> {
+    let msg_name = msg.payload_type.as_str();
+    let msg_id = msg.msg_id.as_str();
+
+    match action {
+        MsgAction::Create => {
+            create_message(msg_name, msg.clone(), caller);
+            counters::record(msg, caller, true);
+            subscriptions::publish(MsgOutcome::Created, msg, caller);
+            Ok(MsgOutcome::Created)
+        }
+        MsgAction::Rewrite { stored, creator } => {
+            // Delete the existing message first, then store the new version in its tier
+            let tier =
+                store::locate(msg_name, msg_id).map_or(StoreTier::Live, |(_, tier)| tier);
+            delete_message(msg_name, msg_id).map_err(IndexerError::InvalidMessage)?;
+            store_message(tier, msg_name, msg.clone(), creator);
+            // The new version may have moved to another bucket or resource
+            counters::record(&stored, creator, false);
+            counters::record(msg, creator, true);
+            subscriptions::publish(MsgOutcome::Updated, msg, creator);
+            Ok(MsgOutcome::Updated)
+        }
+        MsgAction::Delete { stored, creator } => {
+            delete_with_thread(&stored, creator).map_err(IndexerError::InvalidMessage)?;
+            Ok(MsgOutcome::Deleted)
+        }
+        MsgAction::Skip => Ok(MsgOutcome::Unchanged),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{comment, controller, post, principal, publisher, store};
+
+    fn plan(msg: &Message, caller: Principal) -> Result<MsgAction, IndexerError> {
+        plan_message(msg, caller, &BatchCheck::default())
+    }
+
+    fn changed(msg_id: &str, msg_type: MessageType, caller: Principal) -> Message {
+        Message { payload: b"changed".to_vec().into(), ..post(msg_id, msg_type, caller) }
+    }
+
+    /// Retried messages are no-ops, and only Create of a taken id fails
+    #[test]
+    fn operations_are_idempotent() {
+        let caller = controller();
+        store(&post("a", MessageType::Create, caller), caller);
+
+        let planned = |msg_id: &str, msg_type| plan(&post(msg_id, msg_type, caller), caller);
+        assert!(matches!(planned("a", MessageType::Create), Ok(MsgAction::Skip)));
+        assert!(matches!(planned("a", MessageType::Update), Ok(MsgAction::Skip)));
+        assert!(matches!(
+            plan(&changed("a", MessageType::Create, caller), caller),
+            Err(IndexerError::DuplicateMessage { .. })
+        ));
+        assert!(matches!(
+            plan(&changed("a", MessageType::Replace, caller), caller),
+            Ok(MsgAction::Rewrite { .. })
+        ));
+        assert!(matches!(
+            planned("b", MessageType::Update),
+            Err(IndexerError::MessageNotFound { .. })
+        ));
+        assert!(matches!(planned("b", MessageType::Replace), Ok(MsgAction::Create)));
+        assert!(matches!(planned("b", MessageType::Delete), Ok(MsgAction::Skip)));
+    }
+
+    /// Only the creator or an admin may change a message
+    #[test]
+    fn only_the_creator_changes_a_message() {
+        let creator = controller();
+        let other = publisher(8);
+        store(&post("a", MessageType::Create, creator), creator);
+
+        for msg_type in [MessageType::Update, MessageType::Replace, MessageType::Delete] {
+            assert!(matches!(
+                plan(&changed("a", msg_type, other), other),
+                Err(IndexerError::NotMessageOwner { .. })
+            ));
+        }
+        assert!(matches!(
+            plan(&changed("a", MessageType::Update, creator), creator),
+            Ok(MsgAction::Rewrite { creator: kept, .. }) if kept == creator
+        ));
+    }
+
+    /// Spilled messages keep their id taken and can still be changed by their creator
+    #[test]
+    fn spilled_messages_stay_reachable() {
+        controller();
+        let creator = publisher(8);
+        let message = post("a", MessageType::Create, creator);
+        let entry = MsgEntry { message: message.clone(), principal: creator, schema_version: 1 };
+        spill::record(principal(9), &entry);
+
+        assert!(matches!(plan(&message, creator), Err(IndexerError::DuplicateMessage { .. })));
+        assert!(matches!(
+            plan(&post("a", MessageType::Update, creator), creator),
+            Ok(MsgAction::Rewrite { .. })
+        ));
+        assert!(matches!(
+            plan(&post("a", MessageType::Delete, creator), creator),
+            Ok(MsgAction::Delete { .. })
+        ));
+        assert!(matches!(
+            plan(&post("a", MessageType::Delete, publisher(10)), publisher(10)),
+            Err(IndexerError::NotMessageOwner { .. })
+        ));
+        assert_eq!(find_spilled_message("MsgUserPost", "a").map(|(_, by)| by), Some(creator));
+        assert!(find_spilled_message("MsgUserPost", "b").is_none());
+    }
+
+    /// Deleting a message removes its whole thread and nothing else
+    #[test]
+    fn delete_removes_the_thread() {
+        let caller = controller();
+        let deleted = post("a", MessageType::Create, caller);
+        let kept = post("b", MessageType::Create, caller);
+        let reply = comment("c1", &deleted, 2, caller);
+        let nested = comment("c2", &reply, 3, caller);
+        let other = comment("c3", &kept, 2, caller);
+        for message in [&deleted, &kept, &reply, &nested, &other] {
+            store(message, caller);
+        }
+        assert_eq!(engagement::get("MsgUserPost", "a").comments, 1);
+
+        delete_with_thread(&deleted, caller).unwrap();
+        for removed in [&deleted, &reply, &nested] {
+            assert!(find_any_message(&removed.payload_type, &removed.msg_id).is_none());
+        }
+        assert!(find_any_message(&other.payload_type, "c3").is_some());
+        assert!(engagement::get("MsgUserPost", "a").is_empty());
+        assert_eq!(engagement::get("MsgUserPost", "b").comments, 1);
+    }
+}
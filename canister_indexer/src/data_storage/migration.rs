@@ -0,0 +1,307 @@
+//! Storage layout migrations and the certified tree rebuild started from `post_upgrade`
+
+use super::*;
+
+use std::ops::Bound::{Excluded, Unbounded};
+
+/// Indexes to rebuild from the stored messages
+#[derive(Default)]
+struct Backfills {
+    principal_index: bool,
+    resource_index: bool,
+    search_terms: bool,
+    counters: bool,
+    schema_index: bool,
+}
+
+impl Backfills {
+    /// Indexes added since a storage version
+    fn since(version: u32) -> Self {
+        if version < 1 {
+            // Moved messages are indexed when inserted, except for the counters
+            return Backfills { counters: true, ..Backfills::default() };
+        }
+        Backfills {
+            principal_index: version < 2,
+            resource_index: version < 4,
+            search_terms: version < 5,
+            counters: version < 6,
+            schema_index: version < 8,
+        }
+    }
+}
+
+/// Bring the configuration up to `CURRENT_STORAGE_VERSION` and start the
+/// migration of the message stores
+///
+/// The stores are migrated in batches by `Job::StorageMigration`, and
+/// writes are rejected until the last batch, see `in_progress`. An upgrade
+/// during the migration resumes it where it stopped.
+pub fn run() {
+    let version = state::with(|processor| processor.storage_version);
+    if version >= CURRENT_STORAGE_VERSION {
+        return;
+    }
+
+    // Configuration first, so that the backfills index with the current settings
+    if version < 3 {
+        apply_builtin_retention();
+    }
+    if version < 5 {
+        enable_builtin_search();
+    }
+    if version < 7 {
+        register_builtin_types();
+    }
+
+    state::with_mut(|processor| {
+        processor.migration.get_or_insert(StorageMigration {
+            from_version: version,
+            cursor: None,
+        });
+    });
+    scheduler::schedule(Job::StorageMigration, Duration::ZERO);
+}
+
+/// Checks if the message stores are being migrated
+pub fn in_progress() -> bool {
+    state::with(|processor| processor.migration_in_progress())
+}
+
+/// Migrate the next batch of at most `limit` messages
+///
+/// Legacy collections are split first, then the indexes added since the
+/// stored version are backfilled over both tiers. The last batch records
+/// the current storage version.
+///
+/// # Returns
+/// Whether messages are left to migrate
+pub fn migrate_batch(limit: usize) -> bool {
+    let Some(mut migration) = state::with(|processor| processor.migration.clone()) else {
+        return false;
+    };
+
+    if migrate_legacy_collections(limit) > 0 {
+        return true;
+    }
+
+    let backfills = Backfills::since(migration.from_version);
+    let batch = next_backfill_batch(migration.cursor.as_ref(), limit);
+    for (tier, key, entry) in &batch {
+        backfill_entry(&backfills, *tier, key, entry);
+    }
+
+    match batch.into_iter().last() {
+        Some((tier, key, _)) => {
+            migration.cursor = Some((tier, key));
+            state::with_mut(|processor| processor.migration = Some(migration));
+            true
+        }
+        None => {
+            if backfills.counters {
+                counters::prune(ic_cdk::api::time());
+            }
+            state::with_mut(|processor| {
+                processor.migration = None;
+                processor.storage_version = CURRENT_STORAGE_VERSION;
+            });
+            ic_cdk::println!(
+                "migrate_batch: migrated the message stores from version {} to {}",
+                migration.from_version, CURRENT_STORAGE_VERSION
+            );
+            false
+        }
+    }
+}
+
+/// Messages following the cursor, the live tier first
+fn next_backfill_batch(
+    cursor: Option<&(StoreTier, MsgKey)>,
+    limit: usize,
+) -> Vec<(StoreTier, MsgKey, MsgEntry)> {
+    let tiers: &[StoreTier] = match cursor {
+        Some((StoreTier::Archive, _)) => &[StoreTier::Archive],
+        _ => &[StoreTier::Live, StoreTier::Archive],
+    };
+
+    let mut batch = Vec::new();
+    for &tier in tiers {
+        let after = cursor.filter(|(cursor_tier, _)| *cursor_tier == tier);
+        store::with_tier(tier, |store| {
+            let entries = match after {
+                Some((_, key)) => store.range((Excluded(key.clone()), Unbounded)),
+                None => store.range(..),
+            };
+            batch.extend(
+                entries
+                    .take(limit - batch.len())
+                    .map(|(key, entry)| (tier, key, entry)),
+            );
+        });
+        if batch.len() == limit {
+            break;
+        }
+    }
+    batch
+}
+
+/// Rebuild the requested indexes of a stored message
+///
+/// Messages stored before schema versions were tracked are on `INITIAL_SCHEMA_VERSION`.
+fn backfill_entry(backfills: &Backfills, tier: StoreTier, key: &MsgKey, entry: &MsgEntry) {
+    if backfills.principal_index {
+        let index_key = PrincipalIndexKey::of(entry.principal, key);
+        PRINCIPAL_INDEX.with_borrow_mut(|index| index.insert(index_key, tier));
+    }
+    if backfills.resource_index {
+        RESOURCE_INDEX.with_borrow_mut(|index| {
+            for index_key in ResourceIndexKey::of(&entry.message) {
+                index.insert(index_key, tier);
+            }
+        });
+    }
+    if backfills.search_terms {
+        store::index_search_terms(&entry.message);
+    }
+    if backfills.counters {
+        counters::record(&entry.message, entry.principal, true);
+    }
+    if backfills.schema_index {
+        let index_key = SchemaIndexKey::of(key, entry.schema_version);
+        let count_key = SchemaCountKey {
+            payload_type: index_key.payload_type.clone(),
+            schema_version: index_key.schema_version,
+        };
+        SCHEMA_COUNTS.with_borrow_mut(|counts| {
+            let count = counts.get(&count_key).unwrap_or(0);
+            counts.insert(count_key, count + 1);
+        });
+        SCHEMA_INDEX.with_borrow_mut(|index| index.insert(index_key, tier));
+    }
+}
+
+/// Start rebuilding the certified tree, which lives on the heap and is lost
+/// by every upgrade
+///
+/// The stored messages are certified in batches by `Job::Certification`;
+/// no certificate is handed out until the last batch, see
+/// `certification::certificate`.
+pub fn start_certification() {
+    CERTIFY_CURSOR.with_borrow_mut(|cursor| *cursor = None);
+    certification::begin_rebuild();
+    scheduler::schedule(Job::Certification, Duration::ZERO);
+}
+
+/// Certify the next batch of at most `limit` stored messages
+///
+/// Messages written meanwhile are certified when stored, and the batches
+/// read the stores as they are, so the rebuilt tree matches the stores.
+///
+/// # Returns
+/// Whether messages are left to certify
+pub fn certify_batch(limit: usize) -> bool {
+    if !certification::rebuilding() {
+        return false;
+    }
+
+    let cursor = CERTIFY_CURSOR.with_borrow(|cursor| cursor.clone());
+    let batch = next_backfill_batch(cursor.as_ref(), limit);
+    let last = batch.last().map(|(tier, key, _)| (*tier, key.clone()));
+    certification::certify_messages(batch.into_iter().map(|(_, _, entry)| entry.into_pair()));
+
+    match last {
+        Some(last) => {
+            CERTIFY_CURSOR.with_borrow_mut(|cursor| *cursor = Some(last));
+            true
+        }
+        None => {
+            CERTIFY_CURSOR.with_borrow_mut(|cursor| *cursor = None);
+            certification::finish_rebuild();
+            false
+        }
+    }
+}
+
+/// Version 0 -> 1: split per-type collections into per-message entries
+///
+/// A collection with more than `limit` messages is written back with the
+/// messages left to split.
+///
+/// # Returns
+/// Number of messages split, 0 once no collection is left
+fn migrate_legacy_collections(limit: usize) -> usize {
+    // Archive first so that a message present in both stores ends up live
+    let legacy_stores = [
+        (&LEGACY_ARCHIVE_STORE, StoreTier::Archive),
+        (&LEGACY_MSG_STORE, StoreTier::Live),
+    ];
+
+    let mut migrated = 0;
+    for (legacy_store, tier) in legacy_stores {
+        while migrated < limit {
+            let Some((key, collection)) = legacy_store.with_borrow_mut(|store| store.pop_first())
+            else {
+                break;
+            };
+
+            let mut messages = collection.extract_content().into_iter();
+            for (message, principal) in messages.by_ref().take(limit - migrated) {
+                let entry = MsgEntry {
+                    message,
+                    principal,
+                    schema_version: INITIAL_SCHEMA_VERSION,
+                };
+                store::insert_entry(tier, entry);
+                migrated += 1;
+            }
+
+            let left: BTreeSet<(Message, Principal)> = messages.collect();
+            ic_cdk::println!(
+                "migrate_legacy_collections: migrated messages of key {} ({:?}), {} left",
+                key, tier, left.len()
+            );
+            if !left.is_empty() {
+                legacy_store.with_borrow_mut(|store| store.insert(key, MsgCollection(left)));
+            }
+        }
+    }
+    migrated
+}
+
+/// Version 6 -> 7: register built-in payload types added since the install
+fn register_builtin_types() {
+    state::with_mut(|processor| {
+        for (name, builtin) in builtin_payload_types() {
+            processor.payload_types.entry(name).or_insert(builtin);
+        }
+    });
+}
+
+/// Version 4 -> 5: make the built-in searchable types searchable
+fn enable_builtin_search() {
+    state::with_mut(|processor| {
+        for (name, builtin) in builtin_payload_types() {
+            if let Some(config) = processor.payload_types.get_mut(&name) {
+                config.searchable |= builtin.searchable;
+            }
+        }
+    });
+}
+
+/// Version 2 -> 3: give built-in payload types their default age rules
+///
+/// Types whose retention already has age rules keep their configuration.
+fn apply_builtin_retention() {
+    state::with_mut(|processor| {
+        for (name, builtin) in builtin_payload_types() {
+            if let Some(config) = processor.payload_types.get_mut(&name) {
+                if !config.retention.has_age_rules() {
+                    config.retention = RetentionPolicy {
+                        archive_threshold: config.retention.archive_threshold,
+                        ..builtin.retention
+                    };
+                }
+            }
+        }
+    });
+}
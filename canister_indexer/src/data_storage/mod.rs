@@ -0,0 +1,1301 @@
+use candid::{CandidType, Decode, Encode, Principal};
+
+use canister_types::message::{Message, MessageSource, MessageType};
+use ic_cdk_timers::TimerId;
+use ic_stable_structures::{
+    memory_manager::{MemoryId, MemoryManager, VirtualMemory},
+    storable::Bound,
+    DefaultMemoryImpl, StableBTreeMap, StableCell, Storable,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+    thread::LocalKey,
+    time::Duration,
+};
+use crate::{
+    analytics::{
+        ActivityBucket, CounterGranularity, CounterSubject, CounterWindow, RankedSubject,
+        TrendingQuery,
+    },
+    certification,
+    data_transfer::ImportProgress,
+    discussion::{self, Engagement, ThreadNode},
+    http_gateway,
+    indexer_error::IndexerError,
+    jobs::{Job, JobRunStatus, JobState},
+    moderation::{
+        ModerationAction, ModerationLogEntry, ModerationQueueItem, ModerationQueuePage,
+        ModerationRecord, MsgReport,
+    },
+    msg_key::{MsgIdKey, MsgKey},
+    namespace::{self, NamespaceConfig, NAMESPACE_SEPARATOR},
+    pagination::MsgCursor,
+    payload_registry::{
+        builtin_payload_types, builtin_schema_version, PayloadTypeConfig, RetentionPolicy,
+    },
+    payload_schema::{self, SchemaVersionStats, INITIAL_SCHEMA_VERSION},
+    rate_limit::{self, RateLimitConfig, RateLimitUsage, NANOS_PER_DAY},
+    signatures::{MsgVerification, TrustedKeys},
+    storable::cbor_storable,
+    text_search,
+    ARCHIVE_MSG_MIGRATION_SIZE, CERTIFY_BATCH_SIZE, CLEANUP_DELAY_SECS, DELIVERY_RETRY_BASE_SECS,
+    MAX_DELIVERY_FAILURES, MAX_FOLLOWING, MAX_SUBSCRIPTIONS,
+};
+
+type MemSpace = VirtualMemory<DefaultMemoryImpl>;
+
+/// Rights granted to a registered publisher
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug, PartialEq, Eq)]
+pub struct PublisherRights {
+    /// Payload types the publisher may submit
+    pub payload_types: BTreeSet<String>,
+    /// Message types the publisher may submit
+    pub msg_types: BTreeSet<MessageType>,
+}
+
+impl PublisherRights {
+    /// Check whether these rights cover the given payload and message type
+    pub fn check(&self, payload_type: &str, msg_type: &MessageType) -> Result<(), IndexerError> {
+        if !self.payload_types.contains(payload_type) {
+            return Err(IndexerError::PayloadTypeNotAllowed(payload_type.to_string()));
+        }
+        if !self.msg_types.contains(msg_type) {
+            return Err(IndexerError::MessageTypeNotAllowed(msg_type.clone()));
+        }
+        Ok(())
+    }
+}
+
+/// Data processor configuration and state
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug)]
+pub struct DataProcessor {
+    pub identifier: String,
+    pub controller: Principal,
+    pub participant_count: u32,
+    /// Principals allowed to manage any message regardless of its creator
+    #[serde(default)]
+    pub admins: BTreeSet<Principal>,
+    /// Registered publishers and the rights granted to each of them
+    #[serde(default)]
+    pub publishers: BTreeMap<Principal, PublisherRights>,
+    /// Layout version of the message stores, see `CURRENT_STORAGE_VERSION`
+    #[serde(default)]
+    pub storage_version: u32,
+    /// Payload types accepted by the indexer, keyed by name
+    #[serde(default = "builtin_payload_types")]
+    pub payload_types: BTreeMap<String, PayloadTypeConfig>,
+    /// Principals allowed to review reports and hide or delete messages
+    #[serde(default)]
+    pub moderators: BTreeSet<Principal>,
+    /// Rate limits and quotas applied to the writes of publishers
+    #[serde(default)]
+    pub rate_limits: RateLimitConfig,
+    /// Progress of the import of another indexer's export, if one was started
+    #[serde(default)]
+    pub import: Option<ImportProgress>,
+    /// User canister the trusted keys of message authors are fetched from
+    #[serde(default)]
+    pub user_canister: Option<Principal>,
+    /// Registered namespaces, keyed by namespace key
+    #[serde(default)]
+    pub namespaces: BTreeMap<String, NamespaceConfig>,
+    /// Canisters allowed to subscribe to changes of the stored messages
+    #[serde(default)]
+    pub subscriber_canisters: BTreeSet<Principal>,
+    /// Progress of the migration of the message stores, if one is running
+    #[serde(default)]
+    pub migration: Option<StorageMigration>,
+}
+
+impl Default for DataProcessor {
+    fn default() -> Self {
+        Self {
+            identifier: String::from("default_processor"),
+            controller: Principal::anonymous(),
+            participant_count: 0,
+            admins: BTreeSet::new(),
+            publishers: BTreeMap::new(),
+            storage_version: 0,
+            payload_types: builtin_payload_types(),
+            moderators: BTreeSet::new(),
+            rate_limits: RateLimitConfig::default(),
+            import: None,
+            user_canister: None,
+            namespaces: BTreeMap::new(),
+            subscriber_canisters: BTreeSet::new(),
+            migration: None,
+        }
+    }
+}
+
+impl DataProcessor {
+    /// Checks if the caller is the configured controller or a canister controller
+    ///
+    /// The anonymous principal, which is the controller until an owner is
+    /// set, is never a controller.
+    pub fn is_controller(&self, caller: &Principal) -> bool {
+        (caller == &self.controller && caller != &Principal::anonymous())
+            || ic_cdk::api::is_controller(caller)
+    }
+
+    /// Checks if the caller is the configured controller or a canister controller
+    pub fn controller_permission(&self, caller: Principal) -> Result<(), String> {
+        if self.is_controller(&caller) {
+            Ok(())
+        } else {
+            Err("Unauthorized".to_string())
+        }
+    }
+
+    /// Checks if the caller is a controller or a registered admin
+    pub fn is_admin(&self, caller: &Principal) -> bool {
+        self.is_controller(caller) || self.admins.contains(caller)
+    }
+
+    /// Checks if the caller is a registered moderator or an admin
+    pub fn is_moderator(&self, caller: &Principal) -> bool {
+        self.is_admin(caller) || self.moderators.contains(caller)
+    }
+
+    /// Checks if the caller may manage a subscription: an allowed subscriber canister or an admin
+    pub fn is_subscriber(&self, caller: &Principal) -> bool {
+        self.subscriber_canisters.contains(caller) || self.is_admin(caller)
+    }
+
+    /// This processor with the controller, admins, publishers, moderators and
+    /// subscriber canisters of another one
+    pub fn with_access_control_of(self, other: &DataProcessor) -> Self {
+        Self {
+            controller: other.controller,
+            admins: other.admins.clone(),
+            publishers: other.publishers.clone(),
+            moderators: other.moderators.clone(),
+            subscriber_canisters: other.subscriber_canisters.clone(),
+            ..self
+        }
+    }
+
+    /// Replace the global rate limits, checking that they only name known payload types
+    pub fn set_rate_limits(&mut self, config: RateLimitConfig) -> Result<(), String> {
+        config.validate()?;
+        if let Some(payload_type) = config
+            .per_payload_type
+            .keys()
+            .find(|payload_type| self.payload_config(payload_type).is_none())
+        {
+            return Err(format!("Unknown payload type: {}", payload_type));
+        }
+        self.rate_limits = config;
+        Ok(())
+    }
+
+    /// Checks if the message stores are being migrated to the current storage layout
+    pub fn migration_in_progress(&self) -> bool {
+        self.migration.is_some()
+    }
+
+    /// Checks if an import was started and has chunks left to import
+    pub fn import_in_progress(&self) -> bool {
+        self.import.as_ref().is_some_and(|progress| !progress.is_completed())
+    }
+
+    /// Registry entry of a payload type, namespaced or not
+    ///
+    /// A namespaced payload type uses the entry of its unqualified type with
+    /// the retention policy of the namespace, and is unknown while the
+    /// namespace is not registered.
+    pub fn payload_config(&self, payload_type: &str) -> Option<PayloadTypeConfig> {
+        let (namespace, base_type) = namespace::split(payload_type);
+        let mut config = self.payload_types.get(base_type)?.clone();
+        if let Some(namespace) = namespace {
+            let namespace = self.namespaces.get(namespace)?;
+            if let Some(retention) = namespace.retention.get(base_type) {
+                config.retention = retention.clone();
+            }
+            config.name = payload_type.to_string();
+        }
+        Some(config)
+    }
+
+    /// Live message count above which a payload type is archived, if any
+    pub fn archive_threshold(&self, payload_type: &str) -> Option<u64> {
+        self.payload_config(payload_type)
+            .and_then(|config| config.retention.archive_threshold)
+    }
+
+    /// Namespace whose own rate limits apply to a payload type, None for the global limits
+    pub fn rate_limit_scope<'a>(&self, payload_type: &'a str) -> Option<&'a str> {
+        namespace::namespace_of(payload_type).filter(|namespace| {
+            self.namespaces
+                .get(*namespace)
+                .is_some_and(|config| config.rate_limits.is_some())
+        })
+    }
+
+    /// Rate limits of a scope returned by `rate_limit_scope`
+    pub fn rate_limit_config(&self, scope: Option<&str>) -> &RateLimitConfig {
+        scope
+            .and_then(|namespace| self.namespaces.get(namespace))
+            .and_then(|config| config.rate_limits.as_ref())
+            .unwrap_or(&self.rate_limits)
+    }
+
+    /// Checks if the caller may submit a message of the given payload and message type
+    pub fn authorize_publisher(
+        &self,
+        caller: Principal,
+        payload_type: &str,
+        msg_type: &MessageType,
+    ) -> Result<(), IndexerError> {
+        if self.is_admin(&caller) {
+            return Ok(());
+        }
+        self.publishers
+            .get(&caller)
+            .ok_or(IndexerError::PublisherNotRegistered(caller))?
+            .check(payload_type, msg_type)
+    }
+}
+
+cbor_storable!(DataProcessor);
+
+/// Progress of a storage migration, run in batches by `Job::StorageMigration`
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug, PartialEq, Eq)]
+pub struct StorageMigration {
+    /// Storage version the message stores are migrated from
+    pub from_version: u32,
+    /// Tier and key of the last message whose indexes were backfilled,
+    /// None until the first backfill batch
+    pub cursor: Option<(StoreTier, MsgKey)>,
+}
+
+/// Legacy collection of messages with associated principals
+///
+/// Storage layout version 0 kept one of these per payload type; it is only
+/// read while migrating to the per-message layout.
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug)]
+pub struct MsgCollection(pub BTreeSet<(Message, Principal)>);
+
+impl Storable for MsgCollection {
+    const BOUND: Bound = Bound::Unbounded;
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(Encode!(self).unwrap())
+    }
+}
+
+impl MsgCollection {
+    /// Extract the underlying message collection
+    pub fn extract_content(self) -> BTreeSet<(Message, Principal)> {
+        self.0
+    }
+}
+
+/// A stored message together with the principal that created it
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug)]
+pub struct MsgEntry {
+    pub message: Message,
+    pub principal: Principal,
+    /// Schema version of the payload type the payload is encoded with
+    #[serde(default = "initial_schema_version")]
+    pub schema_version: u32,
+}
+
+fn initial_schema_version() -> u32 {
+    INITIAL_SCHEMA_VERSION
+}
+
+cbor_storable!(MsgEntry);
+
+impl MsgEntry {
+    /// Split the entry into the message and its creator
+    pub fn into_pair(self) -> (Message, Principal) {
+        (self.message, self.principal)
+    }
+}
+
+/// Store holding a message: the live (hot) store or the local archive
+#[derive(CandidType, Clone, Copy, Deserialize, Serialize, Debug, PartialEq, Eq)]
+pub enum StoreTier {
+    Live,
+    Archive,
+}
+
+cbor_storable!(StoreTier);
+
+/// Where a message identified by `MsgIdKey` can be found
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug)]
+pub struct MsgLocation {
+    pub timestamp: u64,
+    pub tier: StoreTier,
+}
+
+cbor_storable!(MsgLocation);
+
+impl MsgLocation {
+    /// Primary key of the located message
+    pub fn key(&self, id_key: &MsgIdKey) -> MsgKey {
+        MsgKey {
+            payload_type: id_key.payload_type.clone(),
+            timestamp: self.timestamp,
+            msg_id: id_key.msg_id.clone(),
+        }
+    }
+}
+
+/// Secondary key listing the messages of a principal by payload type and time
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PrincipalIndexKey {
+    pub principal: Principal,
+    pub payload_type: String,
+    pub timestamp: u64,
+    pub msg_id: String,
+}
+
+cbor_storable!(PrincipalIndexKey);
+
+impl PrincipalIndexKey {
+    /// Build the index key of a stored message
+    pub fn of(principal: Principal, key: &MsgKey) -> Self {
+        Self {
+            principal,
+            payload_type: key.payload_type.clone(),
+            timestamp: key.timestamp,
+            msg_id: key.msg_id.clone(),
+        }
+    }
+
+    /// Smallest possible key of a principal and payload type at the given timestamp
+    pub fn lower_bound(principal: Principal, payload_type: &str, timestamp: u64) -> Self {
+        Self {
+            principal,
+            payload_type: payload_type.to_string(),
+            timestamp,
+            msg_id: String::new(),
+        }
+    }
+
+    /// Exclusive upper bound of all keys of a principal and payload type
+    pub fn type_upper_bound(principal: Principal, payload_type: &str) -> Self {
+        Self::lower_bound(principal, &format!("{}\0", payload_type), 0)
+    }
+
+    /// Primary key of the indexed message
+    pub fn msg_key(&self) -> MsgKey {
+        MsgKey {
+            payload_type: self.payload_type.clone(),
+            timestamp: self.timestamp,
+            msg_id: self.msg_id.clone(),
+        }
+    }
+}
+
+/// Secondary key listing the messages about a resource by time
+///
+/// Every message with a `msg_resource` is indexed twice: once under its
+/// `resource_id` and once with `resource_id` set to None, which lists all
+/// resources of a type within a canister.
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ResourceIndexKey {
+    /// Namespace key of the message, empty for global messages
+    ///
+    /// Keys written before namespaces existed decode as global ones.
+    #[serde(default)]
+    pub namespace: String,
+    pub canister_id: Principal,
+    pub resource_type: String,
+    pub resource_id: Option<u64>,
+    pub timestamp: u64,
+    pub msg_id: String,
+    pub payload_type: String,
+}
+
+cbor_storable!(ResourceIndexKey);
+
+impl ResourceIndexKey {
+    /// Build the index keys of a stored message, empty if it has no resource
+    pub fn of(message: &Message) -> Vec<Self> {
+        let Some(resource) = &message.msg_resource else {
+            return Vec::new();
+        };
+
+        [Some(resource.resource_id), None]
+            .into_iter()
+            .map(|resource_id| Self {
+                namespace: namespace::namespace_of(&message.payload_type)
+                    .unwrap_or_default()
+                    .to_string(),
+                canister_id: resource.canister_id,
+                resource_type: resource.resource_type.clone(),
+                resource_id,
+                timestamp: message.timestamp,
+                msg_id: message.msg_id.clone(),
+                payload_type: message.payload_type.clone(),
+            })
+            .collect()
+    }
+
+    /// Smallest possible key of a resource in a namespace at the given timestamp
+    pub fn lower_bound(
+        namespace: Option<&str>,
+        canister_id: Principal,
+        resource_type: &str,
+        resource_id: Option<u64>,
+        timestamp: u64,
+    ) -> Self {
+        Self {
+            namespace: namespace.unwrap_or_default().to_string(),
+            canister_id,
+            resource_type: resource_type.to_string(),
+            resource_id,
+            timestamp,
+            msg_id: String::new(),
+            payload_type: String::new(),
+        }
+    }
+
+    /// Primary key of the indexed message
+    pub fn msg_key(&self) -> MsgKey {
+        MsgKey {
+            payload_type: self.payload_type.clone(),
+            timestamp: self.timestamp,
+            msg_id: self.msg_id.clone(),
+        }
+    }
+}
+
+/// Inverted index key of a search term occurring in a message
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SearchIndexKey {
+    pub term: String,
+    /// Namespace key of the message, empty for global messages
+    ///
+    /// Keys written before namespaces existed decode as global ones, which
+    /// keeps their order.
+    #[serde(default)]
+    pub namespace: String,
+    pub payload_type: String,
+    pub msg_id: String,
+}
+
+cbor_storable!(SearchIndexKey);
+
+impl SearchIndexKey {
+    /// Key of a term occurring in a message
+    pub fn of(term: String, message: &Message) -> Self {
+        Self {
+            term,
+            namespace: namespace::namespace_of(&message.payload_type)
+                .unwrap_or_default()
+                .to_string(),
+            payload_type: message.payload_type.clone(),
+            msg_id: message.msg_id.clone(),
+        }
+    }
+
+    /// Smallest possible key of a term in a namespace, optionally within a payload type
+    pub fn lower_bound(term: &str, namespace: Option<&str>, payload_type: Option<&str>) -> Self {
+        Self {
+            term: term.to_string(),
+            namespace: namespace.unwrap_or_default().to_string(),
+            payload_type: payload_type.unwrap_or_default().to_string(),
+            msg_id: String::new(),
+        }
+    }
+
+    /// Exclusive upper bound of all keys of a term in a namespace, optionally within a payload type
+    pub fn upper_bound(term: &str, namespace: Option<&str>, payload_type: Option<&str>) -> Self {
+        match payload_type {
+            Some(payload_type) => {
+                Self::lower_bound(term, namespace, Some(&format!("{}\0", payload_type)))
+            }
+            None => Self {
+                namespace: format!("{}\0", namespace.unwrap_or_default()),
+                ..Self::lower_bound(term, None, None)
+            },
+        }
+    }
+}
+
+/// Occurrences of a search term in a message
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug)]
+pub struct SearchPosting {
+    pub frequency: u32,
+    /// Timestamp of the message, used to rank equally relevant matches
+    pub timestamp: u64,
+}
+
+cbor_storable!(SearchPosting);
+
+/// Key of a message counter bucket
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct CounterKey {
+    pub payload_type: String,
+    pub granularity: CounterGranularity,
+    pub bucket_start: u64,
+    pub subject: CounterSubject,
+}
+
+cbor_storable!(CounterKey);
+
+impl CounterKey {
+    /// Smallest possible key of a bucket
+    pub fn lower_bound(payload_type: &str, granularity: CounterGranularity, bucket_start: u64) -> Self {
+        Self {
+            payload_type: payload_type.to_string(),
+            granularity,
+            bucket_start,
+            subject: CounterSubject::Total,
+        }
+    }
+}
+
+/// Key of a follow relation, listing the principals related to `owner`
+///
+/// Stored twice: in `FOLLOWING` with the follower as owner and in
+/// `FOLLOWERS` with the followed principal as owner.
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FollowKey {
+    pub owner: Principal,
+    pub other: Principal,
+}
+
+cbor_storable!(FollowKey);
+
+/// Follower and following counts of a principal
+#[derive(CandidType, Clone, Default, Deserialize, Serialize, Debug)]
+pub struct FollowCounts {
+    pub followers: u64,
+    pub following: u64,
+}
+
+cbor_storable!(FollowCounts);
+
+/// One page of a follower or following list
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug)]
+pub struct FollowPage {
+    /// Related principals with the time the relation was created, in principal order
+    pub principals: Vec<(Principal, u64)>,
+    /// Cursor to pass back to fetch the next page, if any
+    pub next_cursor: Option<String>,
+}
+
+/// Secondary key listing the comments on a message by time
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct CommentKey {
+    pub parent_payload_type: String,
+    pub parent_msg_id: String,
+    pub timestamp: u64,
+    pub msg_id: String,
+}
+
+cbor_storable!(CommentKey);
+
+impl CommentKey {
+    /// Smallest possible key of the comments on a message
+    pub fn lower_bound(parent_payload_type: &str, parent_msg_id: &str) -> Self {
+        Self {
+            parent_payload_type: parent_payload_type.to_string(),
+            parent_msg_id: parent_msg_id.to_string(),
+            timestamp: 0,
+            msg_id: String::new(),
+        }
+    }
+
+    /// Exclusive upper bound of the keys of the comments on a message
+    pub fn upper_bound(parent_payload_type: &str, parent_msg_id: &str) -> Self {
+        Self::lower_bound(parent_payload_type, &format!("{}\0", parent_msg_id))
+    }
+}
+
+/// Key of the reaction of a principal to a message
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ReactionKey {
+    pub payload_type: String,
+    pub msg_id: String,
+    pub principal: Principal,
+}
+
+cbor_storable!(ReactionKey);
+
+cbor_storable!(Engagement);
+
+/// Key of the report of a principal against a message
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ReportKey {
+    pub payload_type: String,
+    pub msg_id: String,
+    pub reporter: Principal,
+}
+
+cbor_storable!(ReportKey);
+
+/// A stored report, see `ReportKey`
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug)]
+pub struct ReportEntry {
+    pub reason: String,
+    pub timestamp: u64,
+}
+
+cbor_storable!(ReportEntry);
+
+cbor_storable!(ModerationRecord);
+
+/// Key of a reported message in the moderation queue, oldest open report first
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ModerationQueueKey {
+    pub queued_at: u64,
+    pub payload_type: String,
+    pub msg_id: String,
+}
+
+cbor_storable!(ModerationQueueKey);
+
+/// Secondary key listing the messages of a payload type by schema version and time
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SchemaIndexKey {
+    pub payload_type: String,
+    pub schema_version: u32,
+    pub timestamp: u64,
+    pub msg_id: String,
+}
+
+cbor_storable!(SchemaIndexKey);
+
+impl SchemaIndexKey {
+    /// Build the index key of a stored message
+    pub fn of(key: &MsgKey, schema_version: u32) -> Self {
+        Self {
+            payload_type: key.payload_type.clone(),
+            schema_version,
+            timestamp: key.timestamp,
+            msg_id: key.msg_id.clone(),
+        }
+    }
+
+    /// Smallest possible key of a payload type and schema version
+    pub fn lower_bound(payload_type: &str, schema_version: u32) -> Self {
+        Self {
+            payload_type: payload_type.to_string(),
+            schema_version,
+            timestamp: 0,
+            msg_id: String::new(),
+        }
+    }
+
+    /// Key of the message in the store of its tier
+    pub fn msg_key(&self) -> MsgKey {
+        MsgKey {
+            payload_type: self.payload_type.clone(),
+            timestamp: self.timestamp,
+            msg_id: self.msg_id.clone(),
+        }
+    }
+}
+
+/// Key of the message count of a payload type on one schema version
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SchemaCountKey {
+    pub payload_type: String,
+    pub schema_version: u32,
+}
+
+cbor_storable!(SchemaCountKey);
+
+/// Message counters of a single payload type
+#[derive(CandidType, Clone, Default, Deserialize, Serialize, Debug)]
+pub struct TypeStats {
+    pub live: u64,
+    pub archived: u64,
+}
+
+cbor_storable!(TypeStats);
+
+impl TypeStats {
+    fn counter_mut(&mut self, tier: StoreTier) -> &mut u64 {
+        match tier {
+            StoreTier::Live => &mut self.live,
+            StoreTier::Archive => &mut self.archived,
+        }
+    }
+}
+
+/// Timestamp range and message count of one payload type inside an archive canister
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug)]
+pub struct ArchiveRange {
+    pub from_timestamp: u64,
+    pub to_timestamp: u64,
+    pub message_count: u64,
+    /// Oldest schema version the archived messages may still be encoded with
+    #[serde(default = "initial_schema_version")]
+    pub schema_version: u32,
+    /// Position of the re-encoding to the current schema version in progress,
+    /// None before the first batch
+    #[serde(default)]
+    pub schema_cursor: Option<String>,
+}
+
+/// Registry entry of a child archive canister created by the indexer
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug)]
+pub struct ArchiveCanisterInfo {
+    pub canister_id: Principal,
+    pub created_at: u64,
+    pub message_count: u64,
+    /// Covered timestamp range per payload type
+    pub ranges: BTreeMap<String, ArchiveRange>,
+    /// Whether the archive wasm module is installed; messages are only handed
+    /// over to installed archives
+    pub installed: bool,
+}
+
+cbor_storable!(ArchiveCanisterInfo);
+
+impl ArchiveCanisterInfo {
+    /// Record messages of a payload type handed over to this archive
+    ///
+    /// Messages on an older schema version than the range's restart its re-encoding.
+    pub fn record(
+        &mut self,
+        payload_type: &str,
+        from_timestamp: u64,
+        to_timestamp: u64,
+        count: u64,
+        schema_version: u32,
+    ) {
+        self.message_count += count;
+        self.ranges
+            .entry(payload_type.to_string())
+            .and_modify(|range| {
+                range.from_timestamp = range.from_timestamp.min(from_timestamp);
+                range.to_timestamp = range.to_timestamp.max(to_timestamp);
+                range.message_count += count;
+                if schema_version < range.schema_version {
+                    range.schema_version = schema_version;
+                    range.schema_cursor = None;
+                }
+            })
+            .or_insert(ArchiveRange {
+                from_timestamp,
+                to_timestamp,
+                message_count: count,
+                schema_version,
+                schema_cursor: None,
+            });
+    }
+}
+
+/// A message handed over to an archive canister
+///
+/// The message is kept without its payload, so that later changes of the
+/// message can be checked here and forwarded to the archive.
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug)]
+pub struct SpilledMsg {
+    pub canister_id: Principal,
+    pub creator: Principal,
+    pub message: Message,
+}
+
+cbor_storable!(SpilledMsg);
+
+/// Change of a spilled message waiting to be sent to its archive canister
+#[derive(CandidType, Clone, Copy, Deserialize, Serialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ArchiveOp {
+    Delete,
+    SetHidden(bool),
+}
+
+cbor_storable!(ArchiveOp);
+
+/// Key of a pending `ArchiveOp`, grouping the changes per archive canister and payload type
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ArchiveOpKey {
+    pub canister_id: Principal,
+    pub payload_type: String,
+    pub msg_id: String,
+}
+
+cbor_storable!(ArchiveOpKey);
+
+/// Effect of an ingested message on the stored data
+#[derive(CandidType, Clone, Copy, Deserialize, Serialize, Debug, PartialEq, Eq)]
+pub enum MsgOutcome {
+    /// A new message was stored
+    Created,
+    /// An existing message was rewritten
+    Updated,
+    /// An existing message was deleted
+    Deleted,
+    /// The message was already applied, nothing changed
+    Unchanged,
+}
+
+/// Result of successfully processing one message
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug)]
+pub struct MsgReceipt {
+    pub msg_id: String,
+    pub outcome: MsgOutcome,
+}
+
+/// A change to the stored messages, as delivered to subscribers
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug)]
+pub struct MsgEvent {
+    /// Position of the event in the event log
+    pub seq: u64,
+    /// Created, Updated or Deleted
+    pub outcome: MsgOutcome,
+    /// The new version of the message, or the removed one for Deleted
+    pub message: Message,
+    /// The principal that created the message
+    pub principal: Principal,
+    /// Time the change was applied
+    pub recorded_at: u64,
+}
+
+cbor_storable!(MsgEvent);
+
+/// Selects the events delivered to a subscriber
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug)]
+pub struct SubscriptionFilter {
+    /// Payload types to subscribe to
+    pub payload_types: BTreeSet<String>,
+    /// Only deliver messages created by this principal
+    pub principal: Option<Principal>,
+    /// Only deliver messages about this resource
+    pub resource: Option<MessageSource>,
+}
+
+impl SubscriptionFilter {
+    /// Whether an event passes this filter
+    pub fn matches(&self, event: &MsgEvent) -> bool {
+        self.payload_types.contains(&event.message.payload_type)
+            && self.principal.map_or(true, |principal| principal == event.principal)
+            && self
+                .resource
+                .as_ref()
+                .map_or(true, |resource| event.message.msg_resource.as_ref() == Some(resource))
+    }
+}
+
+/// Delivery state of a subscription
+#[derive(CandidType, Clone, Copy, Deserialize, Serialize, Debug, PartialEq, Eq)]
+pub enum SubscriptionStatus {
+    Active,
+    /// Delivery failed `MAX_DELIVERY_FAILURES` times in a row and was stopped
+    DeadLettered,
+}
+
+/// A subscriber canister and its delivery progress
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug)]
+pub struct Subscription {
+    pub subscriber: Principal,
+    pub filter: SubscriptionFilter,
+    pub created_at: u64,
+    pub status: SubscriptionStatus,
+    /// Sequence number of the next event to deliver
+    pub cursor: u64,
+    /// Number of events delivered so far
+    pub delivered: u64,
+    /// Consecutive failed deliveries
+    pub failures: u32,
+    /// Time before which no delivery is attempted
+    pub next_attempt_at: u64,
+    pub last_error: Option<String>,
+}
+
+cbor_storable!(Subscription);
+
+/// Key of the rate limit usage of a caller, per payload type or across all of them
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct UsageKey {
+    pub principal: Principal,
+    /// None for the caller bucket and the daily counts, `<namespace>/` for
+    /// those of a namespace with its own limits
+    pub payload_type: Option<String>,
+}
+
+cbor_storable!(UsageKey);
+
+/// Rate limit usage of a caller
+///
+/// The daily counts are only kept under the key without payload type.
+#[derive(CandidType, Clone, Default, Deserialize, Serialize, Debug)]
+pub struct CallerUsage {
+    /// Time at which the token bucket is full again
+    pub full_at: u64,
+    /// UTC day the counts below belong to, in days since the epoch
+    pub day: u64,
+    pub messages: u64,
+    pub payload_bytes: u64,
+}
+
+cbor_storable!(CallerUsage);
+
+impl CallerUsage {
+    /// Counts of the given day, starting from zero on a new day
+    fn on_day(mut self, day: u64) -> Self {
+        if self.day != day {
+            self.day = day;
+            self.messages = 0;
+            self.payload_bytes = 0;
+        }
+        self
+    }
+}
+
+cbor_storable!(Job);
+
+cbor_storable!(JobState);
+
+cbor_storable!(TrustedKeys);
+
+cbor_storable!(MsgVerification);
+
+/// Current layout of the message stores, see `migration`
+pub const CURRENT_STORAGE_VERSION: u32 = 8;
+
+// Memory management constants
+const PROCESSOR_MEM_ID: MemoryId = MemoryId::new(0);
+const LEGACY_MSG_MEM_ID: MemoryId = MemoryId::new(1);
+const LEGACY_ARCHIVE_MEM_ID: MemoryId = MemoryId::new(2);
+const MSG_ENTRY_MEM_ID: MemoryId = MemoryId::new(3);
+const ARCHIVE_ENTRY_MEM_ID: MemoryId = MemoryId::new(4);
+const MSG_ID_INDEX_MEM_ID: MemoryId = MemoryId::new(5);
+const TYPE_STATS_MEM_ID: MemoryId = MemoryId::new(6);
+const PRINCIPAL_INDEX_MEM_ID: MemoryId = MemoryId::new(7);
+const ARCHIVE_CANISTER_MEM_ID: MemoryId = MemoryId::new(8);
+const ARCHIVE_WASM_MEM_ID: MemoryId = MemoryId::new(9);
+const SPILLED_MSG_MEM_ID: MemoryId = MemoryId::new(10);
+const ARCHIVE_OP_MEM_ID: MemoryId = MemoryId::new(11);
+const SPILLED_BY_TIME_MEM_ID: MemoryId = MemoryId::new(12);
+const RESOURCE_INDEX_MEM_ID: MemoryId = MemoryId::new(13);
+const EVENT_LOG_MEM_ID: MemoryId = MemoryId::new(14);
+const SUBSCRIPTION_MEM_ID: MemoryId = MemoryId::new(15);
+const SEARCH_INDEX_MEM_ID: MemoryId = MemoryId::new(16);
+const COUNTER_MEM_ID: MemoryId = MemoryId::new(17);
+const FOLLOWING_MEM_ID: MemoryId = MemoryId::new(18);
+const FOLLOWERS_MEM_ID: MemoryId = MemoryId::new(19);
+const FOLLOW_COUNT_MEM_ID: MemoryId = MemoryId::new(20);
+const COMMENT_INDEX_MEM_ID: MemoryId = MemoryId::new(21);
+const REACTION_MEM_ID: MemoryId = MemoryId::new(22);
+const ENGAGEMENT_MEM_ID: MemoryId = MemoryId::new(23);
+const THREAD_DELETION_MEM_ID: MemoryId = MemoryId::new(24);
+const REPORT_MEM_ID: MemoryId = MemoryId::new(25);
+const MODERATION_MEM_ID: MemoryId = MemoryId::new(26);
+const MODERATION_QUEUE_MEM_ID: MemoryId = MemoryId::new(27);
+const HIDDEN_MSG_MEM_ID: MemoryId = MemoryId::new(28);
+const RATE_USAGE_MEM_ID: MemoryId = MemoryId::new(29);
+const JOB_MEM_ID: MemoryId = MemoryId::new(30);
+const TRUSTED_KEY_MEM_ID: MemoryId = MemoryId::new(31);
+const MSG_VERIFICATION_MEM_ID: MemoryId = MemoryId::new(32);
+const SCHEMA_INDEX_MEM_ID: MemoryId = MemoryId::new(33);
+const SCHEMA_COUNT_MEM_ID: MemoryId = MemoryId::new(34);
+
+type MsgEntryMap = StableBTreeMap<MsgKey, MsgEntry, MemSpace>;
+
+// Thread-local storage for canister state
+thread_local! {
+    static PROCESSOR: RefCell<DataProcessor> = RefCell::new(DataProcessor::default());
+
+    static MEM_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+
+    static PROCESSOR_STORE: RefCell<StableCell<DataProcessor, MemSpace>> = RefCell::new(
+        StableCell::init(
+            MEM_MANAGER.with_borrow(|m| m.get(PROCESSOR_MEM_ID)),
+            DataProcessor::default()
+        ).expect("failed to init PROCESSOR_STORE store")
+    );
+
+    // Layout version 0: one Candid-encoded collection per payload type
+    static LEGACY_MSG_STORE: RefCell<StableBTreeMap<String, MsgCollection, MemSpace>> = RefCell::new(
+        StableBTreeMap::init(
+            MEM_MANAGER.with_borrow(|m| m.get(LEGACY_MSG_MEM_ID)),
+        )
+    );
+
+    static LEGACY_ARCHIVE_STORE: RefCell<StableBTreeMap<String, MsgCollection, MemSpace>> = RefCell::new(
+        StableBTreeMap::init(
+            MEM_MANAGER.with_borrow(|m| m.get(LEGACY_ARCHIVE_MEM_ID)),
+        )
+    );
+
+    // Layout version 1: one entry per message
+    static MSG_STORE: RefCell<MsgEntryMap> = RefCell::new(
+        StableBTreeMap::init(
+            MEM_MANAGER.with_borrow(|m| m.get(MSG_ENTRY_MEM_ID)),
+        )
+    );
+
+    static ARCHIVE_STORE: RefCell<MsgEntryMap> = RefCell::new(
+        StableBTreeMap::init(
+            MEM_MANAGER.with_borrow(|m| m.get(ARCHIVE_ENTRY_MEM_ID)),
+        )
+    );
+
+    static MSG_ID_INDEX: RefCell<StableBTreeMap<MsgIdKey, MsgLocation, MemSpace>> = RefCell::new(
+        StableBTreeMap::init(
+            MEM_MANAGER.with_borrow(|m| m.get(MSG_ID_INDEX_MEM_ID)),
+        )
+    );
+
+    static TYPE_STATS: RefCell<StableBTreeMap<String, TypeStats, MemSpace>> = RefCell::new(
+        StableBTreeMap::init(
+            MEM_MANAGER.with_borrow(|m| m.get(TYPE_STATS_MEM_ID)),
+        )
+    );
+
+    static PRINCIPAL_INDEX: RefCell<StableBTreeMap<PrincipalIndexKey, StoreTier, MemSpace>> = RefCell::new(
+        StableBTreeMap::init(
+            MEM_MANAGER.with_borrow(|m| m.get(PRINCIPAL_INDEX_MEM_ID)),
+        )
+    );
+
+    static ARCHIVE_CANISTERS: RefCell<StableBTreeMap<Principal, ArchiveCanisterInfo, MemSpace>> = RefCell::new(
+        StableBTreeMap::init(
+            MEM_MANAGER.with_borrow(|m| m.get(ARCHIVE_CANISTER_MEM_ID)),
+        )
+    );
+
+    static ARCHIVE_WASM: RefCell<StableCell<Vec<u8>, MemSpace>> = RefCell::new(
+        StableCell::init(
+            MEM_MANAGER.with_borrow(|m| m.get(ARCHIVE_WASM_MEM_ID)),
+            Vec::new()
+        ).expect("failed to init ARCHIVE_WASM store")
+    );
+
+    static SPILLED_MSGS: RefCell<StableBTreeMap<MsgIdKey, SpilledMsg, MemSpace>> = RefCell::new(
+        StableBTreeMap::init(
+            MEM_MANAGER.with_borrow(|m| m.get(SPILLED_MSG_MEM_ID)),
+        )
+    );
+
+    static ARCHIVE_OPS: RefCell<StableBTreeMap<ArchiveOpKey, ArchiveOp, MemSpace>> = RefCell::new(
+        StableBTreeMap::init(
+            MEM_MANAGER.with_borrow(|m| m.get(ARCHIVE_OP_MEM_ID)),
+        )
+    );
+
+    // Spilled messages by payload type and time, for the retention policies
+    static SPILLED_BY_TIME: RefCell<StableBTreeMap<MsgKey, Principal, MemSpace>> = RefCell::new(
+        StableBTreeMap::init(
+            MEM_MANAGER.with_borrow(|m| m.get(SPILLED_BY_TIME_MEM_ID)),
+        )
+    );
+
+    static RESOURCE_INDEX: RefCell<StableBTreeMap<ResourceIndexKey, StoreTier, MemSpace>> = RefCell::new(
+        StableBTreeMap::init(
+            MEM_MANAGER.with_borrow(|m| m.get(RESOURCE_INDEX_MEM_ID)),
+        )
+    );
+
+    static EVENT_LOG: RefCell<StableBTreeMap<u64, MsgEvent, MemSpace>> = RefCell::new(
+        StableBTreeMap::init(
+            MEM_MANAGER.with_borrow(|m| m.get(EVENT_LOG_MEM_ID)),
+        )
+    );
+
+    static SUBSCRIPTIONS: RefCell<StableBTreeMap<Principal, Subscription, MemSpace>> = RefCell::new(
+        StableBTreeMap::init(
+            MEM_MANAGER.with_borrow(|m| m.get(SUBSCRIPTION_MEM_ID)),
+        )
+    );
+
+    static SEARCH_INDEX: RefCell<StableBTreeMap<SearchIndexKey, SearchPosting, MemSpace>> = RefCell::new(
+        StableBTreeMap::init(
+            MEM_MANAGER.with_borrow(|m| m.get(SEARCH_INDEX_MEM_ID)),
+        )
+    );
+
+    static COUNTERS: RefCell<StableBTreeMap<CounterKey, u64, MemSpace>> = RefCell::new(
+        StableBTreeMap::init(
+            MEM_MANAGER.with_borrow(|m| m.get(COUNTER_MEM_ID)),
+        )
+    );
+
+    static FOLLOWING: RefCell<StableBTreeMap<FollowKey, u64, MemSpace>> = RefCell::new(
+        StableBTreeMap::init(
+            MEM_MANAGER.with_borrow(|m| m.get(FOLLOWING_MEM_ID)),
+        )
+    );
+
+    static FOLLOWERS: RefCell<StableBTreeMap<FollowKey, u64, MemSpace>> = RefCell::new(
+        StableBTreeMap::init(
+            MEM_MANAGER.with_borrow(|m| m.get(FOLLOWERS_MEM_ID)),
+        )
+    );
+
+    static FOLLOW_COUNTS: RefCell<StableBTreeMap<Principal, FollowCounts, MemSpace>> = RefCell::new(
+        StableBTreeMap::init(
+            MEM_MANAGER.with_borrow(|m| m.get(FOLLOW_COUNT_MEM_ID)),
+        )
+    );
+
+    static COMMENT_INDEX: RefCell<StableBTreeMap<CommentKey, StoreTier, MemSpace>> = RefCell::new(
+        StableBTreeMap::init(
+            MEM_MANAGER.with_borrow(|m| m.get(COMMENT_INDEX_MEM_ID)),
+        )
+    );
+
+    static REACTIONS: RefCell<StableBTreeMap<ReactionKey, String, MemSpace>> = RefCell::new(
+        StableBTreeMap::init(
+            MEM_MANAGER.with_borrow(|m| m.get(REACTION_MEM_ID)),
+        )
+    );
+
+    static ENGAGEMENT: RefCell<StableBTreeMap<MsgIdKey, Engagement, MemSpace>> = RefCell::new(
+        StableBTreeMap::init(
+            MEM_MANAGER.with_borrow(|m| m.get(ENGAGEMENT_MEM_ID)),
+        )
+    );
+
+    // Messages whose thread is left to delete, with the time it was queued
+    static THREAD_DELETIONS: RefCell<StableBTreeMap<MsgIdKey, u64, MemSpace>> = RefCell::new(
+        StableBTreeMap::init(
+            MEM_MANAGER.with_borrow(|m| m.get(THREAD_DELETION_MEM_ID)),
+        )
+    );
+
+    static REPORTS: RefCell<StableBTreeMap<ReportKey, ReportEntry, MemSpace>> = RefCell::new(
+        StableBTreeMap::init(
+            MEM_MANAGER.with_borrow(|m| m.get(REPORT_MEM_ID)),
+        )
+    );
+
+    static MODERATION: RefCell<StableBTreeMap<MsgIdKey, ModerationRecord, MemSpace>> = RefCell::new(
+        StableBTreeMap::init(
+            MEM_MANAGER.with_borrow(|m| m.get(MODERATION_MEM_ID)),
+        )
+    );
+
+    static MODERATION_QUEUE: RefCell<StableBTreeMap<ModerationQueueKey, u32, MemSpace>> = RefCell::new(
+        StableBTreeMap::init(
+            MEM_MANAGER.with_borrow(|m| m.get(MODERATION_QUEUE_MEM_ID)),
+        )
+    );
+
+    // Messages excluded from list queries, with the time they were hidden
+    static HIDDEN_MSGS: RefCell<StableBTreeMap<MsgIdKey, u64, MemSpace>> = RefCell::new(
+        StableBTreeMap::init(
+            MEM_MANAGER.with_borrow(|m| m.get(HIDDEN_MSG_MEM_ID)),
+        )
+    );
+
+    static RATE_USAGE: RefCell<StableBTreeMap<UsageKey, CallerUsage, MemSpace>> = RefCell::new(
+        StableBTreeMap::init(
+            MEM_MANAGER.with_borrow(|m| m.get(RATE_USAGE_MEM_ID)),
+        )
+    );
+
+    static JOBS: RefCell<StableBTreeMap<Job, JobState, MemSpace>> = RefCell::new(
+        StableBTreeMap::init(
+            MEM_MANAGER.with_borrow(|m| m.get(JOB_MEM_ID)),
+        )
+    );
+
+    static TRUSTED_KEYS: RefCell<StableBTreeMap<Principal, TrustedKeys, MemSpace>> = RefCell::new(
+        StableBTreeMap::init(
+            MEM_MANAGER.with_borrow(|m| m.get(TRUSTED_KEY_MEM_ID)),
+        )
+    );
+
+    static MSG_VERIFICATIONS: RefCell<StableBTreeMap<MsgIdKey, MsgVerification, MemSpace>> = RefCell::new(
+        StableBTreeMap::init(
+            MEM_MANAGER.with_borrow(|m| m.get(MSG_VERIFICATION_MEM_ID)),
+        )
+    );
+
+    static SCHEMA_INDEX: RefCell<StableBTreeMap<SchemaIndexKey, StoreTier, MemSpace>> = RefCell::new(
+        StableBTreeMap::init(
+            MEM_MANAGER.with_borrow(|m| m.get(SCHEMA_INDEX_MEM_ID)),
+        )
+    );
+
+    static SCHEMA_COUNTS: RefCell<StableBTreeMap<SchemaCountKey, u64, MemSpace>> = RefCell::new(
+        StableBTreeMap::init(
+            MEM_MANAGER.with_borrow(|m| m.get(SCHEMA_COUNT_MEM_ID)),
+        )
+    );
+
+    // Last message added to the certified tree by its rebuild, see `migration::certify_batch`
+    static CERTIFY_CURSOR: RefCell<Option<(StoreTier, MsgKey)>> = RefCell::new(None);
+
+    // Timers do not survive upgrades, see `scheduler::rearm`
+    static JOB_TIMERS: RefCell<BTreeMap<Job, TimerId>> = RefCell::new(BTreeMap::new());
+
+    // Jobs with a run in this canister version, see `scheduler::begin`
+    static RUNNING_JOBS: RefCell<BTreeSet<Job>> = RefCell::new(BTreeSet::new());
+}
+
+/// Drop the state left by an earlier unit test on this thread
+#[cfg(test)]
+pub fn reset() {
+    macro_rules! clear {
+        ($($map:ident),* $(,)?) => {
+            $($map.with_borrow_mut(|map| map.clear_new());)*
+        };
+    }
+
+    PROCESSOR.with_borrow_mut(|processor| *processor = DataProcessor::default());
+    clear!(
+        LEGACY_MSG_STORE,
+        LEGACY_ARCHIVE_STORE,
+        MSG_STORE,
+        ARCHIVE_STORE,
+        MSG_ID_INDEX,
+        TYPE_STATS,
+        PRINCIPAL_INDEX,
+        ARCHIVE_CANISTERS,
+        SPILLED_MSGS,
+        ARCHIVE_OPS,
+        SPILLED_BY_TIME,
+        RESOURCE_INDEX,
+        EVENT_LOG,
+        SUBSCRIPTIONS,
+        SEARCH_INDEX,
+        COUNTERS,
+        FOLLOWING,
+        FOLLOWERS,
+        FOLLOW_COUNTS,
+        COMMENT_INDEX,
+        REACTIONS,
+        ENGAGEMENT,
+        THREAD_DELETIONS,
+        REPORTS,
+        MODERATION,
+        MODERATION_QUEUE,
+        HIDDEN_MSGS,
+        RATE_USAGE,
+        JOBS,
+        TRUSTED_KEYS,
+        MSG_VERIFICATIONS,
+        SCHEMA_INDEX,
+        SCHEMA_COUNTS,
+    );
+    CERTIFY_CURSOR.with_borrow_mut(|cursor| *cursor = None);
+    JOB_TIMERS.with_borrow_mut(BTreeMap::clear);
+    RUNNING_JOBS.with_borrow_mut(BTreeSet::clear);
+}
+
+pub mod store;
+pub mod archive_registry;
+pub mod subscriptions;
+pub mod counters;
+pub mod social;
+pub mod engagement;
+pub mod moderation;
+pub mod rate_limits;
+pub mod schema;
+pub mod migration;
+pub mod signatures;
+pub mod transfer;
+pub mod scheduler;
+pub mod state;
+pub mod message;
+pub mod history;
+pub mod spill;
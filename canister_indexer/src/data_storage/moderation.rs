@@ -0,0 +1,336 @@
+//! User reports, the moderation queue and hidden messages
+//!
+//! Hiding is keyed by payload type and message id, so a hidden message stays
+//! hidden when its publisher rewrites it or when it is archived.
+
+use super::*;
+use crate::moderation::{validate_reason, MAX_LISTED_REPORTS};
+
+/// Whether a message is excluded from list queries
+pub fn is_hidden(payload_type: &str, msg_id: &str) -> bool {
+    HIDDEN_MSGS.with_borrow(|hidden| {
+        !hidden.is_empty() && hidden.contains_key(&MsgIdKey::new(payload_type, msg_id))
+    })
+}
+
+/// Time a message was hidden, if it is
+pub fn hidden_at(payload_type: &str, msg_id: &str) -> Option<u64> {
+    HIDDEN_MSGS.with_borrow(|hidden| hidden.get(&MsgIdKey::new(payload_type, msg_id)))
+}
+
+/// Restore an imported moderation record with its open reports
+pub fn restore(record: ModerationRecord, hidden_at: Option<u64>, reports: Vec<MsgReport>) {
+    let id_key = MsgIdKey::new(&record.payload_type, &record.msg_id);
+    REPORTS.with_borrow_mut(|stored| {
+        for report in reports {
+            let key = ReportKey {
+                payload_type: record.payload_type.clone(),
+                msg_id: record.msg_id.clone(),
+                reporter: report.reporter,
+            };
+            stored.insert(key, ReportEntry { reason: report.reason, timestamp: report.timestamp });
+        }
+    });
+    if let Some(queue_key) = queue_key(&record) {
+        MODERATION_QUEUE.with_borrow_mut(|queue| queue.insert(queue_key, record.open_reports));
+    }
+    if let Some(hidden_at) = hidden_at {
+        HIDDEN_MSGS.with_borrow_mut(|hidden| hidden.insert(id_key.clone(), hidden_at));
+    }
+    MODERATION.with_borrow_mut(|records| records.insert(id_key, record));
+}
+
+/// Moderation history of a message
+pub fn record(payload_type: &str, msg_id: &str) -> Option<ModerationRecord> {
+    MODERATION.with_borrow(|records| records.get(&MsgIdKey::new(payload_type, msg_id)))
+}
+
+/// Change the moderation record of a message, dropping records left without content
+fn update_record(payload_type: &str, msg_id: &str, f: impl FnOnce(&mut ModerationRecord)) {
+    let id_key = MsgIdKey::new(payload_type, msg_id);
+    MODERATION.with_borrow_mut(|records| {
+        let mut record = records
+            .get(&id_key)
+            .unwrap_or_else(|| ModerationRecord::new(payload_type, msg_id));
+        f(&mut record);
+        if record.total_reports == 0 && record.actions.is_empty() {
+            records.remove(&id_key);
+        } else {
+            records.insert(id_key, record);
+        }
+    });
+}
+
+fn queue_key(record: &ModerationRecord) -> Option<ModerationQueueKey> {
+    record.queued_at.map(|queued_at| ModerationQueueKey {
+        queued_at,
+        payload_type: record.payload_type.clone(),
+        msg_id: record.msg_id.clone(),
+    })
+}
+
+fn report_keys(payload_type: &str, msg_id: &str) -> Vec<ReportKey> {
+    REPORTS.with_borrow(|reports| {
+        let lower = ReportKey {
+            payload_type: payload_type.to_string(),
+            msg_id: msg_id.to_string(),
+            reporter: Principal::management_canister(),
+        };
+        reports
+            .range(lower..)
+            .map(|(key, _)| key)
+            .take_while(|key| key.payload_type == payload_type && key.msg_id == msg_id)
+            .collect()
+    })
+}
+
+/// Open reports of a message, newest first
+pub fn reports(payload_type: &str, msg_id: &str) -> Vec<MsgReport> {
+    let mut reports: Vec<MsgReport> = REPORTS.with_borrow(|reports| {
+        report_keys(payload_type, msg_id)
+            .into_iter()
+            .filter_map(|key| {
+                reports.get(&key).map(|report| MsgReport {
+                    reporter: key.reporter,
+                    reason: report.reason,
+                    timestamp: report.timestamp,
+                })
+            })
+            .collect()
+    });
+    reports.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+    reports
+}
+
+/// Drop the open reports of a message and take it off the queue
+fn close_reports(payload_type: &str, msg_id: &str, record: &mut ModerationRecord) {
+    let keys = report_keys(payload_type, msg_id);
+    REPORTS.with_borrow_mut(|reports| {
+        for key in &keys {
+            reports.remove(key);
+        }
+    });
+    if let Some(queue_key) = queue_key(record) {
+        MODERATION_QUEUE.with_borrow_mut(|queue| queue.remove(&queue_key));
+    }
+    record.open_reports = 0;
+    record.queued_at = None;
+}
+
+/// Report a message for review by the moderators
+///
+/// A principal has at most one open report per message; reporting again
+/// replaces the reason.
+///
+/// # Returns
+/// Whether the report is new
+pub fn report(
+    payload_type: &str,
+    msg_id: &str,
+    reporter: Principal,
+    reason: String,
+) -> Result<bool, String> {
+    validate_reason(&reason)?;
+    if store::locate(payload_type, msg_id).is_none() && !spill::contains(payload_type, msg_id) {
+        return Err(format!("Message {} not found in store for type '{}'", msg_id, payload_type));
+    }
+
+    let now = ic_cdk::api::time();
+    let key = ReportKey {
+        payload_type: payload_type.to_string(),
+        msg_id: msg_id.to_string(),
+        reporter,
+    };
+    let previous = REPORTS.with_borrow_mut(|reports| {
+        reports.insert(key, ReportEntry { reason, timestamp: now })
+    });
+    if previous.is_some() {
+        return Ok(false);
+    }
+
+    update_record(payload_type, msg_id, |record| {
+        record.open_reports += 1;
+        record.total_reports += 1;
+        let queued_at = *record.queued_at.get_or_insert(now);
+        MODERATION_QUEUE.with_borrow_mut(|queue| {
+            queue.insert(
+                ModerationQueueKey {
+                    queued_at,
+                    payload_type: payload_type.to_string(),
+                    msg_id: msg_id.to_string(),
+                },
+                record.open_reports,
+            )
+        });
+    });
+    Ok(true)
+}
+
+/// Hide, unhide or delete a message, closing its open reports
+///
+/// The action and its reason are appended to the moderation record of the
+/// message, which is kept after a deletion. The action is forwarded to the
+/// archive canister of a spilled message.
+pub fn moderate(
+    payload_type: &str,
+    msg_id: &str,
+    action: ModerationAction,
+    moderator: Principal,
+    reason: String,
+) -> Result<(), String> {
+    validate_reason(&reason)?;
+    if store::locate(payload_type, msg_id).is_none() && !spill::contains(payload_type, msg_id) {
+        return Err(format!("Message {} not found in store for type '{}'", msg_id, payload_type));
+    }
+
+    let now = ic_cdk::api::time();
+    let id_key = MsgIdKey::new(payload_type, msg_id);
+    match action {
+        ModerationAction::Hide => {
+            HIDDEN_MSGS.with_borrow_mut(|hidden| hidden.insert(id_key, now));
+            spill::set_hidden(payload_type, msg_id, true);
+            http_gateway::invalidate(payload_type);
+        }
+        ModerationAction::Unhide => {
+            HIDDEN_MSGS.with_borrow_mut(|hidden| hidden.remove(&id_key));
+            spill::set_hidden(payload_type, msg_id, false);
+            http_gateway::invalidate(payload_type);
+        }
+        ModerationAction::Delete => {
+            message::delete_moderated_message(payload_type, msg_id)
+                .map_err(|err| err.to_string())?;
+        }
+    }
+
+    update_record(payload_type, msg_id, |record| {
+        close_reports(payload_type, msg_id, record);
+        record.hidden = action == ModerationAction::Hide;
+        record.actions.push(ModerationLogEntry {
+            action,
+            moderator,
+            reason,
+            timestamp: now,
+        });
+    });
+    Ok(())
+}
+
+/// Drop the moderation state of a removed message, keeping its action history
+pub fn forget(payload_type: &str, msg_id: &str) {
+    HIDDEN_MSGS.with_borrow_mut(|hidden| hidden.remove(&MsgIdKey::new(payload_type, msg_id)));
+    if record(payload_type, msg_id).is_some() {
+        update_record(payload_type, msg_id, |record| {
+            close_reports(payload_type, msg_id, record);
+            record.hidden = false;
+        });
+    }
+}
+
+/// A page of reported messages awaiting review, oldest report first
+///
+/// # Returns
+/// The `limit` queued messages after `offset`, and whether more follow
+pub fn queue_page(offset: usize, limit: usize) -> ModerationQueuePage {
+    let keys: Vec<ModerationQueueKey> = MODERATION_QUEUE.with_borrow(|queue| {
+        queue.iter().skip(offset).take(limit + 1).map(|(key, _)| key).collect()
+    });
+    let next_cursor = (keys.len() > limit).then(|| (offset + limit).to_string());
+
+    let items = keys
+        .into_iter()
+        .take(limit)
+        .filter_map(|key| {
+            let record = record(&key.payload_type, &key.msg_id)?;
+            let (entry, _) = store::get_entry(&key.payload_type, &key.msg_id)?;
+            let mut reports = reports(&key.payload_type, &key.msg_id);
+            reports.truncate(MAX_LISTED_REPORTS);
+            Some(ModerationQueueItem {
+                message: entry.message,
+                principal: entry.principal,
+                record,
+                reports,
+            })
+        })
+        .collect();
+    ModerationQueuePage { items, next_cursor }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::moderation::MAX_REASON_CHARS;
+    use crate::test_fixtures::{controller, post, principal, store};
+
+    fn report_from(reporter: u8, timestamp: u64) -> MsgReport {
+        MsgReport { reporter: principal(reporter), reason: "spam".to_string(), timestamp }
+    }
+
+    /// Restore a message reported twice and hidden
+    fn reported(msg_id: &str, queued_at: u64) {
+        let record = ModerationRecord {
+            open_reports: 2,
+            total_reports: 2,
+            queued_at: Some(queued_at),
+            hidden: true,
+            ..ModerationRecord::new("MsgUserPost", msg_id)
+        };
+        let reports = vec![report_from(1, queued_at), report_from(2, queued_at + 1)];
+        restore(record, Some(queued_at), reports);
+    }
+
+    /// Reasons are checked and only stored messages can be reported
+    #[test]
+    fn reports_need_a_reason_and_a_message() {
+        let creator = controller();
+        store(&post("a", MessageType::Create, creator), creator);
+
+        let reason = |chars| "x".repeat(chars);
+        assert!(validate_reason(&reason(1)).is_ok());
+        assert!(validate_reason(&reason(MAX_REASON_CHARS)).is_ok());
+        assert!(validate_reason(&reason(MAX_REASON_CHARS + 1)).is_err());
+        assert!(validate_reason("  ").is_err());
+        assert!(report("MsgUserPost", "a", principal(1), String::new()).is_err());
+        assert!(report("MsgUserPost", "b", principal(1), reason(1)).is_err());
+    }
+
+    /// The queue lists reported messages oldest first with their newest reports
+    #[test]
+    fn queue_lists_the_oldest_reports_first() {
+        let creator = controller();
+        for msg_id in ["a", "b", "c"] {
+            store(&post(msg_id, MessageType::Create, creator), creator);
+        }
+        reported("b", 10);
+        reported("a", 20);
+
+        let first = queue_page(0, 1);
+        assert_eq!(first.items.len(), 1);
+        assert_eq!(first.items[0].message.msg_id, "b");
+        let reporters: Vec<Principal> =
+            first.items[0].reports.iter().map(|report| report.reporter).collect();
+        assert_eq!(reporters, [principal(2), principal(1)]);
+        assert_eq!(first.next_cursor, Some("1".to_string()));
+
+        let second = queue_page(1, 1);
+        assert_eq!(second.items[0].message.msg_id, "a");
+        assert_eq!(second.next_cursor, None);
+        assert!(is_hidden("MsgUserPost", "a"));
+        assert!(!is_hidden("MsgUserPost", "c"));
+    }
+
+    /// Forgetting a message closes its reports and keeps its history
+    #[test]
+    fn forget_keeps_the_history() {
+        let creator = controller();
+        store(&post("a", MessageType::Create, creator), creator);
+        reported("a", 10);
+
+        forget("MsgUserPost", "a");
+        assert!(!is_hidden("MsgUserPost", "a"));
+        assert!(reports("MsgUserPost", "a").is_empty());
+        assert!(queue_page(0, 10).items.is_empty());
+        let record = record("MsgUserPost", "a").unwrap();
+        assert_eq!((record.open_reports, record.total_reports), (0, 2));
+        assert_eq!((record.queued_at, record.hidden), (None, false));
+    }
+}
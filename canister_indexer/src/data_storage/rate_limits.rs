@@ -0,0 +1,217 @@
+//! Rate limits and daily quotas of publishers
+//!
+//! Only registered publishers are tracked: admins and controllers are exempt,
+//! and other callers are rejected when their messages are authorized.
+
+use super::*;
+
+fn is_exempt(processor: &DataProcessor, caller: &Principal) -> bool {
+    processor.is_admin(caller) || !processor.publishers.contains_key(caller)
+}
+
+fn usage_of(principal: Principal, payload_type: Option<String>) -> CallerUsage {
+    RATE_USAGE
+        .with_borrow(|usage| usage.get(&UsageKey { principal, payload_type }))
+        .unwrap_or_default()
+}
+
+/// Usage key of the interaction bucket, which no payload type or namespace key can equal
+const INTERACTION_USAGE_KEY: &str = "/interactions";
+
+/// Charge one interaction of a caller: a follow, reaction, report or subscription
+///
+/// Unlike message writes, interactions are limited for every caller but
+/// admins and controllers, publisher or not.
+pub fn admit_interaction(caller: Principal) -> Result<(), IndexerError> {
+    let Some(limit) = state::with(|processor| {
+        if processor.is_admin(&caller) {
+            None
+        } else {
+            processor.rate_limits.per_interaction
+        }
+    }) else {
+        return Ok(());
+    };
+
+    let now = ic_cdk::api::time();
+    let usage_key = Some(INTERACTION_USAGE_KEY.to_string());
+    let mut usage = usage_of(caller, usage_key.clone());
+    usage.full_at = limit
+        .take(usage.full_at, now, 1)
+        .map_err(|retry_at| rate_limit::rate_limited("interactions", now, retry_at))?;
+    RATE_USAGE.with_borrow_mut(|store| {
+        store.insert(UsageKey { principal: caller, payload_type: usage_key }, usage)
+    });
+    Ok(())
+}
+
+/// Usage key of the caller bucket and daily counts of a rate limit scope
+///
+/// Global counts have no payload type, the counts of a namespace with its
+/// own limits are kept under the namespace key followed by the separator.
+fn scope_usage_key(scope: Option<&str>) -> Option<String> {
+    scope.map(|namespace| format!("{}{}", namespace, NAMESPACE_SEPARATOR))
+}
+
+/// Charge the messages of one call against the limits of the caller
+///
+/// Messages of a namespace with its own limits are charged against those,
+/// all other messages against the global limits. Either every limit
+/// admits the whole call and all of them are charged, or the call is
+/// rejected without charging anything.
+pub fn admit(caller: Principal, messages: &[Message]) -> Result<(), IndexerError> {
+    let Some(scopes) = state::with(|processor| {
+        if is_exempt(processor, &caller) {
+            return None;
+        }
+        let mut scopes: BTreeMap<Option<&str>, (RateLimitConfig, Vec<&Message>)> =
+            BTreeMap::new();
+        for msg in messages {
+            let scope = processor.rate_limit_scope(&msg.payload_type);
+            scopes
+                .entry(scope)
+                .or_insert_with(|| (processor.rate_limit_config(scope).clone(), Vec::new()))
+                .1
+                .push(msg);
+        }
+        Some(scopes)
+    }) else {
+        return Ok(());
+    };
+
+    let now = ic_cdk::api::time();
+    let mut charged = Vec::new();
+    for (scope, (config, messages)) in scopes {
+        let limit_name = |limit: &str| match scope {
+            Some(namespace) => format!("{} in {}", limit, namespace),
+            None => limit.to_string(),
+        };
+        let count = messages.len() as u64;
+        let payload_bytes: u64 = messages.iter().map(|msg| msg.payload.len() as u64).sum();
+
+        // Caller bucket and daily counts
+        let usage_key = scope_usage_key(scope);
+        let mut usage = usage_of(caller, usage_key.clone()).on_day(now / NANOS_PER_DAY);
+        let quota_reset = (usage.day + 1) * NANOS_PER_DAY;
+        if let Some(limit) = &config.per_caller {
+            if count > limit.burst {
+                return Err(IndexerError::InvalidMessage(format!(
+                    "Batch of {} messages exceeds the burst of {} messages",
+                    count, limit.burst
+                )));
+            }
+            usage.full_at = limit.take(usage.full_at, now, count).map_err(|retry_at| {
+                rate_limit::rate_limited(&limit_name("per caller"), now, retry_at)
+            })?;
+        }
+        usage.messages += count;
+        usage.payload_bytes += payload_bytes;
+        if config.daily_quota.is_some_and(|quota| usage.messages > quota) {
+            return Err(rate_limit::rate_limited(&limit_name("daily quota"), now, quota_reset));
+        }
+        if config.daily_payload_quota.is_some_and(|quota| usage.payload_bytes > quota) {
+            return Err(rate_limit::rate_limited(
+                &limit_name("daily payload quota"),
+                now,
+                quota_reset,
+            ));
+        }
+        charged.push((usage_key, usage));
+
+        // Buckets of the limited payload types, keyed by unqualified type in a namespace
+        let mut counts: BTreeMap<&str, u64> = BTreeMap::new();
+        for msg in messages {
+            *counts.entry(msg.payload_type.as_str()).or_insert(0) += 1;
+        }
+        for (payload_type, count) in counts {
+            let limit_key = match scope {
+                Some(_) => namespace::base_type(payload_type),
+                None => payload_type,
+            };
+            let Some(limit) = config.per_payload_type.get(limit_key) else {
+                continue;
+            };
+            let mut usage = usage_of(caller, Some(payload_type.to_string()));
+            usage.full_at = limit.take(usage.full_at, now, count).map_err(|retry_at| {
+                rate_limit::rate_limited(&format!("payload type {}", payload_type), now, retry_at)
+            })?;
+            charged.push((Some(payload_type.to_string()), usage));
+        }
+    }
+
+    RATE_USAGE.with_borrow_mut(|store| {
+        for (payload_type, usage) in charged {
+            store.insert(UsageKey { principal: caller, payload_type }, usage);
+        }
+    });
+    Ok(())
+}
+
+/// Current usage of a principal against the global limits or the limits of a namespace
+///
+/// A namespace without its own limits reports the global usage.
+pub fn usage(principal: Principal, namespace: Option<&str>) -> RateLimitUsage {
+    let now = ic_cdk::api::time();
+    let (scope, config, exempt, interaction_limit) = state::with(|processor| {
+        let scope = namespace.filter(|namespace| {
+            processor
+                .namespaces
+                .get(*namespace)
+                .is_some_and(|config| config.rate_limits.is_some())
+        });
+        (
+            scope,
+            processor.rate_limit_config(scope).clone(),
+            is_exempt(processor, &principal),
+            processor.rate_limits.per_interaction,
+        )
+    });
+    let interactions = usage_of(principal, Some(INTERACTION_USAGE_KEY.to_string()));
+    let usage = usage_of(principal, scope_usage_key(scope)).on_day(now / NANOS_PER_DAY);
+
+    RateLimitUsage {
+        principal,
+        exempt,
+        available: config.per_caller.map(|limit| limit.available(usage.full_at, now)),
+        available_by_payload_type: config
+            .per_payload_type
+            .iter()
+            .map(|(payload_type, limit)| {
+                let payload_type = namespace::qualify(scope, payload_type);
+                let usage = usage_of(principal, Some(payload_type.clone()));
+                (payload_type, limit.available(usage.full_at, now))
+            })
+            .collect(),
+        messages_today: usage.messages,
+        payload_bytes_today: usage.payload_bytes,
+        daily_quota: config.daily_quota,
+        daily_payload_quota: config.daily_payload_quota,
+        quota_resets_at: (usage.day + 1) * NANOS_PER_DAY,
+        interactions_available: interaction_limit
+            .map(|limit| limit.available(interactions.full_at, now)),
+    }
+}
+
+/// Drop the usage of callers whose buckets are full and whose daily counts are stale
+pub fn prune(now: u64) {
+    let today = now / NANOS_PER_DAY;
+    let stale: Vec<UsageKey> = RATE_USAGE.with_borrow(|store| {
+        store
+            .iter()
+            .filter(|(key, usage)| {
+                // Only the global and namespace usage keep daily counts
+                let has_daily_counts = match &key.payload_type {
+                    Some(payload_type) => payload_type.ends_with(NAMESPACE_SEPARATOR),
+                    None => true,
+                };
+                usage.full_at <= now && (!has_daily_counts || usage.day < today)
+            })
+            .map(|(key, _)| key)
+            .collect()
+    });
+    RATE_USAGE.with_borrow_mut(|store| {
+        for key in &stale {
+            store.remove(key);
+        }
+    });
+}
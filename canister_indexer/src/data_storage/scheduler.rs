@@ -0,0 +1,306 @@
+//! Scheduler running the background jobs of the indexer
+//!
+//! Job schedules and run history are kept in stable memory so that
+//! `rearm` can restore the timers after an upgrade. Each job has at most one
+//! pending timer and one running instance: scheduling a job that is already
+//! due earlier is a no-op, and a run requested while the job is running is
+//! started once the current run finishes.
+
+use super::*;
+
+fn job_state(job: Job) -> JobState {
+    JOBS.with_borrow(|jobs| jobs.get(&job)).unwrap_or_else(|| JobState::new(job))
+}
+
+fn update_job<R>(job: Job, f: impl FnOnce(&mut JobState) -> R) -> R {
+    let mut record = job_state(job);
+    let result = f(&mut record);
+    JOBS.with_borrow_mut(|jobs| jobs.insert(job, record));
+    result
+}
+
+/// Schedule a run of a job after a delay
+///
+/// Keeps the pending run if it is due earlier.
+pub fn schedule(job: Job, delay: Duration) {
+    let due_at = ic_cdk::api::time().saturating_add(delay.as_nanos() as u64);
+    let armed = JOB_TIMERS.with_borrow(|timers| timers.contains_key(&job));
+    let keep = update_job(job, |record| match record.next_run_at {
+        Some(next_run_at) if armed && next_run_at <= due_at => true,
+        _ => {
+            record.next_run_at = Some(due_at);
+            false
+        }
+    });
+    if !keep {
+        arm(job, due_at);
+    }
+}
+
+/// Set the timer of a job, replacing its pending timer
+fn arm(job: Job, due_at: u64) {
+    let delay = Duration::from_nanos(due_at.saturating_sub(ic_cdk::api::time()));
+    let timer_id = ic_cdk_timers::set_timer(delay, move || fire(job));
+    if let Some(previous) = JOB_TIMERS.with_borrow_mut(|timers| timers.insert(job, timer_id)) {
+        ic_cdk_timers::clear_timer(previous);
+    }
+}
+
+/// Restore the job timers from stable memory
+///
+/// Timers do not survive upgrades, so this is called from both `init`
+/// and `post_upgrade`, see `JobState::rearm`.
+pub fn rearm() {
+    let now = ic_cdk::api::time();
+    for job in Job::ALL {
+        if let Some(due_at) = update_job(job, |record| record.rearm(now)) {
+            arm(job, due_at);
+        }
+    }
+}
+
+/// Schedule and run history of every job
+pub fn list() -> Vec<JobState> {
+    Job::ALL.into_iter().map(job_state).collect()
+}
+
+fn fire(job: Job) {
+    JOB_TIMERS.with_borrow_mut(|timers| timers.remove(&job));
+    update_job(job, |record| record.next_run_at = None);
+    ic_cdk::spawn(run(job));
+}
+
+/// Clears the in-memory running mark of a job when its run ends
+///
+/// Also dropped when the run traps after an await, since the future is
+/// dropped by the cleanup of the failed callback.
+struct RunGuard(Job);
+
+impl Drop for RunGuard {
+    fn drop(&mut self) {
+        RUNNING_JOBS.with_borrow_mut(|running| running.remove(&self.0));
+    }
+}
+
+/// Mark a job as running, unless a run is in progress
+///
+/// A run is in progress while it is marked in memory, however long it
+/// awaits. A run only recorded in stable memory trapped after an await
+/// and is recorded as interrupted.
+fn begin(job: Job, now: u64) -> Option<RunGuard> {
+    if !RUNNING_JOBS.with_borrow_mut(|running| running.insert(job)) {
+        update_job(job, |record| record.rerun_pending = true);
+        return None;
+    }
+    update_job(job, |record| {
+        if record.running_since.is_some() {
+            record.finish(JobRunStatus::Interrupted, now);
+        }
+        record.running_since = Some(now);
+        record.last_started_at = Some(now);
+    });
+    Some(RunGuard(job))
+}
+
+async fn run(job: Job) {
+    // Jobs moving or rewriting messages would race with the index backfill
+    let moves_messages = matches!(
+        job,
+        Job::Retention | Job::Cleanup | Job::SchemaMigration | Job::ThreadDeletion
+    );
+    if moves_messages && migration::in_progress() {
+        schedule(job, Duration::from_secs(CLEANUP_DELAY_SECS));
+        return;
+    }
+
+    let Some(_guard) = begin(job, ic_cdk::api::time()) else {
+        return;
+    };
+
+    let result = match job {
+        Job::Retention => {
+            enforce_retention();
+            Ok(())
+        }
+        Job::Cleanup => execute_cleanup_task().await,
+        Job::Delivery => {
+            crate::subscription_manager::deliver_events().await;
+            Ok(())
+        }
+        Job::SchemaMigration => migrate_payload_schemas().await,
+        Job::ThreadDeletion => {
+            if engagement::delete_queued_threads(ARCHIVE_MSG_MIGRATION_SIZE) {
+                schedule(Job::ThreadDeletion, Duration::ZERO);
+            }
+            crate::subscription_manager::schedule_delivery();
+            Ok(())
+        }
+        Job::StorageMigration => {
+            if migration::migrate_batch(ARCHIVE_MSG_MIGRATION_SIZE) {
+                schedule(Job::StorageMigration, Duration::ZERO);
+            }
+            Ok(())
+        }
+        Job::Certification => {
+            if migration::certify_batch(CERTIFY_BATCH_SIZE) {
+                schedule(Job::Certification, Duration::ZERO);
+            }
+            Ok(())
+        }
+    };
+    let status = match result {
+        Ok(()) => JobRunStatus::Succeeded,
+        Err(err) => {
+            ic_cdk::println!("scheduler: job {:?} failed: {}", job, err);
+            JobRunStatus::Failed(err)
+        }
+    };
+
+    let rerun = update_job(job, |record| {
+        record.finish(status, ic_cdk::api::time());
+        std::mem::take(&mut record.rerun_pending)
+    });
+    if rerun {
+        schedule(job, Duration::ZERO);
+    }
+    if let Some(interval) = job.interval() {
+        schedule(job, interval);
+    }
+}
+
+/// Retention policies of all stored or spilled payload types, in every namespace
+fn retention_policies() -> Vec<(String, RetentionPolicy)> {
+    let mut payload_types: BTreeSet<String> =
+        store::all_type_stats().into_iter().map(|(payload_type, _)| payload_type).collect();
+    payload_types.extend(spill::payload_types());
+    state::with(|processor| {
+        payload_types
+            .into_iter()
+            .filter_map(|payload_type| {
+                let config = processor.payload_config(&payload_type)?;
+                Some((payload_type, config.retention))
+            })
+            .collect()
+    })
+}
+
+/// Archive and delete messages according to the age rules of their payload type
+///
+/// Also prunes the expired counter buckets and the stale rate limit usage,
+/// and retries the pending changes of spilled messages.
+///
+/// At most `ARCHIVE_MSG_MIGRATION_SIZE` messages are archived and deleted
+/// per payload type and run; the remainder is handled by the next run.
+fn enforce_retention() {
+    let now = ic_cdk::api::time();
+    counters::prune(now);
+    rate_limits::prune(now);
+    // Changes that failed to reach their archive canister are sent again
+    if spill::has_pending_ops() {
+        schedule(Job::Cleanup, Duration::from_secs(CLEANUP_DELAY_SECS));
+    }
+
+    for (payload_type, retention) in retention_policies() {
+        if let Some(before) = retention.delete_cutoff(now) {
+            let expired =
+                message::expire_before(&payload_type, before, ARCHIVE_MSG_MIGRATION_SIZE);
+            if expired > 0 {
+                ic_cdk::println!(
+                    "enforce_retention: deleted {} expired messages of key {}",
+                    expired, payload_type
+                );
+            }
+        }
+
+        if let Some(before) = retention.archive_cutoff(now) {
+            let migrated = store::archive_oldest(&payload_type, ARCHIVE_MSG_MIGRATION_SIZE, before);
+            if migrated > 0 {
+                ic_cdk::println!(
+                    "enforce_retention: archived {} messages of key {}",
+                    migrated, payload_type
+                );
+            }
+        }
+    }
+}
+
+/// Re-encode a batch of messages on an older schema version of their payload type
+///
+/// Stored messages are re-encoded first, then those spilled to archive
+/// canisters. Runs again after `CLEANUP_DELAY_SECS` while it keeps
+/// re-encoding messages, and fails with the first error if messages could
+/// not be re-encoded.
+async fn migrate_payload_schemas() -> Result<(), String> {
+    let (mut migrated, mut errors) = schema::migrate_batch(ARCHIVE_MSG_MIGRATION_SIZE);
+    // Archived messages follow once no stored message is left to re-encode
+    if migrated == 0 && !schema::outdated_archive_ranges().is_empty() {
+        let (archived, archive_errors) =
+            crate::archive_manager::migrate_archived_payloads().await;
+        migrated += archived;
+        errors.extend(archive_errors);
+    }
+    ic_cdk::println!(
+        "migrate_payload_schemas: re-encoded {} messages, {} failed",
+        migrated,
+        errors.len()
+    );
+
+    if migrated > 0 || (errors.is_empty() && !schema::outdated_archive_ranges().is_empty()) {
+        schedule(Job::SchemaMigration, Duration::from_secs(CLEANUP_DELAY_SECS));
+    }
+    match errors.first() {
+        Some(first) => Err(format!(
+            "{} messages could not be re-encoded, first: {}",
+            errors.len(),
+            first
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Execute cleanup task to migrate old messages to archive
+///
+/// Runs again after `CLEANUP_DELAY_SECS` while it keeps moving messages.
+async fn execute_cleanup_task() -> Result<(), String> {
+    let now = ic_cdk::api::time();
+
+    // Collect payload types whose live store exceeds their retention threshold
+    let keys_to_migrate: Vec<(String, u64)> = retention_policies()
+        .into_iter()
+        .filter(|(key, retention)| {
+            retention
+                .archive_threshold
+                .is_some_and(|threshold| store::type_stats(key).live > threshold)
+        })
+        .map(|(key, retention)| {
+            ic_cdk::println!("execute_cleanup_task: key {} exceeds threshold, migrating data to ARCHIVE_STORE", key);
+            (key, retention.hot_cutoff(now))
+        })
+        .collect();
+
+    // Process each key for migration, keeping messages that are still hot
+    let mut moved = 0;
+    for (key, hot_cutoff) in keys_to_migrate {
+        let migrated = store::archive_oldest(&key, ARCHIVE_MSG_MIGRATION_SIZE, hot_cutoff);
+        ic_cdk::println!(
+            "execute_cleanup_task: migrated {} messages from key {} to ARCHIVE_STORE",
+            migrated, key
+        );
+        moved += migrated as u64;
+    }
+
+    // Send the changes of spilled messages first, a new spill supersedes them
+    let flushed = crate::archive_manager::flush_archive_ops().await;
+
+    // Hand the oldest archived messages over to child archive canisters
+    let spilled = crate::archive_manager::spill_local_archive().await;
+
+    ic_cdk::println!("execute_cleanup_task: Completed cleanup task");
+
+    let progressed =
+        |result: &Result<u64, String>| result.as_ref().is_ok_and(|count| *count > 0);
+    if moved > 0 || progressed(&flushed) || progressed(&spilled) {
+        schedule(Job::Cleanup, Duration::from_secs(CLEANUP_DELAY_SECS));
+    }
+    flushed.and(spilled).map(|_| ())
+}
@@ -0,0 +1,163 @@
+//! Schema versions of the stored payloads and their re-encoding
+//!
+//! Stored messages keep the payload encoded with the schema version of their
+//! payload type at the time they were written. When the version of a type is
+//! raised, the `SchemaMigration` job re-encodes the older messages with the
+//! migrations of `payload_schema`; until then they are returned as stored.
+//! Messages spilled to archive canisters keep their schema version there and
+//! are re-encoded by the same job, see `archive_manager`.
+
+use super::*;
+use serde_bytes::ByteBuf;
+
+/// Message counts per schema version of every stored payload type
+pub fn stats() -> Vec<SchemaVersionStats> {
+    let counts: Vec<(SchemaCountKey, u64)> =
+        SCHEMA_COUNTS.with_borrow(|counts| counts.iter().collect());
+
+    let mut stats: Vec<SchemaVersionStats> = Vec::new();
+    for (key, count) in counts {
+        match stats.last_mut() {
+            Some(last) if last.payload_type == key.payload_type => {
+                last.messages_by_version.push((key.schema_version, count));
+            }
+            _ => stats.push(SchemaVersionStats {
+                current_version: current_version(&key.payload_type),
+                payload_type: key.payload_type,
+                messages_by_version: vec![(key.schema_version, count)],
+            }),
+        }
+    }
+    stats
+}
+
+/// Schema version new messages of a payload type are stored with
+pub fn current_version(payload_type: &str) -> u32 {
+    state::with(|processor| {
+        processor
+            .payload_config(payload_type)
+            .map_or(INITIAL_SCHEMA_VERSION, |config| config.schema_version)
+    })
+}
+
+/// Schedule the re-encoding if stored or spilled messages are on an older schema version
+pub fn schedule_if_outdated() {
+    if stats().iter().any(|stats| stats.outdated() > 0) || !outdated_archive_ranges().is_empty()
+    {
+        scheduler::schedule(Job::SchemaMigration, Duration::ZERO);
+    }
+}
+
+/// Payload types of archive canisters that may hold messages on an older
+/// schema version, with the archive's range and the current version
+pub fn outdated_archive_ranges() -> Vec<(Principal, String, ArchiveRange, u32)> {
+    archive_registry::list()
+        .into_iter()
+        .filter(|info| info.installed)
+        .flat_map(|info| {
+            info.ranges.into_iter().filter_map(move |(payload_type, range)| {
+                let current_version = current_version(&payload_type);
+                (range.schema_version < current_version)
+                    .then(|| (info.canister_id, payload_type, range, current_version))
+            })
+        })
+        .collect()
+}
+
+/// Raise the schema versions of the built-in payload types to those of this build
+///
+/// Called from `post_upgrade`, after the struct of a built-in type changed.
+pub fn sync_builtin_versions() {
+    state::with_mut(|processor| {
+        for (name, config) in processor.payload_types.iter_mut() {
+            if let Some(version) = builtin_schema_version(name) {
+                if config.schema_version < version {
+                    ic_cdk::println!(
+                        "sync_builtin_versions: {} raised from schema version {} to {}",
+                        name, config.schema_version, version
+                    );
+                    config.schema_version = version;
+                }
+            }
+        }
+    });
+    schedule_if_outdated();
+}
+
+/// Re-encode up to `limit` stored messages that are on an older schema version
+///
+/// Messages that fail to migrate keep their payload and version and are
+/// tried again by the next batch.
+///
+/// # Returns
+/// The number of re-encoded messages and the errors of the failed ones
+pub fn migrate_batch(limit: usize) -> (usize, Vec<String>) {
+    let outdated: Vec<(String, u32)> = stats()
+        .into_iter()
+        .filter(|stats| stats.outdated() > 0)
+        .map(|stats| (stats.payload_type, stats.current_version))
+        .collect();
+
+    let mut migrated = 0;
+    let mut errors = Vec::new();
+    let mut remaining = limit;
+    for (payload_type, current_version) in outdated {
+        if remaining == 0 {
+            break;
+        }
+        let keys: Vec<(SchemaIndexKey, StoreTier)> = SCHEMA_INDEX.with_borrow(|index| {
+            let range = SchemaIndexKey::lower_bound(&payload_type, 0)
+                ..SchemaIndexKey::lower_bound(&payload_type, current_version);
+            index.range(range).take(remaining).collect()
+        });
+        remaining -= keys.len();
+
+        for (index_key, tier) in keys {
+            match migrate_entry(&index_key, tier, current_version) {
+                Ok(()) => migrated += 1,
+                Err(err) => {
+                    errors.push(format!("{} {}: {}", payload_type, index_key.msg_id, err))
+                }
+            }
+        }
+    }
+    (migrated, errors)
+}
+
+/// Re-encode one stored message to a schema version
+fn migrate_entry(
+    index_key: &SchemaIndexKey,
+    tier: StoreTier,
+    to_version: u32,
+) -> Result<(), String> {
+    let mut entry = store::with_tier(tier, |store| store.get(&index_key.msg_key()))
+        .ok_or_else(|| "Message not found".to_string())?;
+    let verification = signatures::verification(&index_key.payload_type, &index_key.msg_id)
+        .map(|verification| MsgVerification {
+            signed_digest: verification.signed_digest.or_else(|| {
+                let digest = crate::signatures::signed_digest(&entry.message);
+                Some(ByteBuf::from(digest.to_vec()))
+            }),
+            ..verification
+        });
+    let payload = payload_schema::migrate_payload(
+        namespace::base_type(&index_key.payload_type),
+        entry.schema_version,
+        to_version,
+        &entry.message.payload,
+    )?;
+    entry.message.payload = payload.into();
+    entry.schema_version = to_version;
+
+    // Re-inserting indexes the new payload and drops the signature
+    // verification, which is restored with the digest of the signed message
+    store::insert_entry(tier, entry);
+    if let Some(verification) = verification {
+        signatures::record_verification(
+            &index_key.payload_type,
+            &index_key.msg_id,
+            verification,
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,36 @@
+//! Trusted keys cached off the user canister and the signature verifications of messages
+
+use super::*;
+
+/// Cached trusted keys of a user, fresh or not
+pub fn cached_keys(user: &Principal) -> Option<TrustedKeys> {
+    TRUSTED_KEYS.with_borrow(|keys| keys.get(user))
+}
+
+pub fn cache_keys(user: Principal, trusted_keys: TrustedKeys) {
+    TRUSTED_KEYS.with_borrow_mut(|keys| keys.insert(user, trusted_keys));
+}
+
+/// Drop the cached keys of a user, so that they are fetched again on next use
+pub fn forget_keys(user: &Principal) -> bool {
+    TRUSTED_KEYS.with_borrow_mut(|keys| keys.remove(user)).is_some()
+}
+
+/// Signature verification of a stored message, if it was signed by its author
+pub fn verification(payload_type: &str, msg_id: &str) -> Option<MsgVerification> {
+    MSG_VERIFICATIONS.with_borrow(|verifications| {
+        verifications.get(&MsgIdKey::new(payload_type, msg_id))
+    })
+}
+
+pub fn record_verification(payload_type: &str, msg_id: &str, verification: MsgVerification) {
+    MSG_VERIFICATIONS.with_borrow_mut(|verifications| {
+        verifications.insert(MsgIdKey::new(payload_type, msg_id), verification)
+    });
+}
+
+pub fn forget_verification(payload_type: &str, msg_id: &str) {
+    MSG_VERIFICATIONS.with_borrow_mut(|verifications| {
+        verifications.remove(&MsgIdKey::new(payload_type, msg_id))
+    });
+}
@@ -0,0 +1,226 @@
+//! Follow relations between principals and the home feed built on them
+
+use super::*;
+use crate::pagination::MsgPage;
+
+/// Payload types merged into the home feed
+pub const FEED_PAYLOAD_TYPES: [&str; 2] = ["MsgUserPost", "MsgSharePlay"];
+
+/// Change the follower and following counts of a principal
+fn adjust_counts(principal: Principal, f: impl FnOnce(&mut FollowCounts)) {
+    FOLLOW_COUNTS.with_borrow_mut(|counts_store| {
+        let mut counts = counts_store.get(&principal).unwrap_or_default();
+        f(&mut counts);
+        if counts.followers == 0 && counts.following == 0 {
+            counts_store.remove(&principal);
+        } else {
+            counts_store.insert(principal, counts);
+        }
+    });
+}
+
+/// Make `follower` follow `followee`
+///
+/// # Returns
+/// Whether the relation is new
+pub fn follow(follower: Principal, followee: Principal) -> Result<bool, String> {
+    if follower == followee {
+        return Err("A principal cannot follow itself".to_string());
+    }
+    let key = FollowKey { owner: follower, other: followee };
+    if FOLLOWING.with_borrow(|following| following.contains_key(&key)) {
+        return Ok(false);
+    }
+    if counts(follower).following >= MAX_FOLLOWING {
+        return Err(format!("A principal can follow at most {} principals", MAX_FOLLOWING));
+    }
+
+    let now = ic_cdk::api::time();
+    FOLLOWING.with_borrow_mut(|following| following.insert(key, now));
+    FOLLOWERS.with_borrow_mut(|followers| {
+        followers.insert(FollowKey { owner: followee, other: follower }, now)
+    });
+    adjust_counts(follower, |counts| counts.following += 1);
+    adjust_counts(followee, |counts| counts.followers += 1);
+    Ok(true)
+}
+
+/// Restore an imported follow relation, keeping the time it was created
+pub fn restore_follow(follower: Principal, followee: Principal, since: u64) {
+    let key = FollowKey { owner: follower, other: followee };
+    if FOLLOWING.with_borrow_mut(|following| following.insert(key, since)).is_some() {
+        return;
+    }
+    FOLLOWERS.with_borrow_mut(|followers| {
+        followers.insert(FollowKey { owner: followee, other: follower }, since)
+    });
+    adjust_counts(follower, |counts| counts.following += 1);
+    adjust_counts(followee, |counts| counts.followers += 1);
+}
+
+/// Remove the relation of `follower` following `followee`
+///
+/// # Returns
+/// Whether the relation existed
+pub fn unfollow(follower: Principal, followee: Principal) -> bool {
+    let key = FollowKey { owner: follower, other: followee };
+    if FOLLOWING.with_borrow_mut(|following| following.remove(&key)).is_none() {
+        return false;
+    }
+
+    FOLLOWERS.with_borrow_mut(|followers| {
+        followers.remove(&FollowKey { owner: followee, other: follower })
+    });
+    adjust_counts(follower, |counts| counts.following = counts.following.saturating_sub(1));
+    adjust_counts(followee, |counts| counts.followers = counts.followers.saturating_sub(1));
+    true
+}
+
+/// Follower and following counts of a principal
+pub fn counts(principal: Principal) -> FollowCounts {
+    FOLLOW_COUNTS.with_borrow(|counts| counts.get(&principal).unwrap_or_default())
+}
+
+/// List a page of the principals related to `owner` in a follow map, after `after`
+fn page_relations(
+    relations: &'static LocalKey<RefCell<StableBTreeMap<FollowKey, u64, MemSpace>>>,
+    owner: Principal,
+    limit: usize,
+    after: Option<Principal>,
+) -> FollowPage {
+    let lower = FollowKey {
+        owner,
+        other: after.unwrap_or_else(Principal::management_canister),
+    };
+
+    let mut principals: Vec<(Principal, u64)> = relations.with_borrow(|relations| {
+        relations
+            .range(lower..)
+            .take_while(|(key, _)| key.owner == owner)
+            .filter(|(key, _)| Some(key.other) != after)
+            .take(limit + 1)
+            .map(|(key, followed_at)| (key.other, followed_at))
+            .collect()
+    });
+
+    let next_cursor = if principals.len() > limit {
+        principals.truncate(limit);
+        principals.last().map(|(principal, _)| principal.to_text())
+    } else {
+        None
+    };
+    FollowPage { principals, next_cursor }
+}
+
+/// List a page of the principals following `principal`
+pub fn followers(principal: Principal, limit: usize, after: Option<Principal>) -> FollowPage {
+    page_relations(&FOLLOWERS, principal, limit, after)
+}
+
+/// List a page of the principals `principal` follows
+pub fn following(principal: Principal, limit: usize, after: Option<Principal>) -> FollowPage {
+    page_relations(&FOLLOWING, principal, limit, after)
+}
+
+/// All principals `principal` follows
+fn all_following(principal: Principal) -> Vec<Principal> {
+    following(principal, MAX_FOLLOWING as usize, None)
+        .principals
+        .into_iter()
+        .map(|(followee, _)| followee)
+        .collect()
+}
+
+/// Retrieve a page of the home feed of a principal, newest first
+///
+/// The feed merges the posts and share-plays of every followed principal
+/// in one namespace, or the global ones for None, from both stores.
+pub fn get_feed_page(
+    follower: Principal,
+    namespace: Option<&str>,
+    limit: usize,
+    before: Option<&MsgCursor>,
+) -> MsgPage {
+    let followees = all_following(follower);
+    let payload_types: Vec<String> = FEED_PAYLOAD_TYPES
+        .iter()
+        .map(|payload_type| namespace::qualify(namespace, payload_type))
+        .collect();
+
+    let fetched = store::principal_timeline_entries(
+        &payload_types,
+        &followees,
+        0,
+        u64::MAX,
+        before,
+        limit + 1,
+    );
+    MsgPage::from_fetched(fetched.into_iter().map(MsgEntry::into_pair).collect(), limit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{controller, post, principal, reset, store};
+
+    fn others(page: &FollowPage) -> Vec<Principal> {
+        page.principals.iter().map(|(principal, _)| *principal).collect()
+    }
+
+    /// Follower and following lists page in principal order and keep the counts
+    #[test]
+    fn relations_page_both_ways() {
+        reset();
+        let (a, b, c) = (principal(1), principal(2), principal(3));
+        restore_follow(a, b, 10);
+        restore_follow(a, c, 11);
+        restore_follow(c, b, 12);
+        restore_follow(a, b, 13);
+
+        let first = following(a, 1, None);
+        assert_eq!(others(&first), [b]);
+        assert_eq!(first.next_cursor, Some(b.to_text()));
+        let second = following(a, 1, Some(b));
+        assert_eq!(second.principals, [(c, 11)]);
+        assert_eq!(second.next_cursor, None);
+        assert_eq!(others(&followers(b, 10, None)), [a, c]);
+        assert_eq!((counts(a).following, counts(b).followers), (2, 2));
+
+        assert_eq!(follow(a, b), Ok(false));
+        assert!(follow(a, a).is_err());
+    }
+
+    /// Unfollowing removes both sides of the relation and its counts
+    #[test]
+    fn unfollow_removes_the_relation() {
+        reset();
+        let (a, b) = (principal(1), principal(2));
+        restore_follow(a, b, 10);
+
+        assert!(unfollow(a, b));
+        assert!(!unfollow(a, b));
+        assert!(following(a, 10, None).principals.is_empty());
+        assert!(followers(b, 10, None).principals.is_empty());
+        assert_eq!((counts(a).following, counts(b).followers), (0, 0));
+        assert!(FOLLOW_COUNTS.with_borrow(|counts| counts.is_empty()));
+    }
+
+    /// The feed merges the posts of followed principals only
+    #[test]
+    fn feed_lists_followed_principals() {
+        let creator = controller();
+        let (reader, followed) = (principal(1), principal(2));
+        restore_follow(reader, followed, 10);
+        let sent = [("a", 1, followed), ("b", 2, creator), ("c", 3, followed)];
+        for (msg_id, timestamp, sender) in sent {
+            store(&Message { timestamp, ..post(msg_id, MessageType::Create, sender) }, sender);
+        }
+
+        let page = get_feed_page(reader, None, 1, None);
+        let ids: Vec<&str> =
+            page.messages.iter().map(|(message, _)| message.msg_id.as_str()).collect();
+        assert_eq!(ids, ["c"]);
+        assert!(page.next_cursor.is_some());
+        assert!(get_feed_page(creator, None, 10, None).messages.is_empty());
+    }
+}
@@ -0,0 +1,193 @@
+//! Messages handed over to archive canisters and the changes pending for them
+//!
+//! A spilled message keeps its header here, so that a later Create of its
+//! id is rejected and Update, Replace, Delete, moderation and retention
+//! still reach it. Changes are queued as `ArchiveOp`s and sent to the
+//! archive canister by the cleanup job, see
+//! `archive_manager::flush_archive_ops`.
+
+use super::*;
+
+/// The spilled message with the given payload type and id
+pub fn get(payload_type: &str, msg_id: &str) -> Option<SpilledMsg> {
+    SPILLED_MSGS.with_borrow(|spilled| spilled.get(&MsgIdKey::new(payload_type, msg_id)))
+}
+
+/// Checks if a message was spilled to an archive canister
+pub fn contains(payload_type: &str, msg_id: &str) -> bool {
+    SPILLED_MSGS.with_borrow(|spilled| spilled.contains_key(&MsgIdKey::new(payload_type, msg_id)))
+}
+
+/// Record a message acknowledged by an archive canister
+///
+/// The archive overwrote any previous copy of the message, so the changes
+/// still pending for it are dropped.
+pub fn record(canister_id: Principal, entry: &MsgEntry) {
+    let message = Message { payload: Default::default(), ..entry.message.clone() };
+    let key = MsgKey::of(&message);
+    let id_key = MsgIdKey::new(&key.payload_type, &key.msg_id);
+
+    ARCHIVE_OPS.with_borrow_mut(|ops| {
+        ops.remove(&ArchiveOpKey {
+            canister_id,
+            payload_type: key.payload_type.clone(),
+            msg_id: key.msg_id.clone(),
+        })
+    });
+    SPILLED_BY_TIME.with_borrow_mut(|by_time| by_time.insert(key, canister_id));
+    SPILLED_MSGS.with_borrow_mut(|spilled| {
+        spilled.insert(
+            id_key,
+            SpilledMsg { canister_id, creator: entry.principal, message },
+        )
+    });
+}
+
+/// Forget a spilled message and queue its deletion from the archive canister
+pub fn remove(payload_type: &str, msg_id: &str) -> Option<SpilledMsg> {
+    let spilled =
+        SPILLED_MSGS.with_borrow_mut(|spilled| spilled.remove(&MsgIdKey::new(payload_type, msg_id)))?;
+    SPILLED_BY_TIME.with_borrow_mut(|by_time| by_time.remove(&MsgKey::of(&spilled.message)));
+    enqueue(spilled.canister_id, payload_type, msg_id, ArchiveOp::Delete);
+    Some(spilled)
+}
+
+/// Queue a change of the hidden flag of a spilled message
+///
+/// # Returns
+/// Whether the message is spilled
+pub fn set_hidden(payload_type: &str, msg_id: &str, hidden: bool) -> bool {
+    let Some(spilled) = get(payload_type, msg_id) else {
+        return false;
+    };
+    enqueue(spilled.canister_id, payload_type, msg_id, ArchiveOp::SetHidden(hidden));
+    true
+}
+
+/// Queue the deletion of a stale copy of a message from an archive canister
+pub fn discard(canister_id: Principal, payload_type: &str, msg_id: &str) {
+    enqueue(canister_id, payload_type, msg_id, ArchiveOp::Delete);
+}
+
+fn enqueue(canister_id: Principal, payload_type: &str, msg_id: &str, op: ArchiveOp) {
+    let key = ArchiveOpKey {
+        canister_id,
+        payload_type: payload_type.to_string(),
+        msg_id: msg_id.to_string(),
+    };
+    ARCHIVE_OPS.with_borrow_mut(|ops| {
+        // A pending deletion makes any other change moot
+        if ops.get(&key) != Some(ArchiveOp::Delete) {
+            ops.insert(key, op);
+        }
+    });
+    scheduler::schedule(Job::Cleanup, Duration::from_secs(CLEANUP_DELAY_SECS));
+}
+
+/// Payload types with spilled messages
+pub fn payload_types() -> Vec<String> {
+    SPILLED_BY_TIME.with_borrow(|by_time| {
+        let mut payload_types = Vec::new();
+        let mut next = by_time.iter().next();
+        while let Some((key, _)) = next {
+            next = by_time.range(MsgKey::type_upper_bound(&key.payload_type)..).next();
+            payload_types.push(key.payload_type);
+        }
+        payload_types
+    })
+}
+
+/// Ids of up to `count` spilled messages of a payload type older than `before`
+pub fn older_than(payload_type: &str, before: u64, count: usize) -> Vec<String> {
+    SPILLED_BY_TIME.with_borrow(|by_time| {
+        by_time
+            .range(MsgKey::lower_bound(payload_type, 0)..MsgKey::lower_bound(payload_type, before))
+            .take(count)
+            .map(|(key, _)| key.msg_id)
+            .collect()
+    })
+}
+
+/// Checks if changes are waiting to be sent to archive canisters
+pub fn has_pending_ops() -> bool {
+    ARCHIVE_OPS.with_borrow(|ops| !ops.is_empty())
+}
+
+/// Up to `limit` pending changes, grouped by archive canister and payload type
+pub fn pending_ops(limit: usize) -> Vec<(ArchiveOpKey, ArchiveOp)> {
+    ARCHIVE_OPS.with_borrow(|ops| ops.iter().take(limit).collect())
+}
+
+/// Drop a change acknowledged by the archive canister, unless it was replaced meanwhile
+pub fn complete_op(key: &ArchiveOpKey, op: ArchiveOp) {
+    ARCHIVE_OPS.with_borrow_mut(|ops| {
+        if ops.get(key) == Some(op) {
+            ops.remove(key);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{post, principal, reset};
+
+    fn spill(msg_id: &str, timestamp: u64, canister_id: Principal) {
+        let message = Message { timestamp, ..post(msg_id, MessageType::Create, principal(1)) };
+        let entry = MsgEntry { message, principal: principal(1), schema_version: 1 };
+        record(canister_id, &entry);
+    }
+
+    fn op_key(canister_id: Principal, msg_id: &str) -> ArchiveOpKey {
+        ArchiveOpKey {
+            canister_id,
+            payload_type: "MsgUserPost".to_string(),
+            msg_id: msg_id.to_string(),
+        }
+    }
+
+    /// A spilled message keeps its header, not its payload, and stays listed by age
+    #[test]
+    fn record_keeps_the_header_of_spilled_messages() {
+        reset();
+        let archive = principal(9);
+        spill("a", 1, archive);
+        spill("b", 5, archive);
+
+        let spilled = get("MsgUserPost", "a").unwrap();
+        assert_eq!((spilled.canister_id, spilled.creator), (archive, principal(1)));
+        assert!(spilled.message.payload.is_empty());
+        assert!(contains("MsgUserPost", "b"));
+        assert!(!contains("MsgUserPost", "c"));
+        assert_eq!(payload_types(), ["MsgUserPost"]);
+        assert_eq!(older_than("MsgUserPost", 5, 10), ["a"]);
+    }
+
+    /// The archive overwrote its copy, so a change pending for it is dropped
+    #[test]
+    fn record_drops_pending_changes_of_the_archive() {
+        reset();
+        let archive = principal(9);
+        let other = principal(10);
+        ARCHIVE_OPS.with_borrow_mut(|ops| {
+            ops.insert(op_key(archive, "a"), ArchiveOp::SetHidden(true));
+            ops.insert(op_key(other, "a"), ArchiveOp::Delete);
+        });
+
+        spill("a", 1, archive);
+        assert_eq!(pending_ops(10), [(op_key(other, "a"), ArchiveOp::Delete)]);
+    }
+
+    /// An acknowledged change is only dropped if it was not replaced meanwhile
+    #[test]
+    fn complete_op_keeps_replaced_changes() {
+        reset();
+        let key = op_key(principal(9), "a");
+        ARCHIVE_OPS.with_borrow_mut(|ops| ops.insert(key.clone(), ArchiveOp::SetHidden(false)));
+
+        complete_op(&key, ArchiveOp::SetHidden(true));
+        assert!(has_pending_ops());
+        complete_op(&key, ArchiveOp::SetHidden(false));
+        assert!(!has_pending_ops());
+    }
+}
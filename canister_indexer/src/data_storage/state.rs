@@ -0,0 +1,35 @@
+//! State management module
+
+use super::*;
+
+/// Execute a function with immutable access to the processor state
+#[allow(dead_code)]
+pub fn with<R>(f: impl FnOnce(&DataProcessor) -> R) -> R {
+    PROCESSOR.with(|r| f(&r.borrow()))
+}
+
+/// Execute a function with mutable access to the processor state
+pub fn with_mut<R>(f: impl FnOnce(&mut DataProcessor) -> R) -> R {
+    PROCESSOR.with(|r| f(&mut r.borrow_mut()))
+}
+
+/// Load processor state from stable storage
+pub fn load() {
+    PROCESSOR_STORE.with(|r| {
+        let s = r.borrow().get().clone();
+        PROCESSOR.with(|h| {
+            *h.borrow_mut() = s;
+        });
+    });
+}
+
+/// Save processor state to stable storage
+pub fn save() {
+    PROCESSOR.with(|h| {
+        PROCESSOR_STORE.with(|r| {
+            r.borrow_mut()
+                .set(h.borrow().clone())
+                .expect("failed to set PROCESSOR_STORE data");
+        });
+    });
+}
@@ -0,0 +1,745 @@
+//! Low-level access to the per-message stores and their indexes
+//!
+//! Every write to `MSG_STORE` or `ARCHIVE_STORE` goes through this module so
+//! that the secondary indexes and the per-type counters stay
+//! consistent.
+
+use super::*;
+
+/// Execute a function with immutable access to the store of a tier
+pub fn with_tier<R>(tier: StoreTier, f: impl FnOnce(&MsgEntryMap) -> R) -> R {
+    match tier {
+        StoreTier::Live => MSG_STORE.with_borrow(f),
+        StoreTier::Archive => ARCHIVE_STORE.with_borrow(f),
+    }
+}
+
+/// Execute a function with mutable access to the store of a tier
+fn with_tier_mut<R>(tier: StoreTier, f: impl FnOnce(&mut MsgEntryMap) -> R) -> R {
+    match tier {
+        StoreTier::Live => MSG_STORE.with_borrow_mut(f),
+        StoreTier::Archive => ARCHIVE_STORE.with_borrow_mut(f),
+    }
+}
+
+fn adjust_stats(payload_type: &str, tier: StoreTier, added: bool) {
+    TYPE_STATS.with_borrow_mut(|stats_store| {
+        let mut stats = stats_store.get(&payload_type.to_string()).unwrap_or_default();
+        let counter = stats.counter_mut(tier);
+        *counter = if added { *counter + 1 } else { counter.saturating_sub(1) };
+
+        if stats.live == 0 && stats.archived == 0 {
+            stats_store.remove(&payload_type.to_string());
+        } else {
+            stats_store.insert(payload_type.to_string(), stats);
+        }
+    });
+}
+
+fn adjust_schema_count(payload_type: &str, schema_version: u32, added: bool) {
+    let key = SchemaCountKey { payload_type: payload_type.to_string(), schema_version };
+    SCHEMA_COUNTS.with_borrow_mut(|counts| {
+        let count = counts.get(&key).unwrap_or(0);
+        let count = if added { count + 1 } else { count.saturating_sub(1) };
+        if count == 0 {
+            counts.remove(&key);
+        } else {
+            counts.insert(key, count);
+        }
+    });
+}
+
+/// Locate a message by payload type and id
+pub fn locate(payload_type: &str, msg_id: &str) -> Option<(MsgKey, StoreTier)> {
+    let id_key = MsgIdKey::new(payload_type, msg_id);
+    MSG_ID_INDEX
+        .with_borrow(|index| index.get(&id_key))
+        .map(|location| (location.key(&id_key), location.tier))
+}
+
+/// Fetch a message entry by payload type and id from whichever tier holds it
+pub fn get_entry(payload_type: &str, msg_id: &str) -> Option<(MsgEntry, StoreTier)> {
+    let (key, tier) = locate(payload_type, msg_id)?;
+    with_tier(tier, |store| store.get(&key)).map(|entry| (entry, tier))
+}
+
+/// Point the secondary indexes at an entry stored under `key` in `tier`
+fn index_entry(key: &MsgKey, entry: &MsgEntry, tier: StoreTier) {
+    MSG_ID_INDEX.with_borrow_mut(|index| {
+        index.insert(
+            MsgIdKey::new(&key.payload_type, &key.msg_id),
+            MsgLocation { timestamp: key.timestamp, tier },
+        )
+    });
+    PRINCIPAL_INDEX.with_borrow_mut(|index| {
+        index.insert(PrincipalIndexKey::of(entry.principal, key), tier)
+    });
+    RESOURCE_INDEX.with_borrow_mut(|index| {
+        for resource_key in ResourceIndexKey::of(&entry.message) {
+            index.insert(resource_key, tier);
+        }
+    });
+    SCHEMA_INDEX.with_borrow_mut(|index| {
+        index.insert(SchemaIndexKey::of(key, entry.schema_version), tier)
+    });
+    if let Some((parent_payload_type, parent_msg_id)) = discussion::comment_parent(&entry.message)
+    {
+        let comment_key = CommentKey {
+            parent_payload_type,
+            parent_msg_id,
+            timestamp: key.timestamp,
+            msg_id: key.msg_id.clone(),
+        };
+        // Moving a comment between tiers must not count it twice
+        let previous = COMMENT_INDEX.with_borrow_mut(|index| index.insert(comment_key.clone(), tier));
+        if previous.is_none() {
+            engagement::adjust(
+                &comment_key.parent_payload_type,
+                &comment_key.parent_msg_id,
+                |stats| stats.comments += 1,
+            );
+        }
+    }
+}
+
+/// Drop an entry stored under `key` from the secondary indexes
+fn unindex_entry(key: &MsgKey, entry: &MsgEntry) {
+    MSG_ID_INDEX.with_borrow_mut(|index| {
+        index.remove(&MsgIdKey::new(&key.payload_type, &key.msg_id))
+    });
+    PRINCIPAL_INDEX.with_borrow_mut(|index| {
+        index.remove(&PrincipalIndexKey::of(entry.principal, key))
+    });
+    RESOURCE_INDEX.with_borrow_mut(|index| {
+        for resource_key in ResourceIndexKey::of(&entry.message) {
+            index.remove(&resource_key);
+        }
+    });
+    SCHEMA_INDEX.with_borrow_mut(|index| {
+        index.remove(&SchemaIndexKey::of(key, entry.schema_version))
+    });
+    if let Some((parent_payload_type, parent_msg_id)) = discussion::comment_parent(&entry.message)
+    {
+        let comment_key = CommentKey {
+            parent_payload_type,
+            parent_msg_id,
+            timestamp: key.timestamp,
+            msg_id: key.msg_id.clone(),
+        };
+        if COMMENT_INDEX.with_borrow_mut(|index| index.remove(&comment_key)).is_some() {
+            engagement::adjust(
+                &comment_key.parent_payload_type,
+                &comment_key.parent_msg_id,
+                |stats| stats.comments = stats.comments.saturating_sub(1),
+            );
+        }
+    }
+}
+
+/// Add the terms of a message of a searchable payload type to the search index
+///
+/// Postings do not depend on the tier, so archiving leaves them untouched.
+pub fn index_search_terms(message: &Message) {
+    let searchable = state::with(|processor| {
+        processor
+            .payload_config(&message.payload_type)
+            .is_some_and(|config| config.searchable)
+    });
+    if !searchable {
+        return;
+    }
+
+    SEARCH_INDEX.with_borrow_mut(|index| {
+        for (term, frequency) in text_search::term_frequencies(&message.payload) {
+            index.insert(
+                SearchIndexKey::of(term, message),
+                SearchPosting { frequency, timestamp: message.timestamp },
+            );
+        }
+    });
+}
+
+/// Drop the terms of a message from the search index
+fn unindex_search_terms(message: &Message) {
+    SEARCH_INDEX.with_borrow_mut(|index| {
+        for term in text_search::term_frequencies(&message.payload).into_keys() {
+            index.remove(&SearchIndexKey::of(term, message));
+        }
+    });
+}
+
+/// Insert an entry into a tier, replacing any stored message with the same id
+pub fn insert_entry(tier: StoreTier, entry: MsgEntry) {
+    let message = &entry.message;
+    remove_entry(&message.payload_type, &message.msg_id);
+
+    let key = MsgKey::of(message);
+    index_entry(&key, &entry, tier);
+    index_search_terms(&entry.message);
+    adjust_stats(&key.payload_type, tier, true);
+    adjust_schema_count(&key.payload_type, entry.schema_version, true);
+    certification::certify_message(&entry.message, entry.principal);
+    http_gateway::invalidate(&key.payload_type);
+    with_tier_mut(tier, |store| store.insert(key, entry));
+}
+
+/// Remove a message by payload type and id, returning the removed entry
+pub fn remove_entry(payload_type: &str, msg_id: &str) -> Option<(MsgEntry, StoreTier)> {
+    let (key, tier) = locate(payload_type, msg_id)?;
+    let entry = with_tier_mut(tier, |store| store.remove(&key))?;
+
+    unindex_entry(&key, &entry);
+    unindex_search_terms(&entry.message);
+    adjust_stats(payload_type, tier, false);
+    adjust_schema_count(payload_type, entry.schema_version, false);
+    signatures::forget_verification(payload_type, msg_id);
+    certification::uncertify_message(payload_type, msg_id);
+    http_gateway::invalidate(payload_type);
+    Some((entry, tier))
+}
+
+/// Rank the messages containing every term, best first
+///
+/// The score of a message is the sum of the weighted frequencies of the
+/// terms; equal scores rank newer messages first. Candidates are read
+/// from the postings of the rarest term within the namespace, and only
+/// visible messages containing every other term count towards the
+/// `MAX_SEARCH_CANDIDATES` matches. At most `MAX_SEARCH_RESULTS` matches
+/// are ranked.
+///
+/// The namespace of a payload type takes precedence over `namespace`;
+/// without a payload type, the messages of every type of `namespace` are searched.
+///
+/// # Returns
+/// The `limit` matches after `offset` with their scores, and whether more matches follow
+pub fn search_entries(
+    terms: &[String],
+    payload_type: Option<&str>,
+    namespace: Option<&str>,
+    offset: usize,
+    limit: usize,
+) -> (Vec<(MsgEntry, u32)>, bool) {
+    let namespace = match payload_type {
+        Some(payload_type) => namespace::namespace_of(payload_type),
+        None => namespace,
+    };
+    let range = |term: &str| {
+        SearchIndexKey::lower_bound(term, namespace, payload_type)
+            ..SearchIndexKey::upper_bound(term, namespace, payload_type)
+    };
+
+    // (payload type, msg id) -> (score, timestamp)
+    let mut matches: Vec<((String, String), (u32, u64))> = Vec::new();
+    SEARCH_INDEX.with_borrow(|index| {
+        let Some(rarest) = terms.iter().min_by_key(|term| {
+            index.range(range(term)).take(text_search::MAX_SEARCH_SCAN).count()
+        }) else {
+            return;
+        };
+
+        for (key, posting) in index.range(range(rarest)).take(text_search::MAX_SEARCH_SCAN) {
+            let mut score = posting.frequency.saturating_mul(text_search::term_weight(rarest));
+            let contains_all = terms.iter().filter(|term| *term != rarest).all(|term| {
+                let other = index.get(&SearchIndexKey { term: term.clone(), ..key.clone() });
+                if let Some(other) = &other {
+                    score = score
+                        .saturating_add(other.frequency.saturating_mul(text_search::term_weight(term)));
+                }
+                other.is_some()
+            });
+            if !contains_all || moderation::is_hidden(&key.payload_type, &key.msg_id) {
+                continue;
+            }
+
+            matches.push(((key.payload_type, key.msg_id), (score, posting.timestamp)));
+            if matches.len() >= text_search::MAX_SEARCH_CANDIDATES {
+                break;
+            }
+        }
+    });
+
+    matches.sort_by(|(_, (score_a, time_a)), (_, (score_b, time_b))| {
+        (score_b, time_b).cmp(&(score_a, time_a))
+    });
+    matches.truncate(text_search::MAX_SEARCH_RESULTS);
+
+    let has_more = matches.len() > offset.saturating_add(limit);
+    let hits = matches
+        .into_iter()
+        .skip(offset)
+        .take(limit)
+        .filter_map(|((payload_type, msg_id), (score, _))| {
+            get_entry(&payload_type, &msg_id).map(|(entry, _)| (entry, score))
+        })
+        .collect();
+    (hits, has_more)
+}
+
+/// Move the oldest `count` live messages of a payload type to the archive
+///
+/// Only messages with a timestamp below `before` are moved.
+///
+/// # Returns
+/// The number of messages moved
+pub fn archive_oldest(payload_type: &str, count: usize, before: u64) -> usize {
+    let keys: Vec<MsgKey> = MSG_STORE.with_borrow(|store| {
+        store
+            .range(MsgKey::lower_bound(payload_type, 0)..MsgKey::lower_bound(payload_type, before))
+            .take(count)
+            .map(|(key, _)| key)
+            .collect()
+    });
+
+    for key in &keys {
+        if let Some(entry) = MSG_STORE.with_borrow_mut(|store| store.remove(key)) {
+            index_entry(key, &entry, StoreTier::Archive);
+            adjust_stats(payload_type, StoreTier::Live, false);
+            adjust_stats(payload_type, StoreTier::Archive, true);
+            ARCHIVE_STORE.with_borrow_mut(|store| store.insert(key.clone(), entry));
+        }
+    }
+    keys.len()
+}
+
+/// Ids of up to `count` messages of a payload type in a tier older than `before`, oldest first
+pub fn ids_before(tier: StoreTier, payload_type: &str, before: u64, count: usize) -> Vec<String> {
+    with_tier(tier, |store| {
+        store
+            .range(MsgKey::lower_bound(payload_type, 0)..MsgKey::lower_bound(payload_type, before))
+            .take(count)
+            .map(|(key, _)| key.msg_id)
+            .collect()
+    })
+}
+
+/// Whether the message stored under a key is shown by list queries
+fn is_listed(key: &MsgKey) -> bool {
+    !moderation::is_hidden(&key.payload_type, &key.msg_id)
+}
+
+/// Fetch the listed entries of index keys lazily, so that hidden messages do not shorten pages
+fn listed_entries<'a, K: 'a>(
+    keys: impl Iterator<Item = (K, StoreTier)> + 'a,
+    msg_key: impl Fn(&K) -> MsgKey + 'a,
+) -> impl Iterator<Item = MsgEntry> + 'a {
+    keys.filter_map(move |(key, tier)| {
+        let key = msg_key(&key);
+        if !is_listed(&key) {
+            return None;
+        }
+        with_tier(tier, |store| store.get(&key))
+    })
+}
+
+/// List entries of a payload type in a tier, newest or oldest first
+///
+/// Hidden messages are skipped.
+pub fn list_entries(
+    tier: StoreTier,
+    payload_type: &str,
+    newest_first: bool,
+    limit: usize,
+    offset: usize,
+) -> Vec<MsgEntry> {
+    with_tier(tier, |store| {
+        let range = store
+            .range(MsgKey::lower_bound(payload_type, 0)..MsgKey::type_upper_bound(payload_type))
+            .filter(|(key, _)| is_listed(key));
+        if newest_first {
+            range.rev().skip(offset).take(limit).map(|(_, entry)| entry).collect()
+        } else {
+            range.skip(offset).take(limit).map(|(_, entry)| entry).collect()
+        }
+    })
+}
+
+/// List up to `limit` entries of a payload type in a tier that are older than the cursor
+pub fn page_entries(
+    tier: StoreTier,
+    payload_type: &str,
+    limit: usize,
+    before: Option<&MsgCursor>,
+) -> Vec<MsgEntry> {
+    let upper = match before {
+        Some(cursor) => MsgKey {
+            payload_type: payload_type.to_string(),
+            timestamp: cursor.timestamp,
+            msg_id: cursor.msg_id.clone(),
+        },
+        None => MsgKey::type_upper_bound(payload_type),
+    };
+
+    with_tier(tier, |store| {
+        store
+            .range(MsgKey::lower_bound(payload_type, 0)..upper)
+            .rev()
+            .filter(|(key, _)| is_listed(key))
+            .take(limit)
+            .map(|(_, entry)| entry)
+            .collect()
+    })
+}
+
+/// List up to `limit` of a principal's entries of a payload type that are older than the cursor
+pub fn page_principal_entries(
+    principal: Principal,
+    payload_type: &str,
+    limit: usize,
+    before: Option<&MsgCursor>,
+) -> Vec<MsgEntry> {
+    let upper = match before {
+        Some(cursor) => PrincipalIndexKey {
+            principal,
+            payload_type: payload_type.to_string(),
+            timestamp: cursor.timestamp,
+            msg_id: cursor.msg_id.clone(),
+        },
+        None => PrincipalIndexKey::type_upper_bound(principal, payload_type),
+    };
+
+    PRINCIPAL_INDEX.with_borrow(|index| {
+        let keys = index
+            .range(PrincipalIndexKey::lower_bound(principal, payload_type, 0)..upper)
+            .rev();
+        listed_entries(keys, PrincipalIndexKey::msg_key).take(limit).collect()
+    })
+}
+
+/// List the entries of a namespace about a resource across both tiers, newest first
+///
+/// With `resource_id` set to None, the entries about any resource of the
+/// type within the canister are listed.
+pub fn page_resource_entries(
+    namespace: Option<&str>,
+    canister_id: Principal,
+    resource_type: &str,
+    resource_id: Option<u64>,
+    limit: usize,
+    before: Option<&MsgCursor>,
+) -> Vec<MsgEntry> {
+    let lower =
+        ResourceIndexKey::lower_bound(namespace, canister_id, resource_type, resource_id, 0);
+    let upper = match before {
+        // Messages with the same timestamp and id are ordered by payload type
+        Some(cursor) => ResourceIndexKey {
+            timestamp: cursor.timestamp,
+            msg_id: cursor.msg_id.clone(),
+            payload_type: cursor.payload_type.clone().unwrap_or_default(),
+            ..lower.clone()
+        },
+        None => ResourceIndexKey { timestamp: u64::MAX, ..lower.clone() },
+    };
+
+    RESOURCE_INDEX.with_borrow(|index| {
+        let keys = index.range(lower..upper).rev();
+        listed_entries(keys, ResourceIndexKey::msg_key).take(limit).collect()
+    })
+}
+
+/// The oldest `count` entries of a payload type in a tier
+///
+/// Hidden messages are included, as they move between tiers like any other.
+pub fn oldest_entries(tier: StoreTier, payload_type: &str, count: usize) -> Vec<MsgEntry> {
+    with_tier(tier, |store| {
+        store
+            .range(MsgKey::lower_bound(payload_type, 0)..MsgKey::type_upper_bound(payload_type))
+            .take(count)
+            .map(|(_, entry)| entry)
+            .collect()
+    })
+}
+
+/// Merge newest-first streams into one newest-first list of at most `limit` items
+///
+/// Items are ordered by their timestamp, message id and payload type.
+fn merge_newest_first<'a, T>(
+    sources: Vec<Box<dyn Iterator<Item = T> + 'a>>,
+    position: impl Fn(&T) -> (u64, String, String),
+    limit: usize,
+) -> Vec<T> {
+    let mut sources: Vec<_> = sources.into_iter().map(Iterator::peekable).collect();
+    let mut merged = Vec::new();
+
+    while merged.len() < limit {
+        let newest = sources
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(i, source)| source.peek().map(|item| (i, position(item))))
+            .max_by(|(_, a), (_, b)| a.cmp(b))
+            .map(|(i, _)| i);
+
+        match newest.and_then(|i| sources[i].next()) {
+            Some(item) => merged.push(item),
+            None => break,
+        }
+    }
+    merged
+}
+
+/// List entries of several payload types within `[from, to)`, merged newest first
+///
+/// Covers both the live and the archive store. With a principal, only the
+/// messages created by it are listed, using the principal index.
+pub fn timeline_entries(
+    payload_types: &[String],
+    principal: Option<Principal>,
+    from: u64,
+    to: u64,
+    before: Option<&MsgCursor>,
+    limit: usize,
+) -> Vec<MsgEntry> {
+    match principal {
+        Some(principal) => {
+            principal_timeline_entries(payload_types, &[principal], from, to, before, limit)
+        }
+        None => type_timeline_entries(payload_types, from, to, before, limit),
+    }
+}
+
+fn type_timeline_entries(
+    payload_types: &[String],
+    from: u64,
+    to: u64,
+    before: Option<&MsgCursor>,
+    limit: usize,
+) -> Vec<MsgEntry> {
+    let bounds: Vec<(MsgKey, MsgKey)> = payload_types
+        .iter()
+        .map(|payload_type| {
+            let mut upper = MsgKey::lower_bound(payload_type, to);
+            if let Some(cursor) = before {
+                upper = upper.min(MsgKey {
+                    payload_type: payload_type.clone(),
+                    timestamp: cursor.timestamp,
+                    msg_id: cursor.msg_id_bound(payload_type),
+                });
+            }
+            (MsgKey::lower_bound(payload_type, from), upper)
+        })
+        .filter(|(lower, upper)| lower < upper)
+        .collect();
+
+    MSG_STORE.with_borrow(|live| {
+        ARCHIVE_STORE.with_borrow(|archive| {
+            let mut sources: Vec<Box<dyn Iterator<Item = (MsgKey, MsgEntry)> + '_>> =
+                Vec::new();
+            for (lower, upper) in &bounds {
+                for store in [live, archive] {
+                    let range = store.range(lower.clone()..upper.clone()).rev();
+                    sources.push(Box::new(range.filter(|(key, _)| is_listed(key))));
+                }
+            }
+
+            let position = |(key, _): &(MsgKey, MsgEntry)| {
+                (key.timestamp, key.msg_id.clone(), key.payload_type.clone())
+            };
+            merge_newest_first(sources, position, limit)
+                .into_iter()
+                .map(|(_, entry)| entry)
+                .collect()
+        })
+    })
+}
+
+/// List the entries of several principals and payload types within `[from, to)`,
+/// merged newest first
+pub fn principal_timeline_entries(
+    payload_types: &[String],
+    principals: &[Principal],
+    from: u64,
+    to: u64,
+    before: Option<&MsgCursor>,
+    limit: usize,
+) -> Vec<MsgEntry> {
+    let bounds: Vec<(PrincipalIndexKey, PrincipalIndexKey)> = principals
+        .iter()
+        .flat_map(|&principal| {
+            payload_types.iter().map(move |payload_type| (principal, payload_type))
+        })
+        .map(|(principal, payload_type)| {
+            let mut upper = PrincipalIndexKey::lower_bound(principal, payload_type, to);
+            if let Some(cursor) = before {
+                upper = upper.min(PrincipalIndexKey {
+                    principal,
+                    payload_type: payload_type.clone(),
+                    timestamp: cursor.timestamp,
+                    msg_id: cursor.msg_id_bound(payload_type),
+                });
+            }
+            (PrincipalIndexKey::lower_bound(principal, payload_type, from), upper)
+        })
+        .filter(|(lower, upper)| lower < upper)
+        .collect();
+
+    PRINCIPAL_INDEX.with_borrow(|index| {
+        let mut sources: Vec<Box<dyn Iterator<Item = MsgEntry> + '_>> = Vec::new();
+        for (lower, upper) in &bounds {
+            let keys = index.range(lower.clone()..upper.clone()).rev();
+            sources.push(Box::new(listed_entries(keys, PrincipalIndexKey::msg_key)));
+        }
+
+        merge_newest_first(
+            sources,
+            |entry| {
+                let message = &entry.message;
+                (message.timestamp, message.msg_id.clone(), message.payload_type.clone())
+            },
+            limit,
+        )
+    })
+}
+
+/// List a principal's entries of a payload type across both tiers, newest first
+pub fn list_principal_entries(
+    principal: Principal,
+    payload_type: &str,
+    limit: usize,
+    offset: usize,
+) -> Vec<MsgEntry> {
+    PRINCIPAL_INDEX.with_borrow(|index| {
+        let keys = index
+            .range(
+                PrincipalIndexKey::lower_bound(principal, payload_type, 0)
+                    ..PrincipalIndexKey::type_upper_bound(principal, payload_type),
+            )
+            .rev();
+        listed_entries(keys, PrincipalIndexKey::msg_key).skip(offset).take(limit).collect()
+    })
+}
+
+/// Counters of a single payload type
+pub fn type_stats(payload_type: &str) -> TypeStats {
+    TYPE_STATS
+        .with_borrow(|stats| stats.get(&payload_type.to_string()))
+        .unwrap_or_default()
+}
+
+/// Counters of every payload type that has stored messages
+pub fn all_type_stats() -> Vec<(String, TypeStats)> {
+    TYPE_STATS.with_borrow(|stats| stats.iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{controller, post, principal, store};
+    use canister_types::message::MessageSource;
+
+    fn stored_post(msg_id: &str, timestamp: u64, creator: Principal) -> Message {
+        let message = Message { timestamp, ..post(msg_id, MessageType::Create, creator) };
+        store(&message, creator);
+        message
+    }
+
+    fn ids(entries: Vec<MsgEntry>) -> Vec<String> {
+        entries.into_iter().map(|entry| entry.message.msg_id).collect()
+    }
+
+    /// A principal's messages are listed newest first, without those of others
+    #[test]
+    fn principal_lists_skip_other_principals() {
+        let creator = controller();
+        let other = principal(8);
+        stored_post("a", 1, creator);
+        stored_post("b", 2, other);
+        stored_post("c", 3, creator);
+
+        let list = |principal, offset| {
+            ids(list_principal_entries(principal, "MsgUserPost", 10, offset))
+        };
+        assert_eq!(list(creator, 0), ["c", "a"]);
+        assert_eq!(list(creator, 1), ["a"]);
+        assert_eq!(list(other, 0), ["b"]);
+    }
+
+    /// Pages of a principal's messages continue after the cursor, without those of others
+    #[test]
+    fn principal_pages_list_only_the_principal() {
+        let creator = controller();
+        let other = principal(8);
+        stored_post("a", 1, creator);
+        stored_post("b", 2, other);
+        let newest = stored_post("c", 3, creator);
+
+        let page = |principal, cursor: Option<&MsgCursor>| {
+            ids(page_principal_entries(principal, "MsgUserPost", 10, cursor))
+        };
+        assert_eq!(page(creator, None), ["c", "a"]);
+        assert_eq!(page(creator, Some(&MsgCursor::of(&newest))), ["a"]);
+        assert_eq!(page(other, None), ["b"]);
+    }
+
+    /// Messages about a resource are listed by resource id and by resource type
+    #[test]
+    fn resource_pages_list_by_id_and_by_type() {
+        let creator = controller();
+        let canister_id = principal(3);
+        for (msg_id, timestamp, resource_id) in [("a", 1, 1), ("b", 2, 2), ("c", 3, 1)] {
+            let message = Message {
+                timestamp,
+                msg_resource: Some(MessageSource {
+                    canister_id,
+                    resource_type: "game".to_string(),
+                    resource_id,
+                }),
+                ..post(msg_id, MessageType::Create, creator)
+            };
+            store(&message, creator);
+        }
+        stored_post("d", 4, creator);
+
+        let page = |resource_id| {
+            ids(page_resource_entries(None, canister_id, "game", resource_id, 10, None))
+        };
+        assert_eq!(page(Some(1)), ["c", "a"]);
+        assert_eq!(page(Some(2)), ["b"]);
+        assert_eq!(page(None), ["c", "b", "a"]);
+        assert!(page_resource_entries(Some("game:1"), canister_id, "game", None, 10, None)
+            .is_empty());
+    }
+
+    /// Timelines interleave the requested payload types within the time window
+    #[test]
+    fn timelines_merge_payload_types_in_the_window() {
+        let creator = controller();
+        stored_post("a", 1, creator);
+        let play = Message {
+            payload_type: "MsgSharePlay".to_string(),
+            timestamp: 2,
+            ..post("b", MessageType::Create, creator)
+        };
+        store(&play, creator);
+        stored_post("c", 3, creator);
+        stored_post("d", 9, principal(8));
+
+        let types = ["MsgSharePlay".to_string(), "MsgUserPost".to_string()];
+        assert_eq!(ids(timeline_entries(&types, None, 1, 9, None, 10)), ["c", "b", "a"]);
+        assert_eq!(ids(timeline_entries(&types, None, 1, 10, None, 2)), ["d", "c"]);
+        assert_eq!(ids(timeline_entries(&types[1..], Some(creator), 2, 10, None, 10)), ["c"]);
+    }
+
+    /// Archiving moves the oldest messages below the cutoff and keeps them indexed
+    #[test]
+    fn archive_oldest_moves_messages_below_the_cutoff() {
+        let creator = controller();
+        for (msg_id, timestamp) in [("a", 1), ("b", 2), ("c", 3)] {
+            stored_post(msg_id, timestamp, creator);
+        }
+
+        assert_eq!(ids_before(StoreTier::Live, "MsgUserPost", 3, 10), ["a", "b"]);
+        assert_eq!(archive_oldest("MsgUserPost", 10, 3), 2);
+        assert_eq!(ids_before(StoreTier::Archive, "MsgUserPost", 3, 10), ["a", "b"]);
+        assert_eq!(ids_before(StoreTier::Live, "MsgUserPost", 4, 10), ["c"]);
+        assert_eq!(
+            get_entry("MsgUserPost", "a").map(|(_, tier)| tier),
+            Some(StoreTier::Archive)
+        );
+        assert_eq!(
+            ids(page_principal_entries(creator, "MsgUserPost", 10, None)),
+            ["c", "b", "a"]
+        );
+
+        let stats = type_stats("MsgUserPost");
+        assert_eq!((stats.live, stats.archived), (1, 2));
+    }
+}
@@ -0,0 +1,318 @@
+//! Subscriber registry and the event log delivered to subscribers
+//!
+//! Events are only logged while at least one subscription exists, and are
+//! pruned once every active subscriber has received them.
+
+use super::*;
+
+/// Sequence number the next logged event will get
+fn next_seq() -> u64 {
+    EVENT_LOG.with_borrow(|log| log.last_key_value().map_or(0, |(seq, _)| seq + 1))
+}
+
+/// Log a change to the stored messages for delivery to subscribers
+pub fn publish(outcome: MsgOutcome, message: &Message, principal: Principal) {
+    if SUBSCRIPTIONS.with_borrow(|subscriptions| subscriptions.is_empty()) {
+        return;
+    }
+
+    let seq = next_seq();
+    let event = MsgEvent {
+        seq,
+        outcome,
+        message: message.clone(),
+        principal,
+        recorded_at: ic_cdk::api::time(),
+    };
+    EVENT_LOG.with_borrow_mut(|log| log.insert(seq, event));
+}
+
+/// Register a subscriber or replace its filter
+///
+/// New subscribers receive the events logged from now on; an existing
+/// subscription keeps its delivery progress.
+pub fn subscribe(subscriber: Principal, filter: SubscriptionFilter) -> Result<(), String> {
+    let existing = get(&subscriber);
+    if existing.is_none() && count() >= MAX_SUBSCRIPTIONS {
+        return Err(format!("At most {} subscriptions are allowed", MAX_SUBSCRIPTIONS));
+    }
+
+    let subscription = match existing {
+        Some(subscription) => Subscription { filter, ..subscription },
+        None => Subscription {
+            subscriber,
+            filter,
+            created_at: ic_cdk::api::time(),
+            status: SubscriptionStatus::Active,
+            cursor: next_seq(),
+            delivered: 0,
+            failures: 0,
+            next_attempt_at: 0,
+            last_error: None,
+        },
+    };
+    SUBSCRIPTIONS.with_borrow_mut(|subscriptions| subscriptions.insert(subscriber, subscription));
+    Ok(())
+}
+
+/// Remove a subscription
+///
+/// # Returns
+/// Whether the subscriber was registered
+pub fn unsubscribe(subscriber: &Principal) -> bool {
+    let removed =
+        SUBSCRIPTIONS.with_borrow_mut(|subscriptions| subscriptions.remove(subscriber).is_some());
+    prune_events();
+    removed
+}
+
+/// Number of registered subscriptions
+pub fn count() -> u64 {
+    SUBSCRIPTIONS.with_borrow(|subscriptions| subscriptions.len())
+}
+
+/// Get the subscription of a subscriber
+pub fn get(subscriber: &Principal) -> Option<Subscription> {
+    SUBSCRIPTIONS.with_borrow(|subscriptions| subscriptions.get(subscriber))
+}
+
+/// List all subscriptions
+pub fn list() -> Vec<Subscription> {
+    SUBSCRIPTIONS.with_borrow(|subscriptions| {
+        subscriptions.iter().map(|(_, subscription)| subscription).collect()
+    })
+}
+
+/// List the subscriptions whose delivery was stopped
+pub fn dead_letters() -> Vec<Subscription> {
+    list()
+        .into_iter()
+        .filter(|subscription| subscription.status == SubscriptionStatus::DeadLettered)
+        .collect()
+}
+
+/// Resume delivery to a dead-lettered subscriber
+///
+/// Delivery continues with the oldest event still in the log; events
+/// pruned in the meantime are not delivered.
+///
+/// # Returns
+/// Whether the subscriber was registered
+pub fn reactivate(subscriber: &Principal) -> bool {
+    let first_seq = EVENT_LOG.with_borrow(|log| log.first_key_value().map(|(seq, _)| seq));
+    update(subscriber, |subscription| {
+        subscription.status = SubscriptionStatus::Active;
+        subscription.failures = 0;
+        subscription.next_attempt_at = 0;
+        subscription.cursor = subscription.cursor.max(first_seq.unwrap_or_else(next_seq));
+    })
+}
+
+/// Apply a change to a stored subscription, if it still exists
+fn update(subscriber: &Principal, f: impl FnOnce(&mut Subscription)) -> bool {
+    SUBSCRIPTIONS.with_borrow_mut(|subscriptions| match subscriptions.get(subscriber) {
+        Some(mut subscription) => {
+            f(&mut subscription);
+            subscriptions.insert(*subscriber, subscription);
+            true
+        }
+        None => false,
+    })
+}
+
+/// Collect the next events matching a subscription
+///
+/// At most `max_scan` logged events are examined and at most `max_batch`
+/// of them returned.
+///
+/// # Returns
+/// The matching events and the cursor to store once they are delivered
+pub fn pending_batch(
+    subscription: &Subscription,
+    max_scan: usize,
+    max_batch: usize,
+) -> (Vec<MsgEvent>, u64) {
+    let mut cursor = subscription.cursor;
+    let mut batch = Vec::new();
+
+    EVENT_LOG.with_borrow(|log| {
+        for (seq, event) in log.range(subscription.cursor..).take(max_scan) {
+            if batch.len() == max_batch {
+                break;
+            }
+            cursor = seq + 1;
+            if subscription.filter.matches(&event) {
+                batch.push(event);
+            }
+        }
+    });
+    (batch, cursor)
+}
+
+/// Record a successful delivery up to `cursor`
+pub fn record_success(subscriber: &Principal, cursor: u64, delivered: u64) {
+    update(subscriber, |subscription| {
+        subscription.cursor = subscription.cursor.max(cursor);
+        subscription.delivered += delivered;
+        subscription.failures = 0;
+        subscription.next_attempt_at = 0;
+        subscription.last_error = None;
+    });
+}
+
+/// Record a failed delivery, backing off exponentially before the next attempt
+///
+/// After `MAX_DELIVERY_FAILURES` consecutive failures the subscription is
+/// dead-lettered.
+pub fn record_failure(subscriber: &Principal, error: String, now: u64) {
+    update(subscriber, |subscription| {
+        subscription.failures += 1;
+        subscription.last_error = Some(error);
+
+        if subscription.failures >= MAX_DELIVERY_FAILURES {
+            subscription.status = SubscriptionStatus::DeadLettered;
+        } else {
+            let backoff = DELIVERY_RETRY_BASE_SECS << subscription.failures.min(10);
+            subscription.next_attempt_at = now.saturating_add(backoff * 1_000_000_000);
+        }
+    });
+}
+
+/// Earliest time an active subscription with undelivered events is due
+pub fn next_due() -> Option<u64> {
+    let next_seq = next_seq();
+    list()
+        .into_iter()
+        .filter(|subscription| {
+            subscription.status == SubscriptionStatus::Active && subscription.cursor < next_seq
+        })
+        .map(|subscription| subscription.next_attempt_at)
+        .min()
+}
+
+/// Drop the events every active subscriber has received
+pub fn prune_events() {
+    let retain_from = list()
+        .into_iter()
+        .filter(|subscription| subscription.status == SubscriptionStatus::Active)
+        .map(|subscription| subscription.cursor)
+        .min()
+        .unwrap_or_else(next_seq);
+
+    let delivered: Vec<u64> = EVENT_LOG.with_borrow(|log| {
+        log.range(..retain_from).map(|(seq, _)| seq).collect()
+    });
+    EVENT_LOG.with_borrow_mut(|log| {
+        for seq in delivered {
+            log.remove(&seq);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{post, principal, reset};
+
+    fn subscribed(byte: u8, payload_type: &str, cursor: u64) -> Principal {
+        let subscriber = principal(byte);
+        let subscription = Subscription {
+            subscriber,
+            filter: SubscriptionFilter {
+                payload_types: [payload_type.to_string()].into(),
+                principal: None,
+                resource: None,
+            },
+            created_at: 0,
+            status: SubscriptionStatus::Active,
+            cursor,
+            delivered: 0,
+            failures: 0,
+            next_attempt_at: 0,
+            last_error: None,
+        };
+        SUBSCRIPTIONS.with_borrow_mut(|subscriptions| {
+            subscriptions.insert(subscriber, subscription)
+        });
+        subscriber
+    }
+
+    fn log_events(payload_types: &[&str]) {
+        for payload_type in payload_types {
+            let message = Message {
+                payload_type: payload_type.to_string(),
+                ..post("a", MessageType::Create, principal(1))
+            };
+            let seq = next_seq();
+            let event = MsgEvent {
+                seq,
+                outcome: MsgOutcome::Created,
+                message,
+                principal: principal(1),
+                recorded_at: 0,
+            };
+            EVENT_LOG.with_borrow_mut(|log| log.insert(seq, event));
+        }
+    }
+
+    /// Cursors move past skipped events, but never beyond the scanned ones
+    #[test]
+    fn pending_batch_advances_over_unmatched_events() {
+        reset();
+        let subscriber = subscribed(20, "MsgUserPost", 0);
+        log_events(&["MsgUserPost", "MsgComment", "MsgUserPost", "MsgUserPost"]);
+
+        let subscription = get(&subscriber).unwrap();
+        let (batch, cursor) = pending_batch(&subscription, 10, 2);
+        assert_eq!(batch.iter().map(|event| event.seq).collect::<Vec<_>>(), [0, 2]);
+        assert_eq!(cursor, 3);
+
+        let (batch, cursor) = pending_batch(&subscription, 2, 10);
+        assert_eq!(batch.len(), 1);
+        assert_eq!(cursor, 2);
+    }
+
+    /// Delivered events are pruned once every active subscriber received them
+    #[test]
+    fn successful_deliveries_advance_the_cursor() {
+        reset();
+        let first = subscribed(20, "MsgUserPost", 0);
+        let second = subscribed(21, "MsgUserPost", 0);
+        log_events(&["MsgUserPost", "MsgUserPost", "MsgUserPost"]);
+
+        record_success(&first, 3, 3);
+        record_success(&second, 1, 1);
+        // A late acknowledgement never moves a cursor back
+        record_success(&first, 2, 0);
+        assert_eq!(get(&first).map(|subscription| subscription.cursor), Some(3));
+
+        prune_events();
+        let first_seq = EVENT_LOG.with_borrow(|log| log.first_key_value().map(|(seq, _)| seq));
+        assert_eq!(first_seq, Some(1));
+        assert_eq!(next_due(), Some(0));
+    }
+
+    /// Failures back off exponentially until the subscription is dead-lettered
+    #[test]
+    fn failed_deliveries_back_off_then_dead_letter() {
+        reset();
+        let subscriber = subscribed(20, "MsgUserPost", 0);
+        log_events(&["MsgUserPost"]);
+
+        record_failure(&subscriber, "rejected".to_string(), 1_000);
+        let subscription = get(&subscriber).unwrap();
+        assert_eq!(subscription.failures, 1);
+        let backoff = 2 * DELIVERY_RETRY_BASE_SECS * 1_000_000_000;
+        assert_eq!(subscription.next_attempt_at, 1_000 + backoff);
+        assert_eq!(next_due(), Some(subscription.next_attempt_at));
+
+        for _ in 1..MAX_DELIVERY_FAILURES {
+            record_failure(&subscriber, "rejected".to_string(), 1_000);
+        }
+        assert_eq!(dead_letters().len(), 1);
+        assert_eq!(next_due(), None);
+
+        assert!(reactivate(&subscriber));
+        assert_eq!(next_due(), Some(0));
+    }
+}
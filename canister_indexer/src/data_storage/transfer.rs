@@ -0,0 +1,258 @@
+//! Chunked export of the stored data and its import into a fresh canister
+//!
+//! An export walks the processor state, the archive canister registry, both
+//! message tiers, the moderation records, follows, reactions and signature
+//! verifications in key order; see `data_transfer` for the chunk format.
+
+use super::*;
+use crate::data_transfer::{
+    self, ChunkWriter, ExportChunk, ExportCursor, ExportRecord, ExportSection,
+};
+use serde_bytes::ByteBuf;
+use std::ops::Bound::{Excluded, Unbounded};
+
+/// Add the entries of a map after `after` to a chunk until it is full
+///
+/// # Returns
+/// The key of the last added entry, None if no entry follows `after`
+fn export_entries<K, V>(
+    map: &'static LocalKey<RefCell<StableBTreeMap<K, V, MemSpace>>>,
+    after: Option<K>,
+    writer: &mut ChunkWriter,
+    to_record: impl Fn(K, V) -> ExportRecord,
+) -> Option<K>
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+{
+    let lower = after.map_or(Unbounded, Excluded);
+    let mut last = None;
+    map.with_borrow(|map| {
+        for (key, value) in map.range((lower, Unbounded)) {
+            last = Some(key.clone());
+            writer.push(&to_record(key, value));
+            if writer.is_full() {
+                break;
+            }
+        }
+    });
+    last
+}
+
+/// Read the chunk starting at a cursor
+///
+/// Exports do not pin a snapshot: writes between two chunks are only
+/// included if they land after the cursor, so writes to the source
+/// should be stopped for a consistent export.
+pub fn export_chunk(cursor: &ExportCursor) -> ExportChunk {
+    let mut writer = ChunkWriter::default();
+    let mut section = cursor.section.clone();
+
+    let next = loop {
+        if writer.is_full() {
+            break Some(section);
+        }
+        section = match section {
+            ExportSection::Processor => {
+                writer.push(&ExportRecord::Processor(state::with(|processor| processor.clone())));
+                ExportSection::ArchiveCanisters(None)
+            }
+            ExportSection::ArchiveCanisters(after) => {
+                let lower = after.map_or(Unbounded, Excluded);
+                let next = ARCHIVE_CANISTERS
+                    .with_borrow(|registry| registry.range((lower, Unbounded)).next());
+                match next {
+                    Some((canister_id, info)) => {
+                        writer.push(&ExportRecord::ArchiveCanister(info));
+                        ExportSection::ArchiveCanisters(Some(canister_id))
+                    }
+                    None => ExportSection::Messages(StoreTier::Live, None),
+                }
+            }
+            ExportSection::Messages(tier, after) => {
+                let lower = after.map_or(Unbounded, Excluded);
+                let mut last = None;
+                store::with_tier(tier, |store| {
+                    for (key, entry) in store.range((lower, Unbounded)) {
+                        writer.push(&ExportRecord::Message { tier, entry });
+                        last = Some(key);
+                        if writer.is_full() {
+                            break;
+                        }
+                    }
+                });
+                match (last, tier) {
+                    (Some(key), _) => ExportSection::Messages(tier, Some(key)),
+                    (None, StoreTier::Live) => ExportSection::Messages(StoreTier::Archive, None),
+                    (None, StoreTier::Archive) => ExportSection::Moderation(None),
+                }
+            }
+            ExportSection::Moderation(after) => {
+                let last = export_entries(&MODERATION, after, &mut writer, |key, record| {
+                    ExportRecord::Moderation {
+                        hidden_at: moderation::hidden_at(&key.payload_type, &key.msg_id),
+                        reports: moderation::reports(&key.payload_type, &key.msg_id),
+                        record,
+                    }
+                });
+                match last {
+                    Some(key) => ExportSection::Moderation(Some(key)),
+                    None => ExportSection::Follows(None),
+                }
+            }
+            ExportSection::Follows(after) => {
+                let last = export_entries(&FOLLOWING, after, &mut writer, |key, since| {
+                    ExportRecord::Follow { follower: key.owner, followee: key.other, since }
+                });
+                match last {
+                    Some(key) => ExportSection::Follows(Some(key)),
+                    None => ExportSection::Reactions(None),
+                }
+            }
+            ExportSection::Reactions(after) => {
+                let last = export_entries(&REACTIONS, after, &mut writer, |key, reaction| {
+                    ExportRecord::Reaction { key, reaction }
+                });
+                match last {
+                    Some(key) => ExportSection::Reactions(Some(key)),
+                    None => ExportSection::Verifications(None),
+                }
+            }
+            ExportSection::Verifications(after) => {
+                let last = export_entries(&MSG_VERIFICATIONS, after, &mut writer, |key, verification| {
+                    ExportRecord::Verification { key, verification }
+                });
+                match last {
+                    Some(key) => ExportSection::Verifications(Some(key)),
+                    None => break None,
+                }
+            }
+        };
+    };
+
+    writer.finish(cursor, next)
+}
+
+/// Whether no message and no archive canister is stored
+fn is_empty() -> bool {
+    TYPE_STATS.with_borrow(|stats| stats.is_empty())
+        && ARCHIVE_CANISTERS.with_borrow(|registry| registry.is_empty())
+}
+
+/// Verify and apply the next chunk of an export
+///
+/// Chunks must be imported in sequence. A chunk that was already imported
+/// is acknowledged without being applied again, so an interrupted import
+/// resumes by sending the chunk at `next_sequence`. An import can only
+/// start on a canister without messages and archive canisters, and writes
+/// are rejected until its last chunk is imported.
+///
+/// The canister keeps its own controller, admins, publishers, moderators
+/// and subscriber canisters unless `replace_access_control` is set on the
+/// first chunk.
+pub fn import_chunk(
+    chunk: &ExportChunk,
+    replace_access_control: bool,
+) -> Result<ImportProgress, String> {
+    let mut progress = match state::with(|processor| processor.import.clone()) {
+        Some(progress) if progress.export_id == chunk.export_id => {
+            if chunk.sequence < progress.next_sequence {
+                return Ok(progress);
+            }
+            if progress.is_completed() {
+                return Err(format!("Export {} is already imported", chunk.export_id));
+            }
+            if chunk.sequence > progress.next_sequence {
+                return Err(format!(
+                    "Expected chunk {}, got chunk {}",
+                    progress.next_sequence, chunk.sequence
+                ));
+            }
+            progress
+        }
+        Some(progress) if !progress.is_completed() => {
+            return Err(format!("The import of export {} is in progress", progress.export_id));
+        }
+        _ => {
+            if chunk.sequence != 0 {
+                return Err("An import must start with chunk 0".to_string());
+            }
+            if !is_empty() {
+                return Err("Data can only be imported into an empty indexer".to_string());
+            }
+            if migration::in_progress() {
+                return Err("The message stores are being migrated".to_string());
+            }
+            ImportProgress {
+                export_id: chunk.export_id,
+                next_sequence: 0,
+                records_imported: 0,
+                messages_imported: 0,
+                last_checksum: ByteBuf::new(),
+                started_at: ic_cdk::api::time(),
+                completed_at: None,
+                replace_access_control,
+            }
+        }
+    };
+
+    let (header, records) = data_transfer::decode_chunk(chunk)?;
+    if header.previous_checksum != progress.last_checksum {
+        return Err(format!(
+            "Chunk {} does not follow the last imported chunk",
+            chunk.sequence
+        ));
+    }
+
+    for record in records {
+        match record {
+            ExportRecord::Processor(imported) => state::with_mut(|processor| {
+                let imported = if progress.replace_access_control {
+                    imported
+                } else {
+                    imported.with_access_control_of(processor)
+                };
+                *processor = DataProcessor {
+                    storage_version: processor.storage_version,
+                    migration: processor.migration.take(),
+                    ..imported
+                };
+            }),
+            ExportRecord::ArchiveCanister(info) => ARCHIVE_CANISTERS.with_borrow_mut(|registry| {
+                registry.insert(info.canister_id, info);
+            }),
+            ExportRecord::Message { tier, entry } => {
+                counters::record(&entry.message, entry.principal, true);
+                store::insert_entry(tier, entry);
+                progress.messages_imported += 1;
+            }
+            // Exported after the messages, which may no longer be stored
+            // here when they were handed over to archive canisters
+            ExportRecord::Moderation { record, hidden_at, reports } => {
+                moderation::restore(record, hidden_at, reports)
+            }
+            ExportRecord::Follow { follower, followee, since } => {
+                social::restore_follow(follower, followee, since)
+            }
+            ExportRecord::Reaction { key, reaction } => engagement::restore_reaction(key, reaction),
+            ExportRecord::Verification { key, verification } => {
+                signatures::record_verification(&key.payload_type, &key.msg_id, verification)
+            }
+        }
+        progress.records_imported += 1;
+    }
+
+    progress.next_sequence = chunk.sequence + 1;
+    progress.last_checksum = chunk.checksum.clone();
+    if header.last {
+        let now = ic_cdk::api::time();
+        progress.completed_at = Some(now);
+        counters::prune(now);
+    }
+    state::with_mut(|processor| processor.import = Some(progress.clone()));
+    if header.last {
+        // The export may come from a build with older built-in schemas
+        schema::sync_builtin_versions();
+    }
+    Ok(progress)
+}
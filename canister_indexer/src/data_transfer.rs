@@ -0,0 +1,356 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    data_storage::{ArchiveCanisterInfo, DataProcessor, FollowKey, MsgEntry, ReactionKey, StoreTier},
+    moderation::{ModerationRecord, MsgReport},
+    msg_key::{MsgIdKey, MsgKey},
+    signatures::MsgVerification,
+};
+
+/// Version of the chunk layout, bumped whenever `ExportRecord` or the layout changes
+pub const EXPORT_FORMAT_VERSION: u32 = 3;
+/// Oldest chunk layout that can still be imported; the messages of layouts
+/// before version 3 carry no schema version and are read as `INITIAL_SCHEMA_VERSION`
+pub const MIN_IMPORT_FORMAT_VERSION: u32 = 1;
+/// Size of the records of a chunk above which no further record is added
+pub const MAX_EXPORT_CHUNK_BYTES: usize = 1_000_000;
+/// Maximum number of records of a chunk, bounding the work of importing it
+pub const MAX_EXPORT_CHUNK_RECORDS: usize = 1000;
+
+/// Leading bytes of every chunk
+const CHUNK_MAGIC: &[u8; 8] = b"IDXEXPRT";
+
+/// One chunk of an export of the indexer data
+///
+/// `data` starts with the 8 bytes `IDXEXPRT` and the format version as a
+/// 4-byte big-endian integer, followed by the CBOR encoded `ChunkHeader` and
+/// the CBOR encoded records of the chunk, one after the other.
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug)]
+pub struct ExportChunk {
+    /// Identifier shared by the chunks of one export: the time it started
+    pub export_id: u64,
+    /// Position of the chunk in the export, starting at 0
+    pub sequence: u64,
+    pub data: ByteBuf,
+    /// SHA-256 of `data`
+    pub checksum: ByteBuf,
+    /// Cursor to pass back to fetch the next chunk, None for the last chunk
+    pub next_cursor: Option<String>,
+}
+
+/// Progress of the import of an export into this canister
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug)]
+pub struct ImportProgress {
+    pub export_id: u64,
+    /// Sequence of the next chunk to import
+    pub next_sequence: u64,
+    pub records_imported: u64,
+    pub messages_imported: u64,
+    /// Checksum of the last imported chunk
+    pub last_checksum: ByteBuf,
+    pub started_at: u64,
+    /// Time the last chunk was imported, None while chunks are missing
+    pub completed_at: Option<u64>,
+    /// Whether the controller, admins, publishers, moderators and subscriber
+    /// canisters of the export replace the ones of this canister
+    pub replace_access_control: bool,
+}
+
+impl ImportProgress {
+    pub fn is_completed(&self) -> bool {
+        self.completed_at.is_some()
+    }
+}
+
+/// Header of a chunk, repeating the envelope fields covered by the checksum
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct ChunkHeader {
+    pub export_id: u64,
+    pub sequence: u64,
+    /// Checksum of the previous chunk, empty for the first chunk
+    pub previous_checksum: ByteBuf,
+    /// Whether this is the last chunk of the export
+    pub last: bool,
+}
+
+/// A unit of exported data
+///
+/// Secondary indexes, statistics, counters, engagement counts and certified
+/// data are derived from the exported records and rebuilt on import.
+/// Subscriptions, rate limit usage, cached trusted keys, job state and the
+/// archive wasm are not exported.
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub enum ExportRecord {
+    Processor(DataProcessor),
+    ArchiveCanister(ArchiveCanisterInfo),
+    Message { tier: StoreTier, entry: MsgEntry },
+    /// Moderation record of a message with its open reports
+    Moderation {
+        record: ModerationRecord,
+        /// Time the message was hidden, if it is
+        hidden_at: Option<u64>,
+        reports: Vec<MsgReport>,
+    },
+    Follow { follower: Principal, followee: Principal, since: u64 },
+    Reaction { key: ReactionKey, reaction: String },
+    Verification { key: MsgIdKey, verification: MsgVerification },
+}
+
+/// Part of the data an export cursor points into, in export order
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub enum ExportSection {
+    Processor,
+    /// Archive canisters after the given canister
+    ArchiveCanisters(Option<Principal>),
+    /// Messages of a tier after the given key
+    Messages(StoreTier, Option<MsgKey>),
+    /// Moderation records after the given message
+    Moderation(Option<MsgIdKey>),
+    /// Follow relations after the given one
+    Follows(Option<FollowKey>),
+    /// Reactions after the given one
+    Reactions(Option<ReactionKey>),
+    /// Signature verifications after the given message
+    Verifications(Option<MsgIdKey>),
+}
+
+/// Position of an export, passed between chunks as an opaque token
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct ExportCursor {
+    pub export_id: u64,
+    /// Sequence of the chunk starting at this position
+    pub sequence: u64,
+    /// Checksum of the chunk ending at this position, chaining the chunks
+    pub previous_checksum: ByteBuf,
+    pub section: ExportSection,
+}
+
+impl ExportCursor {
+    /// Position of a new export, starting with the processor state
+    pub fn start(export_id: u64) -> Self {
+        Self {
+            export_id,
+            sequence: 0,
+            previous_checksum: ByteBuf::new(),
+            section: ExportSection::Processor,
+        }
+    }
+
+    /// Encode the cursor into an opaque token
+    pub fn encode(&self) -> String {
+        let mut bytes = vec![];
+        ciborium::into_writer(self, &mut bytes).expect("failed to encode ExportCursor data");
+        URL_SAFE_NO_PAD.encode(bytes)
+    }
+
+    /// Decode a token produced by `encode`
+    pub fn decode(token: &str) -> Result<Self, String> {
+        URL_SAFE_NO_PAD
+            .decode(token)
+            .ok()
+            .and_then(|bytes| ciborium::from_reader(bytes.as_slice()).ok())
+            .ok_or_else(|| format!("Invalid cursor: {}", token))
+    }
+}
+
+/// Records of a chunk being built, tracking their encoded size
+#[derive(Default)]
+pub struct ChunkWriter {
+    records: Vec<u8>,
+    count: usize,
+}
+
+impl ChunkWriter {
+    /// Whether no further record may be added
+    pub fn is_full(&self) -> bool {
+        self.records.len() >= MAX_EXPORT_CHUNK_BYTES || self.count >= MAX_EXPORT_CHUNK_RECORDS
+    }
+
+    pub fn push(&mut self, record: &ExportRecord) {
+        ciborium::into_writer(record, &mut self.records).expect("failed to encode ExportRecord data");
+        self.count += 1;
+    }
+
+    /// Seal the records read from `cursor` into a chunk
+    ///
+    /// `next` is the section the following chunk starts in, None if this is the last chunk.
+    pub fn finish(self, cursor: &ExportCursor, next: Option<ExportSection>) -> ExportChunk {
+        let header = ChunkHeader {
+            export_id: cursor.export_id,
+            sequence: cursor.sequence,
+            previous_checksum: cursor.previous_checksum.clone(),
+            last: next.is_none(),
+        };
+        let mut data = Vec::with_capacity(CHUNK_MAGIC.len() + 4 + self.records.len() + 32);
+        data.extend_from_slice(CHUNK_MAGIC);
+        data.extend_from_slice(&EXPORT_FORMAT_VERSION.to_be_bytes());
+        ciborium::into_writer(&header, &mut data).expect("failed to encode ChunkHeader data");
+        data.extend_from_slice(&self.records);
+
+        let checksum = ByteBuf::from(Sha256::digest(&data).to_vec());
+        let next_cursor = next.map(|section| {
+            ExportCursor {
+                export_id: cursor.export_id,
+                sequence: cursor.sequence + 1,
+                previous_checksum: checksum.clone(),
+                section,
+            }
+            .encode()
+        });
+
+        ExportChunk {
+            export_id: cursor.export_id,
+            sequence: cursor.sequence,
+            data: ByteBuf::from(data),
+            checksum,
+            next_cursor,
+        }
+    }
+}
+
+/// Verify the checksum, layout and version of a chunk and decode it
+pub fn decode_chunk(chunk: &ExportChunk) -> Result<(ChunkHeader, Vec<ExportRecord>), String> {
+    if Sha256::digest(&chunk.data).as_slice() != chunk.checksum.as_slice() {
+        return Err(format!("Checksum mismatch in chunk {}", chunk.sequence));
+    }
+
+    let data = chunk.data.as_slice();
+    let rest = data
+        .strip_prefix(CHUNK_MAGIC.as_slice())
+        .ok_or_else(|| "Not an indexer export chunk".to_string())?;
+    if rest.len() < 4 {
+        return Err("Truncated export chunk".to_string());
+    }
+    let (version, mut reader) = rest.split_at(4);
+    let version = u32::from_be_bytes(version.try_into().expect("4-byte version"));
+    if !(MIN_IMPORT_FORMAT_VERSION..=EXPORT_FORMAT_VERSION).contains(&version) {
+        return Err(format!(
+            "Unsupported export format version {}, expected {} to {}",
+            version, MIN_IMPORT_FORMAT_VERSION, EXPORT_FORMAT_VERSION
+        ));
+    }
+
+    let header: ChunkHeader = ciborium::from_reader(&mut reader)
+        .map_err(|err| format!("Failed to decode chunk header: {}", err))?;
+    if header.export_id != chunk.export_id || header.sequence != chunk.sequence {
+        return Err("Chunk header does not match the chunk".to_string());
+    }
+
+    let mut records = Vec::new();
+    while !reader.is_empty() {
+        let record = ciborium::from_reader(&mut reader)
+            .map_err(|err| format!("Failed to decode record {}: {}", records.len(), err))?;
+        records.push(record);
+    }
+    Ok((header, records))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(next: Option<ExportSection>) -> ExportChunk {
+        let mut writer = ChunkWriter::default();
+        writer.push(&ExportRecord::Follow {
+            follower: Principal::from_slice(&[1; 29]),
+            followee: Principal::from_slice(&[2; 29]),
+            since: 7,
+        });
+        writer.finish(&ExportCursor::start(42), next)
+    }
+
+    /// Replace the data of a chunk, keeping its checksum valid
+    fn reseal(chunk: &mut ExportChunk, data: Vec<u8>) {
+        chunk.checksum = ByteBuf::from(Sha256::digest(&data).to_vec());
+        chunk.data = ByteBuf::from(data);
+    }
+
+    #[test]
+    fn chunk_round_trips() {
+        let chunk = chunk(None);
+        assert_eq!(chunk.next_cursor, None);
+
+        let (header, records) = decode_chunk(&chunk).unwrap();
+        assert_eq!(
+            (header.export_id, header.sequence, header.last),
+            (42, 0, true)
+        );
+        assert!(header.previous_checksum.is_empty());
+        assert!(matches!(
+            records.as_slice(),
+            [ExportRecord::Follow { since: 7, .. }]
+        ));
+    }
+
+    /// The cursor of the next chunk carries the checksum of this one
+    #[test]
+    fn next_cursor_chains_checksums() {
+        let chunk = chunk(Some(ExportSection::Follows(None)));
+        let (header, _) = decode_chunk(&chunk).unwrap();
+        assert!(!header.last);
+
+        let next = ExportCursor::decode(&chunk.next_cursor.unwrap()).unwrap();
+        assert_eq!((next.export_id, next.sequence), (42, 1));
+        assert_eq!(next.previous_checksum, chunk.checksum);
+        assert!(matches!(next.section, ExportSection::Follows(None)));
+    }
+
+    #[test]
+    fn decode_rejects_tampered_data() {
+        let mut chunk = chunk(None);
+        let mut data = chunk.data.to_vec();
+        *data.last_mut().unwrap() ^= 1;
+        chunk.data = ByteBuf::from(data);
+
+        assert_eq!(
+            decode_chunk(&chunk).unwrap_err(),
+            "Checksum mismatch in chunk 0"
+        );
+    }
+
+    /// A chunk cannot be replayed at another position of the export
+    #[test]
+    fn decode_rejects_mismatching_envelope() {
+        let mut chunk = chunk(None);
+        chunk.sequence = 1;
+        assert!(decode_chunk(&chunk).is_err());
+
+        let mut chunk = self::chunk(None);
+        chunk.export_id = 43;
+        assert_eq!(
+            decode_chunk(&chunk).unwrap_err(),
+            "Chunk header does not match the chunk"
+        );
+    }
+
+    #[test]
+    fn decode_rejects_unknown_layouts() {
+        let mut chunk = chunk(None);
+        let mut data = chunk.data.to_vec();
+        data[CHUNK_MAGIC.len()..CHUNK_MAGIC.len() + 4]
+            .copy_from_slice(&(EXPORT_FORMAT_VERSION + 1).to_be_bytes());
+        reseal(&mut chunk, data);
+        assert!(decode_chunk(&chunk)
+            .unwrap_err()
+            .starts_with("Unsupported export format version"));
+
+        reseal(&mut chunk, b"NOTANEXPORT".to_vec());
+        assert_eq!(
+            decode_chunk(&chunk).unwrap_err(),
+            "Not an indexer export chunk"
+        );
+
+        reseal(&mut chunk, CHUNK_MAGIC.to_vec());
+        assert_eq!(decode_chunk(&chunk).unwrap_err(), "Truncated export chunk");
+    }
+
+    #[test]
+    fn cursor_decode_rejects_invalid_tokens() {
+        assert!(ExportCursor::decode("not a cursor").is_err());
+        assert!(ExportCursor::decode(&ExportCursor::start(1).encode()).is_ok());
+    }
+}
@@ -0,0 +1,88 @@
+use candid::{CandidType, Principal};
+use canister_types::message::Message;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::namespace;
+
+/// Payload type of comments
+pub const COMMENT_PAYLOAD_TYPE: &str = "MsgComment";
+/// Maximum depth of a thread returned by a single query
+pub const MAX_THREAD_DEPTH: u32 = 8;
+/// Maximum number of replies returned per comment of a thread
+pub const MAX_THREAD_REPLIES: usize = 100;
+/// Maximum length of a reaction, in characters
+pub const MAX_REACTION_CHARS: usize = 16;
+
+/// Payload of a `MsgComment` message
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug)]
+pub struct MsgComment {
+    /// Payload type of the message replied to, `MsgComment` for a reply to a
+    /// comment; the parent must be in the namespace of the comment
+    pub parent_payload_type: String,
+    pub parent_msg_id: String,
+    pub text: String,
+    /// Time the author last edited the text, None if it was never edited
+    ///
+    /// Added in schema version 2.
+    pub edited_at: Option<u64>,
+}
+
+/// A message with its replies, as returned by thread queries
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug)]
+pub struct ThreadNode {
+    pub message: Message,
+    pub principal: Principal,
+    pub engagement: Engagement,
+    /// Replies, oldest first; empty below the requested depth
+    pub replies: Vec<ThreadNode>,
+}
+
+/// Aggregated comment and reaction counts of a message
+#[derive(CandidType, Clone, Default, Deserialize, Serialize, Debug)]
+pub struct Engagement {
+    /// Number of direct comments
+    pub comments: u64,
+    /// Number of principals per reaction
+    pub reactions: BTreeMap<String, u64>,
+}
+
+impl Engagement {
+    pub fn is_empty(&self) -> bool {
+        self.comments == 0 && self.reactions.is_empty()
+    }
+}
+
+/// Reaction summary of a message
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug)]
+pub struct ReactionSummary {
+    pub engagement: Engagement,
+    /// Reaction of the requested principal, if any
+    pub user_reaction: Option<String>,
+}
+
+/// Check that a reaction is a short non-empty token such as an emoji
+pub fn validate_reaction(reaction: &str) -> Result<(), String> {
+    let chars = reaction.chars().count();
+    if chars == 0 || chars > MAX_REACTION_CHARS || reaction.chars().any(char::is_whitespace) {
+        return Err(format!(
+            "A reaction must be 1 to {} characters without whitespace",
+            MAX_REACTION_CHARS
+        ));
+    }
+    Ok(())
+}
+
+/// Payload type of the comments on a message, in the namespace of the message
+pub fn comment_payload_type(parent_payload_type: &str) -> String {
+    namespace::qualify(namespace::namespace_of(parent_payload_type), COMMENT_PAYLOAD_TYPE)
+}
+
+/// Parent of a comment message, None for other messages or undecodable payloads
+pub fn comment_parent(message: &Message) -> Option<(String, String)> {
+    if namespace::base_type(&message.payload_type) != COMMENT_PAYLOAD_TYPE {
+        return None;
+    }
+    let comment: MsgComment = message.decode_payload().ok()?;
+    Some((comment.parent_payload_type, comment.parent_msg_id))
+}
@@ -0,0 +1,627 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use candid::{
+    types::{
+        value::{IDLField, IDLValue},
+        Label,
+    },
+    CandidType, IDLArgs, Principal,
+};
+use canister_types::message::Message;
+use serde::Deserialize;
+use serde_bytes::ByteBuf;
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+use std::{cell::RefCell, collections::BTreeMap};
+
+use crate::{
+    certification::{self, CertifiedPath, HTTP_ASSETS_LABEL},
+    data_storage,
+    indexer_error::IndexerError,
+    namespace,
+    pagination::{MsgCursor, MsgPage},
+    rate_limit::{self, RateLimit},
+    text_search,
+};
+
+/// Number of messages per page of the JSON listings
+pub const HTTP_PAGE_SIZE: usize = 50;
+/// Number of entries of the Atom feed
+pub const ATOM_ENTRY_COUNT: usize = 20;
+
+/// Maximum number of certified responses kept, further renders evict the lowest path
+const MAX_CACHED_RESPONSES: usize = 256;
+/// Renders of the pages of one route, see `handle_update`
+const RENDER_LIMIT: RateLimit = RateLimit { burst: 30, per_minute: 30 };
+/// Payload type published in the Atom feeds
+const ATOM_PAYLOAD_TYPE: &str = "MsgUserPost";
+/// Entry titles are the start of the post text, cut at this many characters
+const ATOM_TITLE_CHARS: usize = 80;
+
+/// Request received through the HTTP gateway
+#[derive(CandidType, Clone, Deserialize, Debug)]
+pub struct HttpRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: ByteBuf,
+    pub certificate_version: Option<u16>,
+}
+
+/// Response returned through the HTTP gateway
+#[derive(CandidType, Clone, Deserialize, Debug)]
+pub struct HttpResponse {
+    pub status_code: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: ByteBuf,
+    /// Asks the gateway to repeat the request as an update call
+    pub upgrade: Option<bool>,
+}
+
+impl HttpResponse {
+    fn error(status_code: u16, message: &str) -> Self {
+        Self {
+            status_code,
+            headers: vec![("Content-Type".to_string(), "text/plain; charset=utf-8".to_string())],
+            body: ByteBuf::from(message.as_bytes().to_vec()),
+            upgrade: None,
+        }
+    }
+
+    fn upgrade() -> Self {
+        Self {
+            status_code: 200,
+            headers: Vec::new(),
+            body: ByteBuf::new(),
+            upgrade: Some(true),
+        }
+    }
+}
+
+/// Public endpoints served over HTTP
+///
+/// Pages are addressed by path only, as certification covers the path and
+/// not the query string:
+/// * `/api/msgs/{payload_type}[/before/{cursor}]`
+/// * `/api/users/{principal}/msgs/{payload_type}[/before/{cursor}]`
+/// * `/feeds/posts.atom`
+///
+/// The same endpoints of a namespace are served below `/ns/{namespace}`,
+/// e.g. `/ns/game:42/feeds/posts.atom`, with unqualified payload types.
+enum Route {
+    TypePage { payload_type: String, before: Option<MsgCursor> },
+    UserPage { principal: Principal, payload_type: String, before: Option<MsgCursor> },
+    AtomFeed { namespace: Option<String> },
+}
+
+impl Route {
+    fn parse(path: &str) -> Option<Self> {
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        match segments.as_slice() {
+            ["ns", namespace, rest @ ..] if !namespace.is_empty() => {
+                Self::parse_endpoint(Some(*namespace), rest)
+            }
+            segments => Self::parse_endpoint(None, segments),
+        }
+    }
+
+    fn parse_endpoint(namespace: Option<&str>, segments: &[&str]) -> Option<Self> {
+        match segments {
+            ["api", "msgs", payload_type, rest @ ..] => Some(Route::TypePage {
+                payload_type: namespace::qualify(namespace, payload_type),
+                before: parse_before(rest)?,
+            }),
+            ["api", "users", principal, "msgs", payload_type, rest @ ..] => Some(Route::UserPage {
+                principal: Principal::from_text(principal).ok()?,
+                payload_type: namespace::qualify(namespace, payload_type),
+                before: parse_before(rest)?,
+            }),
+            ["feeds", "posts.atom"] => Some(Route::AtomFeed {
+                namespace: namespace.map(str::to_string),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Key of the render bucket of the route, shared by all its pages
+    fn render_key(&self) -> String {
+        match self {
+            Route::TypePage { payload_type, .. } => format!("msgs/{}", payload_type),
+            Route::UserPage { payload_type, .. } => format!("users/{}", payload_type),
+            Route::AtomFeed { .. } => format!("feeds/{}", self.payload_type()),
+        }
+    }
+
+    /// Payload type of the messages the response of the route shows
+    fn payload_type(&self) -> String {
+        match self {
+            Route::TypePage { payload_type, .. } | Route::UserPage { payload_type, .. } => {
+                payload_type.clone()
+            }
+            Route::AtomFeed { namespace } => {
+                namespace::qualify(namespace.as_deref(), ATOM_PAYLOAD_TYPE)
+            }
+        }
+    }
+}
+
+/// Path prefix of the endpoints of a namespace, empty for the global endpoints
+fn namespace_prefix(namespace: Option<&str>) -> String {
+    namespace
+        .map(|namespace| format!("/ns/{}", namespace))
+        .unwrap_or_default()
+}
+
+/// Cursor of the `/before/{cursor}` path suffix; None if the suffix is malformed
+fn parse_before(rest: &[&str]) -> Option<Option<MsgCursor>> {
+    match rest {
+        [] => Some(None),
+        ["before", cursor] => MsgCursor::decode(cursor).ok().map(Some),
+        _ => None,
+    }
+}
+
+/// A rendered response body
+#[derive(Clone)]
+struct RenderedBody {
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+thread_local! {
+    // Rendered responses keyed by path, their hashes are certified under `http_assets`
+    static RENDERED_RESPONSES: RefCell<BTreeMap<String, RenderedBody>> = RefCell::new(BTreeMap::new());
+
+    // Time the render bucket of a route is full again, keyed by `Route::render_key`
+    static RENDER_BUCKETS: RefCell<BTreeMap<String, u64>> = RefCell::new(BTreeMap::new());
+}
+
+/// Path of the certified hash of a response
+fn asset_path(path: &str) -> CertifiedPath {
+    vec![HTTP_ASSETS_LABEL.to_vec(), path.as_bytes().to_vec()]
+}
+
+/// Drop the certified responses showing messages of a payload type after a
+/// change to them
+///
+/// Must only be called from update calls, timers and upgrade hooks.
+pub fn invalidate(payload_type: &str) {
+    let stale: Vec<String> = RENDERED_RESPONSES.with_borrow_mut(|responses| {
+        let stale: Vec<String> = responses
+            .keys()
+            .filter(|path| {
+                Route::parse(path).is_some_and(|route| route.payload_type() == payload_type)
+            })
+            .cloned()
+            .collect();
+        for path in &stale {
+            responses.remove(path);
+        }
+        stale
+    });
+    for path in stale {
+        certification::delete(&asset_path(&path));
+    }
+}
+
+/// Path of a request URL, without the query string
+fn request_path(url: &str) -> &str {
+    url.split(['?', '#']).next().unwrap_or("/")
+}
+
+/// Serve a request from the certified responses
+///
+/// Responses that are not rendered yet are handed over to `handle_update`
+/// through an upgrade to an update call.
+pub fn handle_query(request: &HttpRequest) -> HttpResponse {
+    if request.method != "GET" && request.method != "HEAD" {
+        return HttpResponse::error(405, "Method not allowed");
+    }
+    let path = request_path(&request.url);
+    if Route::parse(path).is_none() {
+        return HttpResponse::error(404, "Not found");
+    }
+
+    // Responses served while the certified tree is rebuilt could not be certified
+    if certification::rebuilding() {
+        return HttpResponse::upgrade();
+    }
+    match RENDERED_RESPONSES.with_borrow(|responses| responses.get(path).cloned()) {
+        Some(rendered) => {
+            let mut response = ok_response(&rendered);
+            if let Some(header) = certificate_header(path) {
+                response.headers.push(header);
+            }
+            response
+        }
+        None => HttpResponse::upgrade(),
+    }
+}
+
+/// Charge one render of a route against its bucket
+///
+/// Buckets that are full again are dropped, so only the routes rendered
+/// within the last `RENDER_LIMIT` window are kept.
+fn admit_render(route: &Route) -> Result<(), IndexerError> {
+    let now = ic_cdk::api::time();
+    let key = route.render_key();
+    RENDER_BUCKETS.with_borrow_mut(|buckets| {
+        buckets.retain(|_, full_at| *full_at > now);
+        let full_at = buckets.get(&key).copied().unwrap_or(0);
+        let full_at = RENDER_LIMIT
+            .take(full_at, now, 1)
+            .map_err(|retry_at| rate_limit::rate_limited("renders", now, retry_at))?;
+        buckets.insert(key, full_at);
+        Ok(())
+    })
+}
+
+/// Render a response, keeping it certified for the following query calls
+///
+/// Gateway requests all come from the anonymous principal, so renders are
+/// capped per route rather than per caller: every page of a payload type,
+/// of a user's messages of a payload type, and every feed share one
+/// `RENDER_LIMIT` bucket. Renders over the cap are answered with status 429.
+pub fn handle_update(request: &HttpRequest) -> HttpResponse {
+    let path = request_path(&request.url);
+    let Some(route) = Route::parse(path) else {
+        return HttpResponse::error(404, "Not found");
+    };
+    if let Err(err) = admit_render(&route) {
+        return HttpResponse::error(429, &err.to_string());
+    }
+    let rendered = match render(&route) {
+        Ok(rendered) => rendered,
+        Err(message) => return HttpResponse::error(404, &message),
+    };
+
+    let evicted = RENDERED_RESPONSES.with_borrow_mut(|responses| {
+        let evicted = if responses.len() >= MAX_CACHED_RESPONSES && !responses.contains_key(path) {
+            responses.pop_first().map(|(evicted, _)| evicted)
+        } else {
+            None
+        };
+        responses.insert(path.to_string(), rendered.clone());
+        evicted
+    });
+    if let Some(evicted) = evicted {
+        certification::delete(&asset_path(&evicted));
+    }
+    certification::insert(&asset_path(path), Sha256::digest(&rendered.body).to_vec());
+    ok_response(&rendered)
+}
+
+fn ok_response(rendered: &RenderedBody) -> HttpResponse {
+    HttpResponse {
+        status_code: 200,
+        headers: vec![
+            ("Content-Type".to_string(), rendered.content_type.to_string()),
+            ("Access-Control-Allow-Origin".to_string(), "*".to_string()),
+        ],
+        body: ByteBuf::from(rendered.body.clone()),
+        upgrade: None,
+    }
+}
+
+/// `IC-Certificate` header proving the response hash of a path
+fn certificate_header(path: &str) -> Option<(String, String)> {
+    let certificate = certification::certificate()?;
+    let witness = certification::encode_witness(&certification::witness(&[asset_path(path)]));
+    Some((
+        "IC-Certificate".to_string(),
+        format!(
+            "certificate=:{}:, tree=:{}:",
+            STANDARD.encode(certificate),
+            STANDARD.encode(witness)
+        ),
+    ))
+}
+
+fn render(route: &Route) -> Result<RenderedBody, String> {
+    match route {
+        Route::TypePage { payload_type, before } => {
+            check_payload_type(payload_type)?;
+            let page =
+                data_storage::message::get_message_page(payload_type, HTTP_PAGE_SIZE, before.as_ref());
+            let (namespace, base_type) = namespace::split(payload_type);
+            Ok(render_page(
+                page,
+                &format!("{}/api/msgs/{}", namespace_prefix(namespace), base_type),
+            ))
+        }
+        Route::UserPage { principal, payload_type, before } => {
+            check_payload_type(payload_type)?;
+            let page = data_storage::message::get_message_page_by_pid(
+                payload_type,
+                *principal,
+                HTTP_PAGE_SIZE,
+                before.as_ref(),
+            );
+            let (namespace, base_type) = namespace::split(payload_type);
+            Ok(render_page(
+                page,
+                &format!(
+                    "{}/api/users/{}/msgs/{}",
+                    namespace_prefix(namespace),
+                    principal,
+                    base_type
+                ),
+            ))
+        }
+        Route::AtomFeed { namespace } => {
+            let namespace = namespace.as_deref();
+            check_payload_type(&namespace::qualify(namespace, ATOM_PAYLOAD_TYPE))?;
+            Ok(render_atom(namespace))
+        }
+    }
+}
+
+fn check_payload_type(payload_type: &str) -> Result<(), String> {
+    if data_storage::state::with(|processor| processor.payload_config(payload_type).is_some()) {
+        Ok(())
+    } else {
+        Err(format!("Unknown payload type: {}", payload_type))
+    }
+}
+
+fn render_page(page: MsgPage, base_path: &str) -> RenderedBody {
+    let next = page
+        .next_cursor
+        .as_ref()
+        .map(|cursor| format!("{}/before/{}", base_path, cursor));
+    let body = json!({
+        "messages": page
+            .messages
+            .iter()
+            .map(|(message, principal)| message_json(message, *principal))
+            .collect::<Vec<_>>(),
+        "next_cursor": page.next_cursor,
+        "next": next,
+    });
+
+    RenderedBody {
+        content_type: "application/json",
+        body: body.to_string().into_bytes(),
+    }
+}
+
+/// JSON form of a message; 64-bit numbers are strings, as JSON readers lose their precision
+fn message_json(message: &Message, principal: Principal) -> Value {
+    let payload = IDLArgs::from_bytes(&message.payload)
+        .ok()
+        .and_then(|args| args.args.first().map(idl_json))
+        .unwrap_or(Value::Null);
+    let resource = message.msg_resource.as_ref().map(|resource| {
+        json!({
+            "canister_id": resource.canister_id.to_text(),
+            "resource_type": resource.resource_type,
+            "resource_id": resource.resource_id.to_string(),
+        })
+    });
+
+    json!({
+        "payload_type": message.payload_type,
+        "msg_id": message.msg_id,
+        "msg_type": format!("{:?}", message.msg_type),
+        "msg_resource": resource,
+        "timestamp": message.timestamp.to_string(),
+        "caller": message.caller.to_text(),
+        "principal": principal.to_text(),
+        "payload": payload,
+        "verified": data_storage::signatures::verification(&message.payload_type, &message.msg_id)
+            .is_some(),
+    })
+}
+
+/// JSON form of a decoded Candid value
+fn idl_json(value: &IDLValue) -> Value {
+    match value {
+        IDLValue::Bool(value) => Value::Bool(*value),
+        IDLValue::Text(text) => Value::String(text.clone()),
+        IDLValue::Principal(principal) => Value::String(principal.to_text()),
+        IDLValue::Nat8(n) => json!(n),
+        IDLValue::Nat16(n) => json!(n),
+        IDLValue::Nat32(n) => json!(n),
+        IDLValue::Int8(n) => json!(n),
+        IDLValue::Int16(n) => json!(n),
+        IDLValue::Int32(n) => json!(n),
+        IDLValue::Float32(n) => json!(n),
+        IDLValue::Float64(n) => json!(n),
+        IDLValue::Nat64(n) => Value::String(n.to_string()),
+        IDLValue::Int64(n) => Value::String(n.to_string()),
+        IDLValue::Nat(n) => Value::String(n.to_string()),
+        IDLValue::Int(n) => Value::String(n.to_string()),
+        IDLValue::Opt(inner) => idl_json(inner),
+        IDLValue::Vec(values) => Value::Array(values.iter().map(idl_json).collect()),
+        IDLValue::Record(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|field| (label_name(&field.id), idl_json(&field.val)))
+                .collect::<Map<_, _>>(),
+        ),
+        IDLValue::Variant(variant) => {
+            let IDLField { id, val } = variant.0.as_ref();
+            let mut object = Map::new();
+            object.insert(label_name(id), idl_json(val));
+            Value::Object(object)
+        }
+        _ => Value::Null,
+    }
+}
+
+fn label_name(label: &Label) -> String {
+    match label {
+        Label::Named(name) => name.clone(),
+        Label::Id(id) | Label::Unnamed(id) => id.to_string(),
+    }
+}
+
+fn render_atom(namespace: Option<&str>) -> RenderedBody {
+    let payload_type = namespace::qualify(namespace, ATOM_PAYLOAD_TYPE);
+    let page = data_storage::message::get_message_page(&payload_type, ATOM_ENTRY_COUNT, None);
+    let canister_id = ic_cdk::id();
+    let (feed_id, title) = match namespace {
+        Some(namespace) => (format!("{}:posts", namespace), format!("Posts of {}", namespace)),
+        None => ("posts".to_string(), "Posts".to_string()),
+    };
+    let updated = page
+        .messages
+        .first()
+        .map(|(message, _)| message.timestamp)
+        .unwrap_or(0);
+
+    let mut feed = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    feed.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    feed.push_str(&format!("  <id>urn:ic:{}:{}</id>\n", canister_id, xml_escape(&feed_id)));
+    feed.push_str(&format!("  <title>{}</title>\n", xml_escape(&title)));
+    feed.push_str(&format!("  <updated>{}</updated>\n", rfc3339(updated)));
+    feed.push_str(&format!(
+        "  <link rel=\"self\" href=\"{}/feeds/posts.atom\"/>\n",
+        xml_escape(&namespace_prefix(namespace))
+    ));
+
+    for (message, _) in &page.messages {
+        let text = text_search::payload_texts(&message.payload).join("\n");
+        let title: String = text.chars().take(ATOM_TITLE_CHARS).collect();
+        feed.push_str("  <entry>\n");
+        feed.push_str(&format!(
+            "    <id>urn:ic:{}:{}:{}</id>\n",
+            canister_id,
+            xml_escape(&payload_type),
+            xml_escape(&message.msg_id)
+        ));
+        feed.push_str(&format!("    <title>{}</title>\n", xml_escape(&title)));
+        feed.push_str(&format!("    <updated>{}</updated>\n", rfc3339(message.timestamp)));
+        feed.push_str(&format!(
+            "    <author><name>{}</name></author>\n",
+            message.caller.to_text()
+        ));
+        feed.push_str(&format!("    <content type=\"text\">{}</content>\n", xml_escape(&text)));
+        feed.push_str("  </entry>\n");
+    }
+    feed.push_str("</feed>\n");
+
+    RenderedBody {
+        content_type: "application/atom+xml; charset=utf-8",
+        body: feed.into_bytes(),
+    }
+}
+
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // Control characters are not allowed in XML 1.0
+            c if c.is_control() && c != '\n' && c != '\t' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Format a timestamp in nanoseconds as an RFC 3339 UTC date-time
+fn rfc3339(timestamp: u64) -> String {
+    let secs = timestamp / 1_000_000_000;
+    let (days, day_secs) = (secs / 86_400, secs % 86_400);
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        day_secs / 3600,
+        day_secs % 3600 / 60,
+        day_secs % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::principal;
+
+    /// Paths map to their routes, with the namespace qualifying the payload type
+    #[test]
+    fn routes_parse_global_and_namespaced_paths() {
+        let cursor = MsgCursor { timestamp: 5, msg_id: "a".to_string(), payload_type: None };
+        let path = format!("/api/msgs/MsgUserPost/before/{}", cursor.encode());
+        assert!(matches!(
+            Route::parse(&path),
+            Some(Route::TypePage { payload_type, before: Some(before) })
+                if payload_type == "MsgUserPost" && before == cursor
+        ));
+
+        let path = format!("/ns/game:42/api/users/{}/msgs/MsgUserPost", principal(1));
+        assert!(matches!(
+            Route::parse(&path),
+            Some(Route::UserPage { principal: user, payload_type, before: None })
+                if user == principal(1) && payload_type == "game:42/MsgUserPost"
+        ));
+
+        let feed = Route::parse("/ns/game:42/feeds/posts.atom").unwrap();
+        assert_eq!(feed.payload_type(), "game:42/MsgUserPost");
+        assert_eq!(feed.render_key(), "feeds/game:42/MsgUserPost");
+    }
+
+    /// Unknown endpoints and malformed suffixes are not routed
+    #[test]
+    fn routes_reject_malformed_paths() {
+        for path in [
+            "/",
+            "/api/msgs",
+            "/api/msgs/MsgUserPost/after/00",
+            "/api/msgs/MsgUserPost/before/zz",
+            "/api/users/not-a-principal/msgs/MsgUserPost",
+            "/ns//feeds/posts.atom",
+            "/feeds/comments.atom",
+        ] {
+            assert!(Route::parse(path).is_none(), "{}", path);
+        }
+    }
+
+    /// All pages of a route share one render bucket
+    #[test]
+    fn pages_share_the_render_key() {
+        let first = Route::parse("/api/msgs/MsgUserPost").unwrap();
+        let cursor = MsgCursor { timestamp: 5, msg_id: "a".to_string(), payload_type: None };
+        let later = Route::parse(&format!("/api/msgs/MsgUserPost/before/{}", cursor.encode()));
+        assert_eq!(later.unwrap().render_key(), first.render_key());
+
+        let user = Route::parse(&format!("/api/users/{}/msgs/MsgUserPost", principal(2)));
+        assert_ne!(user.unwrap().render_key(), first.render_key());
+    }
+
+    /// Routes are matched on the path of the URL alone
+    #[test]
+    fn request_paths_drop_the_query() {
+        assert_eq!(request_path("/feeds/posts.atom?x=1#top"), "/feeds/posts.atom");
+        assert_eq!(request_path("/api/msgs/MsgUserPost"), "/api/msgs/MsgUserPost");
+    }
+
+    /// Feed text is valid XML and dates follow RFC 3339
+    #[test]
+    fn feeds_escape_text_and_format_dates() {
+        assert_eq!(
+            xml_escape("<a href=\"x\">&'\u{1}\n"),
+            "&lt;a href=&quot;x&quot;&gt;&amp;&apos;\n"
+        );
+        assert_eq!(rfc3339(0), "1970-01-01T00:00:00Z");
+        assert_eq!(rfc3339(951_782_400_000_000_000), "2000-02-29T00:00:00Z");
+        assert_eq!(rfc3339(1_700_000_000_123_000_000), "2023-11-14T22:13:20Z");
+    }
+}
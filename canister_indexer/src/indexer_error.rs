@@ -0,0 +1,100 @@
+use candid::{CandidType, Principal};
+use canister_types::message::MessageType;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Typed error returned by the indexer message ingestion endpoints
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug, PartialEq, Eq)]
+pub enum IndexerError {
+    /// The message failed basic structural validation
+    InvalidMessage(String),
+    /// The caller is not a registered publisher
+    PublisherNotRegistered(Principal),
+    /// The publisher is not allowed to submit this payload type
+    PayloadTypeNotAllowed(String),
+    /// The publisher is not allowed to submit this message type
+    MessageTypeNotAllowed(MessageType),
+    /// The caller is neither the creator of the message nor an admin
+    NotMessageOwner { payload_type: String, msg_id: String },
+    /// A different message with the same payload type and identifier already exists
+    DuplicateMessage { payload_type: String, msg_id: String },
+    /// No message exists for the given payload type and identifier
+    MessageNotFound { payload_type: String, msg_id: String },
+    /// The payload type is not known to the indexer
+    UnknownPayloadType(String),
+    /// The payload exceeds the maximum size of its payload type
+    PayloadTooLarge { size: u64, max_size: u64 },
+    /// The payload could not be decoded for its declared payload type
+    PayloadDecodeFailed(String),
+    /// A message inside a batch failed; `index` is its position in the batch
+    BatchItemFailed { index: u64, error: Box<IndexerError> },
+    /// The caller exceeded a rate limit or daily quota and may retry after the given delay
+    RateLimited { limit: String, retry_after_secs: u64 },
+    /// Writes are suspended while data is imported from another indexer
+    ImportInProgress,
+    /// Writes are suspended while the message stores are migrated after an upgrade
+    MigrationInProgress,
+    /// The signature does not verify against the author's trusted key
+    InvalidSignature(String),
+    /// The trusted keys of the author could not be fetched from the user canister
+    KeyLookupFailed(String),
+}
+
+impl fmt::Display for IndexerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IndexerError::InvalidMessage(reason) => write!(f, "Invalid message: {}", reason),
+            IndexerError::PublisherNotRegistered(caller) => {
+                write!(f, "Caller {} is not a registered publisher", caller)
+            }
+            IndexerError::PayloadTypeNotAllowed(payload_type) => {
+                write!(f, "Publisher is not allowed to submit payload type {}", payload_type)
+            }
+            IndexerError::MessageTypeNotAllowed(msg_type) => {
+                write!(f, "Publisher is not allowed to submit message type {:?}", msg_type)
+            }
+            IndexerError::NotMessageOwner { payload_type, msg_id } => write!(
+                f,
+                "Caller does not own message {} of type {}",
+                msg_id, payload_type
+            ),
+            IndexerError::DuplicateMessage { payload_type, msg_id } => write!(
+                f,
+                "A different message {} of type {} already exists",
+                msg_id, payload_type
+            ),
+            IndexerError::MessageNotFound { payload_type, msg_id } => {
+                write!(f, "Message {} of type {} not found", msg_id, payload_type)
+            }
+            IndexerError::UnknownPayloadType(payload_type) => {
+                write!(f, "Unknown payload type: {}", payload_type)
+            }
+            IndexerError::PayloadTooLarge { size, max_size } => write!(
+                f,
+                "Payload of {} bytes exceeds the maximum of {} bytes",
+                size, max_size
+            ),
+            IndexerError::PayloadDecodeFailed(reason) => {
+                write!(f, "Failed to decode payload: {}", reason)
+            }
+            IndexerError::BatchItemFailed { index, error } => {
+                write!(f, "Failed to process message at index {}: {}", index, error)
+            }
+            IndexerError::RateLimited { limit, retry_after_secs } => write!(
+                f,
+                "Rate limit exceeded ({}), retry in {} seconds",
+                limit, retry_after_secs
+            ),
+            IndexerError::ImportInProgress => {
+                write!(f, "Writes are suspended while data is being imported")
+            }
+            IndexerError::MigrationInProgress => {
+                write!(f, "Writes are suspended while the message stores are being migrated")
+            }
+            IndexerError::InvalidSignature(reason) => write!(f, "Invalid signature: {}", reason),
+            IndexerError::KeyLookupFailed(reason) => {
+                write!(f, "Failed to fetch the trusted keys: {}", reason)
+            }
+        }
+    }
+}
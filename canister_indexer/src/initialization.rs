@@ -1,6 +1,6 @@
 use canister_types::indexer::CanisterArgs;
 
-use crate::data_storage;
+use crate::{certification, data_storage};
 
 /// Initialize the indexer canister with provided arguments
 /// 
@@ -32,10 +32,17 @@ fn initialize_system(args: Option<CanisterArgs>) {
                 
                 // Initialize participant count to zero
                 processor.participant_count = 0;
+
+                // A fresh canister starts with the current storage layout
+                processor.storage_version = data_storage::CURRENT_STORAGE_VERSION;
             });
             
             // Persist the initialized state
             data_storage::state::save();
+
+            // Start the recurring jobs, such as the retention enforcement
+            data_storage::scheduler::rearm();
+            certification::publish();
         }
         Some(CanisterArgs::Upgrade(_)) => {
             ic_cdk::trap(
@@ -60,8 +67,9 @@ fn before_upgrade() {
 /// Post-upgrade hook to restore and update state after upgrade
 /// 
 /// This function handles the state restoration and optional parameter updates
-/// after a canister upgrade. It loads the saved state and applies any
-/// new configuration parameters provided in the upgrade arguments.
+/// after a canister upgrade. It loads the saved state, starts the migration
+/// of the message stores to the current storage layout and applies any new
+/// configuration parameters provided in the upgrade arguments.
 /// 
 /// # Arguments
 /// * `upgrade_args` - Optional upgrade arguments containing new configuration
@@ -72,6 +80,18 @@ fn before_upgrade() {
 fn after_upgrade(upgrade_args: Option<CanisterArgs>) {
     // Restore state from stable storage
     data_storage::state::load();
+
+    // Start migrating message stores written by older versions of the canister
+    data_storage::migration::run();
+
+    // The certified tree lives on the heap, rebuild it from the stored messages
+    data_storage::migration::start_certification();
+
+    // Timers are dropped by the upgrade, re-arm the scheduled jobs
+    data_storage::scheduler::rearm();
+
+    // Built-in payload types may have a new schema, re-encode the stored messages
+    data_storage::schema::sync_builtin_versions();
     
     match upgrade_args {
         Some(CanisterArgs::Upgrade(upgrade_params)) => {
@@ -0,0 +1,171 @@
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::RETENTION_CHECK_INTERVAL_SECS;
+
+/// Background job run by the scheduler, see `data_storage::scheduler`
+#[derive(CandidType, Clone, Copy, Deserialize, Serialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Job {
+    /// Archive and delete messages according to the retention policies
+    Retention,
+    /// Archive live messages above the archive threshold of their payload
+    /// type and spill the local archive into archive canisters
+    Cleanup,
+    /// Deliver logged events to subscribers
+    Delivery,
+    /// Re-encode stored payloads on an older schema version of their payload type
+    SchemaMigration,
+    /// Delete the comments left in the threads of deleted messages
+    ThreadDeletion,
+    /// Migrate the message stores to the current storage layout and
+    /// backfill the indexes added since the stored layout
+    StorageMigration,
+    /// Rebuild the certified tree from the message stores after an upgrade
+    Certification,
+}
+
+impl Job {
+    pub const ALL: [Job; 7] = [
+        Job::Retention,
+        Job::Cleanup,
+        Job::Delivery,
+        Job::SchemaMigration,
+        Job::ThreadDeletion,
+        Job::StorageMigration,
+        Job::Certification,
+    ];
+
+    /// Interval between two runs of a recurring job, None for jobs run on demand
+    pub fn interval(&self) -> Option<Duration> {
+        match self {
+            Job::Retention => Some(Duration::from_secs(RETENTION_CHECK_INTERVAL_SECS)),
+            Job::Cleanup
+            | Job::Delivery
+            | Job::SchemaMigration
+            | Job::ThreadDeletion
+            | Job::StorageMigration
+            | Job::Certification => None,
+        }
+    }
+}
+
+/// Outcome of a job run
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug, PartialEq, Eq)]
+pub enum JobRunStatus {
+    Succeeded,
+    Failed(String),
+    /// The run was cut short by an upgrade or a trap and did not finish
+    Interrupted,
+}
+
+/// Schedule and run history of a job, kept in stable memory
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug)]
+pub struct JobState {
+    pub job: Job,
+    /// Time the next run is due, None if no run is scheduled
+    pub next_run_at: Option<u64>,
+    /// Start time of the current run, None if the job is not running
+    pub running_since: Option<u64>,
+    /// Whether a run was requested while the job was running
+    pub rerun_pending: bool,
+    pub last_started_at: Option<u64>,
+    pub last_finished_at: Option<u64>,
+    pub last_status: Option<JobRunStatus>,
+    pub run_count: u64,
+    pub failure_count: u64,
+}
+
+impl JobState {
+    pub fn new(job: Job) -> Self {
+        Self {
+            job,
+            next_run_at: None,
+            running_since: None,
+            rerun_pending: false,
+            last_started_at: None,
+            last_finished_at: None,
+            last_status: None,
+            run_count: 0,
+            failure_count: 0,
+        }
+    }
+
+    /// Record the end of the current run
+    pub fn finish(&mut self, status: JobRunStatus, now: u64) {
+        if status != JobRunStatus::Succeeded {
+            self.failure_count += 1;
+        }
+        self.running_since = None;
+        self.last_finished_at = Some(now);
+        self.last_status = Some(status);
+        self.run_count += 1;
+    }
+
+    /// Restore the schedule of the job after its timer was dropped by an upgrade
+    ///
+    /// A run cut short by the upgrade is recorded as interrupted and due
+    /// right away; a recurring job without a pending run is due after its
+    /// interval.
+    ///
+    /// # Returns
+    /// The time the next run is due, None if no run is scheduled
+    pub fn rearm(&mut self, now: u64) -> Option<u64> {
+        if self.running_since.is_some() {
+            self.finish(JobRunStatus::Interrupted, now);
+            self.next_run_at = Some(now);
+        }
+        if self.next_run_at.is_none() {
+            self.next_run_at = self
+                .job
+                .interval()
+                .map(|interval| now.saturating_add(interval.as_nanos() as u64));
+        }
+        self.next_run_at
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_000_000_000_000;
+
+    /// Runs interrupted by an upgrade are recorded and started again right away
+    #[test]
+    fn rearm_restarts_interrupted_runs() {
+        let mut state = JobState::new(Job::Cleanup);
+        state.running_since = Some(NOW - 5);
+
+        assert_eq!(state.rearm(NOW), Some(NOW));
+        assert_eq!(state.running_since, None);
+        assert_eq!(state.last_status, Some(JobRunStatus::Interrupted));
+        assert_eq!((state.run_count, state.failure_count), (1, 1));
+    }
+
+    /// Pending runs keep their time, and only recurring jobs get a new one
+    #[test]
+    fn rearm_keeps_or_creates_the_next_run() {
+        let mut pending = JobState::new(Job::Delivery);
+        pending.next_run_at = Some(NOW + 7);
+        assert_eq!(pending.rearm(NOW), Some(NOW + 7));
+
+        let mut idle = JobState::new(Job::Delivery);
+        assert_eq!(idle.rearm(NOW), None);
+
+        let mut recurring = JobState::new(Job::Retention);
+        let interval = RETENTION_CHECK_INTERVAL_SECS * 1_000_000_000;
+        assert_eq!(recurring.rearm(NOW), Some(NOW + interval));
+        assert_eq!(recurring.last_status, None);
+    }
+
+    #[test]
+    fn failed_runs_are_counted() {
+        let mut state = JobState::new(Job::Retention);
+        state.finish(JobRunStatus::Succeeded, NOW);
+        state.finish(JobRunStatus::Failed("trapped".to_string()), NOW + 1);
+
+        assert_eq!((state.run_count, state.failure_count), (2, 1));
+        assert_eq!(state.last_finished_at, Some(NOW + 1));
+    }
+}
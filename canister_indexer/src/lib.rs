@@ -4,8 +4,31 @@ mod cycles_handler;
 mod initialization;
 mod query_operations;
 mod update_operations;
+mod admin_operations;
+mod archive_manager;
+mod subscription_manager;
 pub mod candid_generator;
+mod access_control;
+mod analytics;
+mod certification;
 mod data_storage;
+mod data_transfer;
+mod discussion;
+mod http_gateway;
+mod indexer_error;
+mod jobs;
+mod moderation;
+mod msg_key;
+mod namespace;
+mod pagination;
+mod payload_registry;
+mod payload_schema;
+mod rate_limit;
+mod signatures;
+mod storable;
+mod text_search;
+#[cfg(test)]
+mod test_fixtures;
 
 export_candid!();
 
@@ -14,3 +37,29 @@ pub const MAX_HISTORY_MSG_COUNT: u64 = 20000;
 pub const ARCHIVE_MSG_DEFAULT_CYCLES: u128 = 1_000_000_000_000;
 pub const ARCHIVE_MSG_THRESHOLD: usize = 5000;
 pub const ARCHIVE_MSG_MIGRATION_SIZE: usize = 500;
+/// Maximum number of messages added to the certified tree per rebuild batch
+pub const CERTIFY_BATCH_SIZE: usize = 2000;
+/// Local archive size of a payload type above which messages move to archive canisters
+pub const ARCHIVE_SPILL_THRESHOLD: u64 = MAX_HISTORY_MSG_COUNT;
+/// Maximum number of messages handed over to a single archive canister
+pub const ARCHIVE_CANISTER_CAPACITY: u64 = 2_000_000;
+/// Interval between two runs of the retention policy enforcement
+pub const RETENTION_CHECK_INTERVAL_SECS: u64 = 60 * 60;
+/// Delay before a requested cleanup run, and between runs while it keeps moving messages
+pub const CLEANUP_DELAY_SECS: u64 = 10;
+/// Time the trusted keys fetched from the user canister are cached
+pub const TRUSTED_KEY_TTL_SECS: u64 = 15 * 60;
+/// Maximum number of principals a principal can follow
+pub const MAX_FOLLOWING: u64 = 1000;
+/// Maximum number of subscriber canisters
+pub const MAX_SUBSCRIPTIONS: u64 = 100;
+/// Time a subscriber has to respond to a delivery before the call fails
+pub const SUBSCRIBER_CALL_TIMEOUT_SECS: u32 = 60;
+/// Maximum number of events delivered to a subscriber in one call
+pub const SUBSCRIPTION_BATCH_SIZE: usize = 100;
+/// Maximum number of logged events examined per subscriber and delivery run
+pub const SUBSCRIPTION_SCAN_SIZE: usize = 1000;
+/// Delay before the first retry of a failed delivery, doubled on every failure
+pub const DELIVERY_RETRY_BASE_SECS: u64 = 10;
+/// Consecutive failed deliveries after which a subscription is dead-lettered
+pub const MAX_DELIVERY_FAILURES: u32 = 10;
@@ -0,0 +1,97 @@
+use candid::{CandidType, Principal};
+use canister_types::message::Message;
+use serde::{Deserialize, Serialize};
+
+/// Maximum length of a report or moderation reason, in characters
+pub const MAX_REASON_CHARS: usize = 500;
+/// Maximum number of reports returned with a queued message
+pub const MAX_LISTED_REPORTS: usize = 20;
+/// Maximum number of queued messages returned by a single query
+pub const MAX_QUEUE_PAGE_SIZE: usize = 100;
+
+/// Action a moderator takes on a message
+#[derive(CandidType, Clone, Copy, Deserialize, Serialize, Debug, PartialEq, Eq)]
+pub enum ModerationAction {
+    /// Exclude the message from list queries
+    Hide,
+    /// Show a hidden message again
+    Unhide,
+    /// Delete the message and its comments
+    Delete,
+}
+
+/// A moderation action with the reason recorded for it
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug)]
+pub struct ModerationLogEntry {
+    pub action: ModerationAction,
+    pub moderator: Principal,
+    pub reason: String,
+    pub timestamp: u64,
+}
+
+/// Moderation history of a message
+///
+/// Records outlive deleted messages so that the actions taken stay auditable.
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug)]
+pub struct ModerationRecord {
+    pub payload_type: String,
+    pub msg_id: String,
+    pub hidden: bool,
+    /// Reports received since the last moderation action
+    pub open_reports: u32,
+    /// Reports received in total
+    pub total_reports: u32,
+    /// Time of the oldest open report, if any
+    pub queued_at: Option<u64>,
+    /// Actions taken, oldest first
+    pub actions: Vec<ModerationLogEntry>,
+}
+
+impl ModerationRecord {
+    pub fn new(payload_type: &str, msg_id: &str) -> Self {
+        Self {
+            payload_type: payload_type.to_string(),
+            msg_id: msg_id.to_string(),
+            hidden: false,
+            open_reports: 0,
+            total_reports: 0,
+            queued_at: None,
+            actions: Vec::new(),
+        }
+    }
+}
+
+/// A user report against a message
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug)]
+pub struct MsgReport {
+    pub reporter: Principal,
+    pub reason: String,
+    pub timestamp: u64,
+}
+
+/// A reported message awaiting review
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug)]
+pub struct ModerationQueueItem {
+    pub message: Message,
+    pub principal: Principal,
+    pub record: ModerationRecord,
+    /// Newest reports first, at most `MAX_LISTED_REPORTS`
+    pub reports: Vec<MsgReport>,
+}
+
+/// One page of the moderation queue, oldest report first
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug)]
+pub struct ModerationQueuePage {
+    pub items: Vec<ModerationQueueItem>,
+    /// Cursor to pass back to fetch the next page, if any
+    pub next_cursor: Option<String>,
+}
+
+/// Check that a report or moderation reason is non-empty and not too long
+pub fn validate_reason(reason: &str) -> Result<(), String> {
+    let chars = reason.trim().chars().count();
+    if chars == 0 || chars > MAX_REASON_CHARS {
+        return Err(format!("A reason must be 1 to {} characters", MAX_REASON_CHARS));
+    }
+    Ok(())
+}
@@ -0,0 +1,64 @@
+//! Storage keys of messages, shared with `canister_archive`
+//!
+//! The archive canister includes this file, so that the messages handed over
+//! to it are stored and paginated under the same keys as in the indexer.
+
+use candid::CandidType;
+use canister_types::message::Message;
+use serde::{Deserialize, Serialize};
+
+use crate::storable::cbor_storable;
+
+/// Primary key of a stored message, ordered by payload type, timestamp and id
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MsgKey {
+    pub payload_type: String,
+    pub timestamp: u64,
+    pub msg_id: String,
+}
+
+cbor_storable!(MsgKey);
+
+impl MsgKey {
+    /// Build the key under which a message is stored
+    pub fn of(message: &Message) -> Self {
+        Self {
+            payload_type: message.payload_type.clone(),
+            timestamp: message.timestamp,
+            msg_id: message.msg_id.clone(),
+        }
+    }
+
+    /// Smallest possible key of a payload type at the given timestamp
+    pub fn lower_bound(payload_type: &str, timestamp: u64) -> Self {
+        Self {
+            payload_type: payload_type.to_string(),
+            timestamp,
+            msg_id: String::new(),
+        }
+    }
+
+    /// Exclusive upper bound of all keys belonging to a payload type
+    pub fn type_upper_bound(payload_type: &str) -> Self {
+        // "\0" is the smallest suffix, so every key of `payload_type` sorts below this one
+        Self::lower_bound(&format!("{}\0", payload_type), 0)
+    }
+}
+
+/// Secondary key locating a message by payload type and message id
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MsgIdKey {
+    pub payload_type: String,
+    pub msg_id: String,
+}
+
+cbor_storable!(MsgIdKey);
+
+impl MsgIdKey {
+    pub fn new(payload_type: &str, msg_id: &str) -> Self {
+        Self {
+            payload_type: payload_type.to_string(),
+            msg_id: msg_id.to_string(),
+        }
+    }
+}
@@ -0,0 +1,178 @@
+//! Namespaces isolating the messages of one game or tenant
+//!
+//! A message belongs to a namespace when its payload type is qualified with
+//! the namespace key, e.g. `game:42/MsgUserPost`. Since every storage key,
+//! index and counter includes the payload type, the messages of a namespace
+//! are kept apart from the global messages and from other namespaces, and
+//! every query by payload type only sees one namespace. Comments live in the
+//! namespace of the message they reply to.
+
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::{payload_registry::RetentionPolicy, rate_limit::RateLimitConfig};
+
+/// Separator between the namespace key and the payload type
+pub const NAMESPACE_SEPARATOR: char = '/';
+/// Maximum length of a tenant name, in characters
+pub const MAX_TENANT_CHARS: usize = 64;
+
+/// Identity of a namespace
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug, PartialEq, Eq)]
+pub enum NamespaceId {
+    /// A game registered on the platform, by game id
+    Game(u64),
+    /// A free-form tenant name
+    Tenant(String),
+}
+
+impl NamespaceId {
+    /// Key qualifying the payload types of the namespace: `game:<id>` or `tenant:<name>`
+    pub fn key(&self) -> String {
+        match self {
+            NamespaceId::Game(game_id) => format!("game:{}", game_id),
+            NamespaceId::Tenant(name) => format!("tenant:{}", name),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if let NamespaceId::Tenant(name) = self {
+            let chars = name.chars().count();
+            if chars == 0 || chars > MAX_TENANT_CHARS {
+                return Err(format!("A tenant name must be 1 to {} characters", MAX_TENANT_CHARS));
+            }
+            if name.chars().any(|c| c == NAMESPACE_SEPARATOR || c.is_whitespace()) {
+                return Err(format!(
+                    "A tenant name cannot contain whitespace or '{}'",
+                    NAMESPACE_SEPARATOR
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Settings of a namespace
+///
+/// Payload types are shared with the global registry; a namespace only
+/// overrides how long its messages are kept and how fast they may be written.
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug, PartialEq, Eq)]
+pub struct NamespaceConfig {
+    pub id: NamespaceId,
+    /// Retention policies replacing the registry's, keyed by unqualified payload type
+    pub retention: BTreeMap<String, RetentionPolicy>,
+    /// Limits applied to the writes of the namespace instead of the global limits,
+    /// with `per_payload_type` keyed by unqualified payload type
+    pub rate_limits: Option<RateLimitConfig>,
+}
+
+impl NamespaceConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        self.id.validate()?;
+        for (payload_type, retention) in &self.retention {
+            retention
+                .validate()
+                .map_err(|err| format!("{}: {}", payload_type, err))?;
+        }
+        if let Some(rate_limits) = &self.rate_limits {
+            rate_limits.validate()?;
+        }
+        Ok(())
+    }
+}
+
+/// Split a payload type into its namespace key, if any, and its unqualified name
+pub fn split(payload_type: &str) -> (Option<&str>, &str) {
+    match payload_type.split_once(NAMESPACE_SEPARATOR) {
+        Some((namespace, base_type)) => (Some(namespace), base_type),
+        None => (None, payload_type),
+    }
+}
+
+/// Namespace key of a payload type, None for global payload types
+pub fn namespace_of(payload_type: &str) -> Option<&str> {
+    split(payload_type).0
+}
+
+/// Payload type without its namespace qualifier
+pub fn base_type(payload_type: &str) -> &str {
+    split(payload_type).1
+}
+
+/// Payload type of `base_type` in a namespace, or the global one for None
+pub fn qualify(namespace: Option<&str>, base_type: &str) -> String {
+    match namespace {
+        Some(namespace) => format!("{}{}{}", namespace, NAMESPACE_SEPARATOR, base_type),
+        None => base_type.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::msg_key::MsgKey;
+
+    #[test]
+    fn payload_types_split_and_qualify() {
+        assert_eq!(
+            split("game:42/MsgUserPost"),
+            (Some("game:42"), "MsgUserPost")
+        );
+        assert_eq!(split("MsgUserPost"), (None, "MsgUserPost"));
+        assert_eq!(namespace_of("tenant:acme/MsgComment"), Some("tenant:acme"));
+        assert_eq!(base_type("tenant:acme/MsgComment"), "MsgComment");
+
+        let key = NamespaceId::Game(42).key();
+        assert_eq!(key, "game:42");
+        assert_eq!(qualify(Some(&key), "MsgUserPost"), "game:42/MsgUserPost");
+        assert_eq!(qualify(None, "MsgUserPost"), "MsgUserPost");
+    }
+
+    #[test]
+    fn tenant_names_are_validated() {
+        assert!(NamespaceId::Game(0).validate().is_ok());
+        assert!(NamespaceId::Tenant("acme".to_string()).validate().is_ok());
+        assert!(NamespaceId::Tenant("x".repeat(MAX_TENANT_CHARS))
+            .validate()
+            .is_ok());
+
+        for name in [
+            String::new(),
+            "x".repeat(MAX_TENANT_CHARS + 1),
+            "a/b".into(),
+            "a b".into(),
+        ] {
+            assert!(
+                NamespaceId::Tenant(name.clone()).validate().is_err(),
+                "accepted {:?}",
+                name
+            );
+        }
+    }
+
+    /// The stored messages of a payload type in one namespace never fall into
+    /// the key range of the same type in another namespace or globally
+    #[test]
+    fn namespaced_keys_do_not_overlap() {
+        let types = [
+            "MsgUserPost".to_string(),
+            qualify(Some("game:1"), "MsgUserPost"),
+            qualify(Some("game:10"), "MsgUserPost"),
+            qualify(Some("game:1"), "MsgUserPosts"),
+        ];
+        for payload_type in &types {
+            let range =
+                MsgKey::lower_bound(payload_type, 0)..MsgKey::type_upper_bound(payload_type);
+            for other in types.iter().filter(|other| *other != payload_type) {
+                let key = MsgKey {
+                    payload_type: other.clone(),
+                    timestamp: u64::MAX,
+                    msg_id: "\u{10FFFF}".to_string(),
+                };
+                assert!(!range.contains(&key), "{} overlaps {}", other, payload_type);
+                assert!(!range.contains(&MsgKey::lower_bound(other, 0)));
+            }
+        }
+    }
+}
@@ -0,0 +1,277 @@
+use candid::{CandidType, Principal};
+use canister_types::message::Message;
+use serde::{Deserialize, Serialize};
+
+/// Position of a message in a timestamp-ordered listing
+///
+/// Clients only ever see the encoded form returned by `encode`; the layout
+/// (8-byte big-endian timestamp followed by the UTF-8 message id, hex encoded,
+/// then `.` and the hex encoded payload type if known) is not part of the
+/// public interface.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MsgCursor {
+    pub timestamp: u64,
+    pub msg_id: String,
+    /// Payload type of the message, ordering messages with the same timestamp
+    /// and id in listings that merge several payload types
+    pub payload_type: Option<String>,
+}
+
+impl MsgCursor {
+    /// Cursor pointing at the given message
+    pub fn of(message: &Message) -> Self {
+        Self {
+            timestamp: message.timestamp,
+            msg_id: message.msg_id.clone(),
+            payload_type: Some(message.payload_type.clone()),
+        }
+    }
+
+    /// Encode the cursor into an opaque token
+    pub fn encode(&self) -> String {
+        let mut bytes = Vec::with_capacity(8 + self.msg_id.len());
+        bytes.extend_from_slice(&self.timestamp.to_be_bytes());
+        bytes.extend_from_slice(self.msg_id.as_bytes());
+        match &self.payload_type {
+            Some(payload_type) => format!("{}.{}", hex(&bytes), hex(payload_type.as_bytes())),
+            None => hex(&bytes),
+        }
+    }
+
+    /// Decode a token produced by `encode`
+    pub fn decode(token: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid cursor: {}", token);
+
+        let (position, payload_type) = match token.split_once('.') {
+            Some((position, payload_type)) => (position, Some(payload_type)),
+            None => (token, None),
+        };
+        if position.len() < 16 {
+            return Err(invalid());
+        }
+        let bytes = unhex(position).ok_or_else(invalid)?;
+        let payload_type = payload_type
+            .map(|payload_type| {
+                unhex(payload_type)
+                    .and_then(|bytes| String::from_utf8(bytes).ok())
+                    .ok_or_else(invalid)
+            })
+            .transpose()?;
+
+        let (timestamp, msg_id) = bytes.split_at(8);
+        Ok(Self {
+            timestamp: u64::from_be_bytes(timestamp.try_into().map_err(|_| invalid())?),
+            msg_id: String::from_utf8(msg_id.to_vec()).map_err(|_| invalid())?,
+            payload_type,
+        })
+    }
+
+    /// Decode an optional token as passed to the paginated queries
+    pub fn decode_opt(token: Option<String>) -> Result<Option<Self>, String> {
+        token.as_deref().map(Self::decode).transpose()
+    }
+
+    /// Exclusive upper bound on the message ids at the cursor's timestamp when
+    /// listing `payload_type` after the cursor
+    ///
+    /// Listings merging several payload types order messages with the same
+    /// timestamp and id by payload type, so the message with the cursor's id
+    /// still follows in a payload type ordered before the cursor's.
+    pub fn msg_id_bound(&self, payload_type: &str) -> String {
+        match &self.payload_type {
+            Some(cursor_type) if payload_type < cursor_type.as_str() => {
+                format!("{}\0", self.msg_id)
+            }
+            _ => self.msg_id.clone(),
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unhex(token: &str) -> Option<Vec<u8>> {
+    if token.len() % 2 != 0 || !token.is_ascii() {
+        return None;
+    }
+    (0..token.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&token[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// One page of a cursor-paginated message listing
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug)]
+pub struct MsgPage {
+    /// Messages of this page, newest first
+    pub messages: Vec<(Message, Principal)>,
+    /// Cursor to pass back to fetch the next (older) page, if any
+    pub next_cursor: Option<String>,
+}
+
+impl MsgPage {
+    /// Build a page from up to `limit + 1` fetched messages
+    ///
+    /// The extra message only signals that another page exists and is dropped.
+    pub fn from_fetched(mut messages: Vec<(Message, Principal)>, limit: usize) -> Self {
+        let has_more = messages.len() > limit;
+        messages.truncate(limit);
+
+        let next_cursor = if has_more {
+            messages.last().map(|(message, _)| MsgCursor::of(message).encode())
+        } else {
+            None
+        };
+
+        Self { messages, next_cursor }
+    }
+}
+
+/// Maximum number of payload types a timeline query may merge
+pub const MAX_TIMELINE_TYPES: usize = 16;
+
+/// Query across several payload types within a time window
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug)]
+pub struct TimelineQuery {
+    /// Payload types to merge into one timeline
+    pub payload_types: Vec<String>,
+    /// Only list messages created by this principal
+    pub principal: Option<Principal>,
+    /// Inclusive lower bound of the message timestamps
+    pub from_timestamp: u64,
+    /// Exclusive upper bound of the message timestamps
+    pub to_timestamp: u64,
+    pub max_count: usize,
+    /// `next_cursor` of the previous page, or None for the first page
+    pub cursor: Option<String>,
+}
+
+impl TimelineQuery {
+    /// Check the time window and deduplicate the payload types
+    pub fn normalize(mut self) -> Result<Self, String> {
+        if self.from_timestamp >= self.to_timestamp {
+            return Err("from_timestamp must be lower than to_timestamp".to_string());
+        }
+
+        self.payload_types.sort();
+        self.payload_types.dedup();
+        if self.payload_types.is_empty() {
+            return Err("At least one payload type is required".to_string());
+        }
+        if self.payload_types.len() > MAX_TIMELINE_TYPES {
+            return Err(format!(
+                "At most {} payload types can be queried at once",
+                MAX_TIMELINE_TYPES
+            ));
+        }
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::post;
+    use canister_types::message::MessageType;
+
+    fn cursor(payload_type: Option<&str>) -> MsgCursor {
+        MsgCursor {
+            timestamp: 1_700_000_000_000_000_000,
+            msg_id: "post-1".to_string(),
+            payload_type: payload_type.map(str::to_string),
+        }
+    }
+
+    /// Cursors decode to the position they were encoded from
+    #[test]
+    fn cursor_round_trips() {
+        for cursor in [cursor(None), cursor(Some("game:7/MsgUserPost"))] {
+            assert_eq!(MsgCursor::decode(&cursor.encode()), Ok(cursor));
+        }
+        assert_eq!(MsgCursor::decode_opt(None), Ok(None));
+    }
+
+    /// Malformed tokens are rejected instead of panicking
+    #[test]
+    fn decode_rejects_malformed_tokens() {
+        let valid = cursor(Some("MsgUserPost")).encode();
+        let (position, _) = valid.split_once('.').unwrap();
+
+        let malformed = [
+            String::new(),
+            "00".to_string(),
+            "000000000000000".to_string(),
+            "zz00000000000000".to_string(),
+            "000000000000000é0".to_string(),
+            format!("{}.0", position),
+            format!("{}.é0", position),
+            format!("{}.ff", position),
+        ];
+        for token in &malformed {
+            assert!(MsgCursor::decode(token).is_err(), "accepted {:?}", token);
+        }
+    }
+
+    /// The cursor's message id is still listed in payload types ordered
+    /// before the cursor's, and excluded from the others
+    #[test]
+    fn msg_id_bound_follows_payload_type_order() {
+        let cursor = cursor(Some("MsgUserPost"));
+        assert_eq!(cursor.msg_id_bound("MsgSharePlay"), "post-1\0");
+        assert_eq!(cursor.msg_id_bound("MsgUserPost"), "post-1");
+        assert_eq!(cursor.msg_id_bound("MsgUserPosts"), "post-1");
+
+        let untyped = MsgCursor {
+            payload_type: None,
+            ..cursor
+        };
+        assert_eq!(untyped.msg_id_bound("MsgSharePlay"), "post-1");
+    }
+
+    fn timeline(payload_types: &[&str], from_timestamp: u64, to_timestamp: u64) -> TimelineQuery {
+        TimelineQuery {
+            payload_types: payload_types.iter().map(|name| name.to_string()).collect(),
+            principal: None,
+            from_timestamp,
+            to_timestamp,
+            max_count: 10,
+            cursor: None,
+        }
+    }
+
+    /// Payload types are deduplicated and the time window must not be empty
+    #[test]
+    fn timeline_queries_are_normalized() {
+        let query = timeline(&["MsgUserPost", "MsgSharePlay", "MsgUserPost"], 1, 2)
+            .normalize()
+            .unwrap();
+        assert_eq!(query.payload_types, ["MsgSharePlay", "MsgUserPost"]);
+
+        assert!(timeline(&["MsgUserPost"], 2, 2).normalize().is_err());
+        assert!(timeline(&[], 1, 2).normalize().is_err());
+        let many: Vec<String> = (0..=MAX_TIMELINE_TYPES).map(|n| format!("Type{}", n)).collect();
+        let many: Vec<&str> = many.iter().map(String::as_str).collect();
+        assert!(timeline(&many, 1, 2).normalize().is_err());
+    }
+
+    /// The cursor of a page is the last message kept, and only set if more follow
+    #[test]
+    fn pages_keep_the_limit_and_point_past_it() {
+        let fetched: Vec<(Message, Principal)> = (0..3u64)
+            .map(|n| {
+                let message = Message {
+                    timestamp: 3 - n,
+                    msg_id: format!("post-{}", n),
+                    ..post("", MessageType::Create, Principal::anonymous())
+                };
+                (message, Principal::anonymous())
+            })
+            .collect();
+
+        let page = MsgPage::from_fetched(fetched.clone(), 2);
+        assert_eq!(page.messages.len(), 2);
+        assert_eq!(page.next_cursor, Some(MsgCursor::of(&fetched[1].0).encode()));
+        assert_eq!(MsgPage::from_fetched(fetched, 3).next_cursor, None);
+    }
+}
@@ -0,0 +1,306 @@
+use candid::{CandidType, Decode};
+use canister_types::message::{Message, MessageType, MsgSharePlay, MsgUserInfo, MsgUserPost};
+use ic_cdk::print;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{
+    discussion::{MsgComment, COMMENT_PAYLOAD_TYPE},
+    indexer_error::IndexerError,
+    namespace,
+    payload_schema::{BUILTIN_SCHEMA_VERSIONS, INITIAL_SCHEMA_VERSION},
+    ARCHIVE_MSG_THRESHOLD,
+};
+
+/// Default maximum payload size of the built-in payload types, in bytes
+pub const DEFAULT_MAX_PAYLOAD_SIZE: u64 = 64 * 1024;
+
+/// Days after which `MsgSharePlay` messages are deleted
+pub const SHARE_PLAY_EXPIRY_DAYS: u64 = 7;
+
+const NANOS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+/// Lifecycle rules applied to the messages of a payload type
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// Live messages above this count are moved to the archive, oldest first;
+    /// None keeps every message live
+    pub archive_threshold: Option<u64>,
+    /// Messages younger than this many days are never archived by `archive_threshold`
+    #[serde(default)]
+    pub hot_days: Option<u64>,
+    /// Live messages older than this many days are moved to the archive
+    #[serde(default)]
+    pub archive_after_days: Option<u64>,
+    /// Messages older than this many days are deleted from both stores;
+    /// None keeps messages forever
+    #[serde(default)]
+    pub delete_after_days: Option<u64>,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self {
+            archive_threshold: Some(ARCHIVE_MSG_THRESHOLD as u64),
+            hot_days: None,
+            archive_after_days: None,
+            delete_after_days: None,
+        }
+    }
+}
+
+impl RetentionPolicy {
+    /// Whether any of the age based rules is set
+    pub fn has_age_rules(&self) -> bool {
+        self.hot_days.is_some()
+            || self.archive_after_days.is_some()
+            || self.delete_after_days.is_some()
+    }
+
+    /// Timestamp below which messages are no longer hot
+    pub fn hot_cutoff(&self, now: u64) -> u64 {
+        cutoff(now, self.hot_days).unwrap_or(u64::MAX)
+    }
+
+    /// Timestamp below which live messages are archived, if archiving by age is enabled
+    pub fn archive_cutoff(&self, now: u64) -> Option<u64> {
+        cutoff(now, self.archive_after_days)
+    }
+
+    /// Timestamp below which messages are deleted, if expiry is enabled
+    pub fn delete_cutoff(&self, now: u64) -> Option<u64> {
+        cutoff(now, self.delete_after_days)
+    }
+
+    /// Check that the age based rules are consistent
+    pub fn validate(&self) -> Result<(), String> {
+        if let (Some(archive_after), Some(delete_after)) =
+            (self.archive_after_days, self.delete_after_days)
+        {
+            if archive_after >= delete_after {
+                return Err("archive_after_days must be lower than delete_after_days".to_string());
+            }
+        }
+        if let (Some(hot), Some(archive_after)) = (self.hot_days, self.archive_after_days) {
+            if hot > archive_after {
+                return Err("hot_days cannot exceed archive_after_days".to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Timestamp `days` days before `now`, in nanoseconds
+fn cutoff(now: u64, days: Option<u64>) -> Option<u64> {
+    days.map(|days| now.saturating_sub(days.saturating_mul(NANOS_PER_DAY)))
+}
+
+/// Registry entry describing a payload type accepted by the indexer
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug, PartialEq, Eq)]
+pub struct PayloadTypeConfig {
+    pub name: String,
+    /// Maximum size of the raw payload, in bytes
+    pub max_payload_size: u64,
+    /// Message types that may be submitted for this payload type
+    pub allowed_msg_types: BTreeSet<MessageType>,
+    pub retention: RetentionPolicy,
+    /// Whether the payload must be valid Candid
+    pub require_candid: bool,
+    /// Whether the text fields of the payload are indexed for full-text search
+    #[serde(default)]
+    pub searchable: bool,
+    /// Layout version of the payload; new messages are stored with this
+    /// version and older messages are re-encoded by the schema migration job
+    #[serde(default = "initial_schema_version")]
+    pub schema_version: u32,
+}
+
+fn initial_schema_version() -> u32 {
+    INITIAL_SCHEMA_VERSION
+}
+
+impl PayloadTypeConfig {
+    /// Configuration of a built-in payload type
+    fn builtin(name: &str) -> Self {
+        // Shared plays are ephemeral, everything else is kept forever
+        let retention = match name {
+            "MsgSharePlay" => RetentionPolicy {
+                delete_after_days: Some(SHARE_PLAY_EXPIRY_DAYS),
+                ..RetentionPolicy::default()
+            },
+            _ => RetentionPolicy::default(),
+        };
+
+        Self {
+            name: name.to_string(),
+            max_payload_size: DEFAULT_MAX_PAYLOAD_SIZE,
+            allowed_msg_types: BTreeSet::from([
+                MessageType::Create,
+                MessageType::Update,
+                MessageType::Replace,
+                MessageType::Delete,
+            ]),
+            retention,
+            require_candid: true,
+            searchable: name == "MsgUserPost" || name == COMMENT_PAYLOAD_TYPE,
+            schema_version: builtin_schema_version(name).unwrap_or(INITIAL_SCHEMA_VERSION),
+        }
+    }
+
+    /// Check a message against this configuration before it is stored
+    pub fn validate(&self, msg: &Message) -> Result<(), IndexerError> {
+        if !self.allowed_msg_types.contains(&msg.msg_type) {
+            return Err(IndexerError::MessageTypeNotAllowed(msg.msg_type.clone()));
+        }
+
+        let size = msg.payload.len() as u64;
+        if size > self.max_payload_size {
+            return Err(IndexerError::PayloadTooLarge {
+                size,
+                max_size: self.max_payload_size,
+            });
+        }
+
+        if self.require_candid {
+            decode_payload(msg)?;
+        }
+        Ok(())
+    }
+}
+
+/// Payload types known to the indexer when it is installed
+pub fn builtin_payload_types() -> BTreeMap<String, PayloadTypeConfig> {
+    BUILTIN_SCHEMA_VERSIONS
+        .into_iter()
+        .map(|(name, _)| (name.to_string(), PayloadTypeConfig::builtin(name)))
+        .collect()
+}
+
+/// Schema version of a built-in payload type in this build, None for other types
+pub fn builtin_schema_version(name: &str) -> Option<u32> {
+    BUILTIN_SCHEMA_VERSIONS
+        .into_iter()
+        .find(|(builtin, _)| *builtin == name)
+        .map(|(_, version)| version)
+}
+
+/// Decode a payload, using the typed struct for built-in payload types in any namespace
+fn decode_payload(msg: &Message) -> Result<(), IndexerError> {
+    let msg_id = &msg.msg_id;
+
+    match namespace::base_type(&msg.payload_type) {
+        "MsgUserInfo" => {
+            let user_info: MsgUserInfo = msg
+                .decode_payload()
+                .map_err(IndexerError::PayloadDecodeFailed)?;
+            print(format!(
+                "Received user info for id {}: {:?}",
+                msg_id, user_info
+            ));
+        }
+        "MsgUserPost" => {
+            let user_post: MsgUserPost = msg
+                .decode_payload()
+                .map_err(IndexerError::PayloadDecodeFailed)?;
+            print(format!(
+                "Received user post for id {}: {:?}",
+                msg_id, user_post
+            ));
+        }
+        "MsgSharePlay" => {
+            let _share_game: MsgSharePlay = msg
+                .decode_payload()
+                .map_err(IndexerError::PayloadDecodeFailed)?;
+        }
+        COMMENT_PAYLOAD_TYPE => {
+            let comment: MsgComment = msg
+                .decode_payload()
+                .map_err(IndexerError::PayloadDecodeFailed)?;
+            if comment.parent_payload_type.is_empty() || comment.parent_msg_id.is_empty() {
+                return Err(IndexerError::InvalidMessage(
+                    "Comment must reference a parent message".to_string(),
+                ));
+            }
+        }
+        _ => {
+            // Runtime-registered types have no Rust struct; `reserved` accepts any well-formed value
+            Decode!(msg.payload.as_slice(), candid::Reserved)
+                .map_err(|e| IndexerError::PayloadDecodeFailed(e.to_string()))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{comment, post, principal};
+
+    const NOW: u64 = 30 * NANOS_PER_DAY;
+
+    /// Messages are checked against the message types, size and layout of their entry
+    #[test]
+    fn validate_applies_the_registry_entry() {
+        let parent = post("a", MessageType::Create, principal(1));
+        let reply = comment("c1", &parent, 2, principal(1));
+        let config = PayloadTypeConfig::builtin(COMMENT_PAYLOAD_TYPE);
+        assert_eq!(config.validate(&reply), Ok(()));
+
+        let delete_only = PayloadTypeConfig {
+            allowed_msg_types: BTreeSet::from([MessageType::Delete]),
+            ..config.clone()
+        };
+        assert_eq!(
+            delete_only.validate(&reply),
+            Err(IndexerError::MessageTypeNotAllowed(MessageType::Create))
+        );
+
+        let small = PayloadTypeConfig { max_payload_size: 4, ..config.clone() };
+        assert!(matches!(small.validate(&reply), Err(IndexerError::PayloadTooLarge { .. })));
+
+        // "hello" is not Candid, which only matters if Candid is required
+        let raw = Message { payload_type: "custom".to_string(), ..parent };
+        let custom = PayloadTypeConfig { name: "custom".to_string(), ..config };
+        assert!(matches!(custom.validate(&raw), Err(IndexerError::PayloadDecodeFailed(_))));
+        let unchecked = PayloadTypeConfig { require_candid: false, ..custom };
+        assert_eq!(unchecked.validate(&raw), Ok(()));
+    }
+
+    /// Age rules turn into timestamp cutoffs, and missing rules into none
+    #[test]
+    fn retention_cutoffs_follow_the_age_rules() {
+        let policy = RetentionPolicy {
+            hot_days: Some(1),
+            archive_after_days: Some(7),
+            delete_after_days: Some(30),
+            ..RetentionPolicy::default()
+        };
+        assert!(policy.has_age_rules());
+        assert_eq!(policy.hot_cutoff(NOW), 29 * NANOS_PER_DAY);
+        assert_eq!(policy.archive_cutoff(NOW), Some(23 * NANOS_PER_DAY));
+        assert_eq!(policy.delete_cutoff(NOW), Some(0));
+        assert_eq!(policy.validate(), Ok(()));
+
+        let default = RetentionPolicy::default();
+        assert!(!default.has_age_rules());
+        assert_eq!(default.hot_cutoff(NOW), u64::MAX);
+        assert_eq!(default.delete_cutoff(NOW), None);
+        assert_eq!(
+            PayloadTypeConfig::builtin("MsgSharePlay").retention.delete_cutoff(NOW),
+            Some(NOW - SHARE_PLAY_EXPIRY_DAYS * NANOS_PER_DAY)
+        );
+    }
+
+    #[test]
+    fn retention_rules_must_be_ordered() {
+        let policy = |hot_days, archive_after_days, delete_after_days| RetentionPolicy {
+            hot_days,
+            archive_after_days,
+            delete_after_days,
+            ..RetentionPolicy::default()
+        };
+        assert!(policy(None, Some(7), Some(7)).validate().is_err());
+        assert!(policy(Some(8), Some(7), None).validate().is_err());
+        assert!(policy(Some(7), Some(7), Some(8)).validate().is_ok());
+    }
+}
@@ -0,0 +1,194 @@
+use candid::{CandidType, Decode, Encode};
+use serde::{Deserialize, Serialize};
+
+use crate::discussion::{MsgComment, COMMENT_PAYLOAD_TYPE};
+
+/// Schema version of payload types registered without one and of messages
+/// stored before schema versions were tracked
+pub const INITIAL_SCHEMA_VERSION: u32 = 1;
+
+/// Current schema versions of the built-in payload types
+///
+/// Bump the version of a type together with its struct, in `canister_types`
+/// or `discussion`, and register a migration from the previous version in
+/// `PAYLOAD_MIGRATIONS`.
+pub const BUILTIN_SCHEMA_VERSIONS: [(&str, u32); 4] = [
+    ("MsgUserInfo", INITIAL_SCHEMA_VERSION),
+    ("MsgUserPost", INITIAL_SCHEMA_VERSION),
+    ("MsgSharePlay", INITIAL_SCHEMA_VERSION),
+    (COMMENT_PAYLOAD_TYPE, 2),
+];
+
+/// Re-encoding of a payload from one schema version to the next
+///
+/// `migrate` receives the Candid payload encoded with the layout of
+/// `from_version` and returns it encoded with the layout of `from_version + 1`,
+/// typically by decoding a copy of the old struct and encoding the new one.
+pub struct PayloadMigration {
+    /// Unqualified payload type, the migration applies in every namespace
+    pub payload_type: &'static str,
+    pub from_version: u32,
+    pub migrate: fn(&[u8]) -> Result<Vec<u8>, String>,
+}
+
+/// Migrations of every payload type, compiled into the indexer
+pub const PAYLOAD_MIGRATIONS: &[PayloadMigration] = &[PayloadMigration {
+    payload_type: COMMENT_PAYLOAD_TYPE,
+    from_version: 1,
+    migrate: migrate_comment_v1,
+}];
+
+/// `MsgComment` on schema version 1, before `edited_at`
+#[derive(CandidType, Deserialize)]
+struct MsgCommentV1 {
+    parent_payload_type: String,
+    parent_msg_id: String,
+    text: String,
+}
+
+fn migrate_comment_v1(payload: &[u8]) -> Result<Vec<u8>, String> {
+    let comment = Decode!(payload, MsgCommentV1).map_err(|err| err.to_string())?;
+    Encode!(&MsgComment {
+        parent_payload_type: comment.parent_payload_type,
+        parent_msg_id: comment.parent_msg_id,
+        text: comment.text,
+        edited_at: None,
+    })
+    .map_err(|err| err.to_string())
+}
+
+/// Number of stored messages of a payload type on each schema version
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug)]
+pub struct SchemaVersionStats {
+    pub payload_type: String,
+    /// Schema version new messages of the type are stored with
+    pub current_version: u32,
+    /// Stored messages per schema version, oldest version first
+    pub messages_by_version: Vec<(u32, u64)>,
+}
+
+impl SchemaVersionStats {
+    /// Number of stored messages still on an older schema version
+    pub fn outdated(&self) -> u64 {
+        self.messages_by_version
+            .iter()
+            .filter(|(version, _)| *version < self.current_version)
+            .map(|(_, count)| count)
+            .sum()
+    }
+}
+
+/// Migration of a payload type from a schema version, if one is registered
+fn migration(payload_type: &str, from_version: u32) -> Option<&'static PayloadMigration> {
+    PAYLOAD_MIGRATIONS.iter().find(|migration| {
+        migration.payload_type == payload_type && migration.from_version == from_version
+    })
+}
+
+/// Check that a payload type can be migrated from one schema version to another
+pub fn check_migration_path(
+    payload_type: &str,
+    from_version: u32,
+    to_version: u32,
+) -> Result<(), String> {
+    for version in from_version..to_version {
+        if migration(payload_type, version).is_none() {
+            return Err(missing_migration(payload_type, version));
+        }
+    }
+    Ok(())
+}
+
+fn missing_migration(payload_type: &str, version: u32) -> String {
+    format!("No migration of {} from schema version {} is registered", payload_type, version)
+}
+
+/// Re-encode a payload from its schema version to a newer one, one version at a time
+pub fn migrate_payload(
+    payload_type: &str,
+    from_version: u32,
+    to_version: u32,
+    payload: &[u8],
+) -> Result<Vec<u8>, String> {
+    let mut payload = payload.to_vec();
+    for version in from_version..to_version {
+        let migration = migration(payload_type, version)
+            .ok_or_else(|| missing_migration(payload_type, version))?;
+        payload = (migration.migrate)(&payload).map_err(|err| {
+            format!(
+                "Migration of {} from schema version {} failed: {}",
+                payload_type, version, err
+            )
+        })?;
+    }
+    Ok(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn comment_v1() -> Vec<u8> {
+        Encode!(&MsgCommentV1 {
+            parent_payload_type: "MsgUserPost".to_string(),
+            parent_msg_id: "post-1".to_string(),
+            text: "nice".to_string(),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn comments_migrate_to_version_2() {
+        let payload = migrate_payload(COMMENT_PAYLOAD_TYPE, 1, 2, &comment_v1()).unwrap();
+        let comment = Decode!(&payload, MsgComment).unwrap();
+
+        assert_eq!(comment.parent_payload_type, "MsgUserPost");
+        assert_eq!(comment.parent_msg_id, "post-1");
+        assert_eq!(comment.text, "nice");
+        assert_eq!(comment.edited_at, None);
+    }
+
+    #[test]
+    fn migrating_to_the_same_version_keeps_the_payload() {
+        assert_eq!(
+            migrate_payload("MsgUserPost", 1, 1, b"raw"),
+            Ok(b"raw".to_vec())
+        );
+    }
+
+    /// Every step between two versions needs a registered migration
+    #[test]
+    fn migration_paths_are_checked() {
+        assert_eq!(check_migration_path(COMMENT_PAYLOAD_TYPE, 1, 2), Ok(()));
+        assert!(check_migration_path(COMMENT_PAYLOAD_TYPE, 1, 3).is_err());
+        assert!(check_migration_path("MsgUserPost", 1, 2).is_err());
+        assert!(migrate_payload(COMMENT_PAYLOAD_TYPE, 1, 3, &comment_v1()).is_err());
+    }
+
+    #[test]
+    fn failed_migration_names_the_step() {
+        let error = migrate_payload(COMMENT_PAYLOAD_TYPE, 1, 2, b"not candid").unwrap_err();
+        assert!(error.starts_with("Migration of MsgComment from schema version 1 failed"));
+    }
+
+    /// Built-in versions above the initial one must be reachable by migrations
+    #[test]
+    fn builtin_versions_have_migration_paths() {
+        for (payload_type, version) in BUILTIN_SCHEMA_VERSIONS {
+            assert_eq!(
+                check_migration_path(payload_type, INITIAL_SCHEMA_VERSION, version),
+                Ok(())
+            );
+        }
+    }
+
+    #[test]
+    fn outdated_counts_older_versions() {
+        let stats = SchemaVersionStats {
+            payload_type: COMMENT_PAYLOAD_TYPE.to_string(),
+            current_version: 2,
+            messages_by_version: vec![(1, 5), (2, 7)],
+        };
+        assert_eq!(stats.outdated(), 5);
+    }
+}
@@ -1,17 +1,37 @@
 use candid::Principal;
-use canister_types::message::Message;
+use canister_types::message::{Message, MessageSource};
 use ic_cdk::query;
 
-use crate::data_storage;
+use crate::{
+    certification::{self, CertifiedMsg, CertifiedMsgPage, MAX_CERTIFIED_PAGE_SIZE},
+    data_storage::{
+        self, engagement, social, subscriptions, ArchiveCanisterInfo, FollowCounts, FollowPage,
+        Subscription,
+    },
+    discussion::{ReactionSummary, ThreadNode, MAX_THREAD_DEPTH, MAX_THREAD_REPLIES},
+    http_gateway::{self, HttpRequest, HttpResponse},
+    pagination::{MsgCursor, MsgPage, TimelineQuery},
+    namespace::NamespaceConfig,
+    payload_registry::PayloadTypeConfig,
+    payload_schema::SchemaVersionStats,
+    rate_limit::RateLimitUsage,
+    signatures::MsgVerification,
+    analytics::{ActivityBucket, CounterWindow, RankedSubject, TrendingQuery},
+    text_search::{SearchPage, SearchQuery, MAX_SEARCH_RESULTS},
+    MAX_HISTORY_MSG_COUNT, MAX_MSG_COUNT,
+};
 
 /// Query function to retrieve message count statistics
 /// 
+/// # Arguments
+/// * `namespace` - Key of the namespace to count, or None for the global messages
+///
 /// Returns a tuple containing:
 /// - Vec<(String, usize)>: List of message types with their respective counts
 /// - usize: Total count of all messages across all types
 #[query]
-fn retrieve_msg_count() -> (Vec<(String, usize)>, usize) {
-    data_storage::message::get_message_size()
+fn retrieve_msg_count(namespace: Option<String>) -> (Vec<(String, usize)>, usize) {
+    data_storage::message::get_message_size(namespace.as_deref())
 }
 
 /// Query function to fetch a specific message by its type and ID
@@ -27,13 +47,41 @@ fn fetch_msg(msg_type: String, msg_id: String) -> Option<(Message, Principal)> {
     data_storage::message::get_message(&msg_type, &msg_id)
 }
 
+/// Query function to fetch a message with a proof that the indexer stores it
+///
+/// # Arguments
+/// * `msg_type` - The type/category of the message
+/// * `msg_id` - The unique identifier of the message
+///
+/// # Returns
+/// * `CertifiedMsg` - The live or archived message if found, with the certificate
+///   and a witness for `msgs/<msg_type>/<msg_id>`, proving absence if not found
+#[query]
+fn fetch_certified_msg(msg_type: String, msg_id: String) -> CertifiedMsg {
+    let message = data_storage::message::get_stored_message(&msg_type, &msg_id);
+    certification::certified_msg(&msg_type, &msg_id, message)
+}
+
+/// Query function to get the signature verification of a message
+///
+/// # Returns
+/// * `Option<MsgVerification>` - The verified signature of the author, None if
+///   the message was stored without a signature or does not exist
+#[query]
+fn fetch_msg_verification(msg_type: String, msg_id: String) -> Option<MsgVerification> {
+    data_storage::signatures::verification(&msg_type, &msg_id)
+}
+
 /// Query function to get all available message categories/types
 /// 
+/// # Arguments
+/// * `namespace` - Key of the namespace to list, or None for the global messages
+///
 /// # Returns
-/// * `Vec<String>` - List of all message type keys currently stored
+/// * `Vec<String>` - List of the message type keys of the namespace currently stored
 #[query]
-fn get_msg_categories() -> Vec<String> {
-    data_storage::message::get_message_keys()
+fn get_msg_categories(namespace: Option<String>) -> Vec<String> {
+    data_storage::message::get_message_keys(namespace.as_deref())
 }
 
 /// Query function to fetch a batch of messages with pagination support
@@ -69,7 +117,8 @@ fn fetch_msg_batch(
 /// * `Vec<(Message, Principal)>` - List of messages associated with the specified user
 /// 
 /// # Note
-/// This function filters messages by the principal ID that created them
+/// Messages are looked up through the principal index, returned newest first
+/// and include messages that have been moved to the archive store
 #[query]
 fn fetch_msg_by_user(
     msg_type: String,
@@ -101,3 +150,555 @@ fn fetch_archive_msg_batch(
 ) -> Vec<(Message, Principal)> {
     data_storage::history::get_history_message_list(msg_type.as_str(), max_count, start_pos)
 }
+
+/// Query function to fetch a page of messages using cursor pagination
+///
+/// Unlike `fetch_msg_batch`, pages stay stable when new messages arrive
+/// between calls: each page continues strictly after the cursor returned
+/// with the previous one.
+///
+/// # Arguments
+/// * `msg_type` - The type/category of messages to retrieve
+/// * `max_count` - Maximum number of messages to return (capped at `MAX_MSG_COUNT`)
+/// * `cursor` - `next_cursor` of the previous page, or None for the first page
+///
+/// # Returns
+/// * `Result<MsgPage, String>` - The page with its `next_cursor`, or an error for an invalid cursor
+///
+/// # Note
+/// Messages are returned sorted by timestamp in descending order (newest first)
+#[query]
+fn fetch_msg_page(
+    msg_type: String,
+    max_count: usize,
+    cursor: Option<String>,
+) -> Result<MsgPage, String> {
+    let cursor = MsgCursor::decode_opt(cursor)?;
+    let limit = max_count.min(MAX_MSG_COUNT as usize);
+    Ok(data_storage::message::get_message_page(&msg_type, limit, cursor.as_ref()))
+}
+
+/// Query function to fetch a page of messages with a proof for each of them
+///
+/// # Arguments
+/// * `msg_type` - The type/category of messages to retrieve
+/// * `max_count` - Maximum number of messages to return (capped at `MAX_CERTIFIED_PAGE_SIZE`)
+/// * `cursor` - `next_cursor` of the previous page, or None for the first page
+///
+/// # Returns
+/// * `Result<CertifiedMsgPage, String>` - The page as returned by `fetch_msg_page`,
+///   with the certificate and one witness covering every message of the page
+///
+/// # Note
+/// The witness proves that each returned message is stored as is, not that the
+/// page is complete
+#[query]
+fn fetch_certified_msg_page(
+    msg_type: String,
+    max_count: usize,
+    cursor: Option<String>,
+) -> Result<CertifiedMsgPage, String> {
+    let cursor = MsgCursor::decode_opt(cursor)?;
+    let limit = max_count.min(MAX_MSG_COUNT as usize).min(MAX_CERTIFIED_PAGE_SIZE);
+    let page = data_storage::message::get_message_page(&msg_type, limit, cursor.as_ref());
+    Ok(certification::certified_page(page))
+}
+
+/// Query function to fetch a page of a user's messages using cursor pagination
+///
+/// # Arguments
+/// * `msg_type` - The type/category of messages to retrieve
+/// * `user_id` - The principal ID of the user whose messages to retrieve
+/// * `max_count` - Maximum number of messages to return (capped at `MAX_MSG_COUNT`)
+/// * `cursor` - `next_cursor` of the previous page, or None for the first page
+///
+/// # Returns
+/// * `Result<MsgPage, String>` - The page with its `next_cursor`, or an error for an invalid cursor
+///
+/// # Note
+/// Messages are returned newest first and include archived messages
+#[query]
+fn fetch_msg_page_by_user(
+    msg_type: String,
+    user_id: Principal,
+    max_count: usize,
+    cursor: Option<String>,
+) -> Result<MsgPage, String> {
+    let cursor = MsgCursor::decode_opt(cursor)?;
+    let limit = max_count.min(MAX_MSG_COUNT as usize);
+    Ok(data_storage::message::get_message_page_by_pid(
+        &msg_type,
+        user_id,
+        limit,
+        cursor.as_ref(),
+    ))
+}
+
+/// Query function to fetch a page of a user's messages with a proof for each of them
+///
+/// # Arguments
+/// * `msg_type` - The type/category of messages to retrieve
+/// * `user_id` - The principal ID of the user whose messages to retrieve
+/// * `max_count` - Maximum number of messages to return (capped at `MAX_CERTIFIED_PAGE_SIZE`)
+/// * `cursor` - `next_cursor` of the previous page, or None for the first page
+///
+/// # Returns
+/// * `Result<CertifiedMsgPage, String>` - The page as returned by `fetch_msg_page_by_user`,
+///   with the certificate and one witness covering every message of the page
+///
+/// # Note
+/// The witness proves that each returned message is stored as is, not that the
+/// page is complete
+#[query]
+fn fetch_certified_msg_page_by_user(
+    msg_type: String,
+    user_id: Principal,
+    max_count: usize,
+    cursor: Option<String>,
+) -> Result<CertifiedMsgPage, String> {
+    let cursor = MsgCursor::decode_opt(cursor)?;
+    let limit = max_count.min(MAX_MSG_COUNT as usize).min(MAX_CERTIFIED_PAGE_SIZE);
+    let page = data_storage::message::get_message_page_by_pid(
+        &msg_type,
+        user_id,
+        limit,
+        cursor.as_ref(),
+    );
+    Ok(certification::certified_page(page))
+}
+
+/// Query function to fetch a page of the messages about a resource
+///
+/// # Arguments
+/// * `resource` - The canister, resource type and resource id the messages refer to
+/// * `max_count` - Maximum number of messages to return (capped at `MAX_MSG_COUNT`)
+/// * `cursor` - `next_cursor` of the previous page, or None for the first page
+/// * `namespace` - Key of the namespace to list, or None for the global messages
+///
+/// # Returns
+/// * `Result<MsgPage, String>` - The page with its `next_cursor`, or an error for an invalid cursor
+///
+/// # Note
+/// Messages of all payload types of the namespace are returned newest first and
+/// include archived messages
+#[query]
+fn fetch_msg_page_by_resource(
+    resource: MessageSource,
+    max_count: usize,
+    cursor: Option<String>,
+    namespace: Option<String>,
+) -> Result<MsgPage, String> {
+    let cursor = MsgCursor::decode_opt(cursor)?;
+    let limit = max_count.min(MAX_MSG_COUNT as usize);
+    Ok(data_storage::message::get_message_page_by_resource(
+        namespace.as_deref(),
+        resource.canister_id,
+        &resource.resource_type,
+        Some(resource.resource_id),
+        limit,
+        cursor.as_ref(),
+    ))
+}
+
+/// Query function to fetch a page of the messages about a resource with a proof
+/// for each of them
+///
+/// # Arguments
+/// * `resource` - The canister, resource type and resource id the messages refer to
+/// * `max_count` - Maximum number of messages to return (capped at `MAX_CERTIFIED_PAGE_SIZE`)
+/// * `cursor` - `next_cursor` of the previous page, or None for the first page
+/// * `namespace` - Key of the namespace to list, or None for the global messages
+///
+/// # Returns
+/// * `Result<CertifiedMsgPage, String>` - The page as returned by `fetch_msg_page_by_resource`,
+///   with the certificate and one witness covering every message of the page
+///
+/// # Note
+/// The witness proves that each returned message is stored as is, not that the
+/// page is complete
+#[query]
+fn fetch_certified_msg_page_by_resource(
+    resource: MessageSource,
+    max_count: usize,
+    cursor: Option<String>,
+    namespace: Option<String>,
+) -> Result<CertifiedMsgPage, String> {
+    let cursor = MsgCursor::decode_opt(cursor)?;
+    let limit = max_count.min(MAX_MSG_COUNT as usize).min(MAX_CERTIFIED_PAGE_SIZE);
+    let page = data_storage::message::get_message_page_by_resource(
+        namespace.as_deref(),
+        resource.canister_id,
+        &resource.resource_type,
+        Some(resource.resource_id),
+        limit,
+        cursor.as_ref(),
+    );
+    Ok(certification::certified_page(page))
+}
+
+/// Query function to fetch a page of the messages about any resource of a type
+///
+/// # Arguments
+/// * `canister_id` - The canister holding the resources
+/// * `resource_type` - The type of resources the messages refer to
+/// * `max_count` - Maximum number of messages to return (capped at `MAX_MSG_COUNT`)
+/// * `cursor` - `next_cursor` of the previous page, or None for the first page
+/// * `namespace` - Key of the namespace to list, or None for the global messages
+///
+/// # Returns
+/// * `Result<MsgPage, String>` - The page with its `next_cursor`, or an error for an invalid cursor
+///
+/// # Note
+/// Messages of all payload types of the namespace are returned newest first and
+/// include archived messages
+#[query]
+fn fetch_msg_page_by_resource_type(
+    canister_id: Principal,
+    resource_type: String,
+    max_count: usize,
+    cursor: Option<String>,
+    namespace: Option<String>,
+) -> Result<MsgPage, String> {
+    let cursor = MsgCursor::decode_opt(cursor)?;
+    let limit = max_count.min(MAX_MSG_COUNT as usize);
+    Ok(data_storage::message::get_message_page_by_resource(
+        namespace.as_deref(),
+        canister_id,
+        &resource_type,
+        None,
+        limit,
+        cursor.as_ref(),
+    ))
+}
+
+/// Query function to fetch a merged timeline of several message types
+///
+/// # Arguments
+/// * `query` - Payload types, optional creator filter, `[from_timestamp, to_timestamp)`
+///   window, page size (capped at `MAX_MSG_COUNT`) and cursor
+///
+/// # Returns
+/// * `Result<MsgPage, String>` - The page with its `next_cursor`, or an error for an
+///   invalid query or cursor
+///
+/// # Note
+/// Messages of all requested types are interleaved newest first and include
+/// messages in the local archive
+#[query]
+fn fetch_msg_timeline(query: TimelineQuery) -> Result<MsgPage, String> {
+    let query = query.normalize()?;
+    let cursor = MsgCursor::decode_opt(query.cursor.clone())?;
+    let limit = query.max_count.min(MAX_MSG_COUNT as usize);
+    Ok(data_storage::message::get_timeline_page(&query, limit, cursor.as_ref()))
+}
+
+/// Query function to fetch a merged timeline page with a proof for each message
+///
+/// # Arguments
+/// * `query` - As for `fetch_msg_timeline`, with the page size capped at
+///   `MAX_CERTIFIED_PAGE_SIZE`
+///
+/// # Returns
+/// * `Result<CertifiedMsgPage, String>` - The page as returned by `fetch_msg_timeline`,
+///   with the certificate and one witness covering every message of the page
+///
+/// # Note
+/// The witness proves that each returned message is stored as is, not that the
+/// page is complete
+#[query]
+fn fetch_certified_msg_timeline(query: TimelineQuery) -> Result<CertifiedMsgPage, String> {
+    let query = query.normalize()?;
+    let cursor = MsgCursor::decode_opt(query.cursor.clone())?;
+    let limit = query.max_count.min(MAX_MSG_COUNT as usize).min(MAX_CERTIFIED_PAGE_SIZE);
+    let page = data_storage::message::get_timeline_page(&query, limit, cursor.as_ref());
+    Ok(certification::certified_page(page))
+}
+
+/// Query function to fetch a page of archived messages using cursor pagination
+///
+/// # Arguments
+/// * `msg_type` - The type/category of archived messages to retrieve
+/// * `max_count` - Maximum number of messages to return (capped at `MAX_HISTORY_MSG_COUNT`)
+/// * `cursor` - `next_cursor` of the previous page, or None for the first page
+///
+/// # Returns
+/// * `Result<MsgPage, String>` - The page with its `next_cursor`, or an error for an invalid cursor
+///
+/// # Note
+/// Archived messages are returned newest first, walking back in time
+#[query]
+fn fetch_archive_msg_page(
+    msg_type: String,
+    max_count: usize,
+    cursor: Option<String>,
+) -> Result<MsgPage, String> {
+    let cursor = MsgCursor::decode_opt(cursor)?;
+    let limit = max_count.min(MAX_HISTORY_MSG_COUNT as usize);
+    Ok(data_storage::history::get_history_message_page(&msg_type, limit, cursor.as_ref()))
+}
+
+/// Query function to list the archive canisters holding older history
+///
+/// Once the local archive of a payload type grows too large, its oldest
+/// messages are moved into child archive canisters. Each entry describes one
+/// archive canister and the timestamp range it covers per payload type, so
+/// clients can query `fetch_msg_page` on the archive canister directly.
+///
+/// # Returns
+/// * `Vec<ArchiveCanisterInfo>` - Archive canisters, oldest first
+#[query]
+fn list_archive_canisters() -> Vec<ArchiveCanisterInfo> {
+    data_storage::archive_registry::list()
+}
+
+/// Query function to list the payload types accepted by the indexer
+///
+/// # Returns
+/// * `Vec<PayloadTypeConfig>` - Registry entries sorted by name
+#[query]
+fn list_payload_types() -> Vec<PayloadTypeConfig> {
+    data_storage::state::with(|processor| processor.payload_types.values().cloned().collect())
+}
+
+/// Query function to report the schema versions of the stored messages
+///
+/// # Returns
+/// * `Vec<SchemaVersionStats>` - Per payload type, the current schema version
+///   and the number of stored messages on each version, sorted by payload type
+///
+/// # Note
+/// Messages on an older version are re-encoded by the `SchemaMigration` job;
+/// messages handed over to archive canisters are not counted.
+#[query]
+fn get_schema_versions() -> Vec<SchemaVersionStats> {
+    data_storage::schema::stats()
+}
+
+/// Query function to list the registered namespaces
+///
+/// # Returns
+/// * `Vec<(String, NamespaceConfig)>` - Namespaces with their keys, sorted by key
+#[query]
+fn list_namespaces() -> Vec<(String, NamespaceConfig)> {
+    data_storage::state::with(|processor| {
+        processor
+            .namespaces
+            .iter()
+            .map(|(key, config)| (key.clone(), config.clone()))
+            .collect()
+    })
+}
+
+/// Query function to search the text of posts and other searchable messages
+///
+/// # Arguments
+/// * `query` - Search text, optional payload type or namespace, page size
+///   (capped at `MAX_MSG_COUNT`) and cursor
+///
+/// # Returns
+/// * `Result<SearchPage, String>` - Matches with their scores and the `next_cursor`,
+///   or an error for an empty query or an invalid cursor
+///
+/// # Note
+/// A message matches if it contains every word, `#hashtag` and `@mention` of
+/// the search text. Matches are ranked by relevance, then newest first; only
+/// the best `MAX_SEARCH_RESULTS` matches can be paged through.
+#[query]
+fn search_msgs(query: SearchQuery) -> Result<SearchPage, String> {
+    let terms = query.terms()?;
+    let offset = query.offset()?.min(MAX_SEARCH_RESULTS);
+    let limit = query.max_count.min(MAX_MSG_COUNT as usize);
+    Ok(data_storage::message::search_messages(
+        &terms,
+        query.payload_type.as_deref(),
+        query.namespace.as_deref(),
+        offset,
+        limit,
+    ))
+}
+
+/// Query function to rank the most active principals or most referenced resources
+///
+/// # Arguments
+/// * `query` - Payload type, dimension (principals or resources), window and
+///   number of subjects to return
+///
+/// # Returns
+/// * `Result<Vec<RankedSubject>, String>` - Subjects with their message counts,
+///   highest first, or an error for an invalid window
+///
+/// # Note
+/// E.g. the most shared games of the week are the top resources of
+/// `MsgSharePlay` over the last 7 daily buckets
+#[query]
+fn fetch_trending(query: TrendingQuery) -> Result<Vec<RankedSubject>, String> {
+    data_storage::counters::top(&query)
+}
+
+/// Query function to retrieve the message count of a type per time bucket
+///
+/// # Arguments
+/// * `msg_type` - The type/category of messages to count
+/// * `window` - Bucket size and time window
+///
+/// # Returns
+/// * `Result<Vec<ActivityBucket>, String>` - Non-empty buckets, oldest first,
+///   or an error for an invalid window
+#[query]
+fn fetch_msg_activity(
+    msg_type: String,
+    window: CounterWindow,
+) -> Result<Vec<ActivityBucket>, String> {
+    data_storage::counters::activity(&msg_type, &window)
+}
+
+/// Query function to retrieve the subscription of the calling canister
+#[query]
+fn get_subscription() -> Option<Subscription> {
+    subscriptions::get(&ic_cdk::caller())
+}
+
+/// Query function to retrieve the rate limit usage of the caller
+///
+/// Shows the messages the caller may submit now and its counts against the
+/// daily quotas, globally or in a namespace with its own limits.
+#[query]
+fn get_rate_limit_usage(namespace: Option<String>) -> RateLimitUsage {
+    data_storage::rate_limits::usage(ic_cdk::caller(), namespace.as_deref())
+}
+
+/// Decode the cursor of a follower or following list
+fn decode_follow_cursor(cursor: Option<String>) -> Result<Option<Principal>, String> {
+    cursor
+        .map(|cursor| Principal::from_text(&cursor).map_err(|_| format!("Invalid cursor: {}", cursor)))
+        .transpose()
+}
+
+/// Query function to list the principals following a user
+///
+/// # Arguments
+/// * `user_id` - The principal whose followers to list
+/// * `max_count` - Maximum number of principals to return (capped at `MAX_MSG_COUNT`)
+/// * `cursor` - `next_cursor` of the previous page, or None for the first page
+///
+/// # Returns
+/// * `Result<FollowPage, String>` - The followers with the time they followed,
+///   or an error for an invalid cursor
+#[query]
+fn get_followers(
+    user_id: Principal,
+    max_count: usize,
+    cursor: Option<String>,
+) -> Result<FollowPage, String> {
+    let after = decode_follow_cursor(cursor)?;
+    let limit = max_count.min(MAX_MSG_COUNT as usize);
+    Ok(social::followers(user_id, limit, after))
+}
+
+/// Query function to list the principals a user follows
+///
+/// # Arguments
+/// * `user_id` - The principal whose followed principals to list
+/// * `max_count` - Maximum number of principals to return (capped at `MAX_MSG_COUNT`)
+/// * `cursor` - `next_cursor` of the previous page, or None for the first page
+///
+/// # Returns
+/// * `Result<FollowPage, String>` - The followed principals with the time they
+///   were followed, or an error for an invalid cursor
+#[query]
+fn get_following(
+    user_id: Principal,
+    max_count: usize,
+    cursor: Option<String>,
+) -> Result<FollowPage, String> {
+    let after = decode_follow_cursor(cursor)?;
+    let limit = max_count.min(MAX_MSG_COUNT as usize);
+    Ok(social::following(user_id, limit, after))
+}
+
+/// Query function to retrieve the follower and following counts of a user
+#[query]
+fn get_follow_counts(user_id: Principal) -> FollowCounts {
+    social::counts(user_id)
+}
+
+/// Query function to fetch a page of a user's home feed
+///
+/// # Arguments
+/// * `user_id` - The principal whose feed to build
+/// * `namespace` - Key of the namespace to build the feed of, or None for the global feed
+/// * `max_count` - Maximum number of messages to return (capped at `MAX_MSG_COUNT`)
+/// * `cursor` - `next_cursor` of the previous page, or None for the first page
+///
+/// # Returns
+/// * `Result<MsgPage, String>` - The page with its `next_cursor`, or an error for an invalid cursor
+///
+/// # Note
+/// The feed merges the `MsgUserPost` and `MsgSharePlay` messages of every
+/// principal the user follows in the namespace, newest first, including
+/// archived messages
+#[query]
+fn fetch_feed(
+    user_id: Principal,
+    namespace: Option<String>,
+    max_count: usize,
+    cursor: Option<String>,
+) -> Result<MsgPage, String> {
+    let cursor = MsgCursor::decode_opt(cursor)?;
+    let limit = max_count.min(MAX_MSG_COUNT as usize);
+    Ok(social::get_feed_page(user_id, namespace.as_deref(), limit, cursor.as_ref()))
+}
+
+/// Query function to fetch a message with its thread of comments
+///
+/// # Arguments
+/// * `msg_type` - The type/category of the message
+/// * `msg_id` - The ID of the message
+/// * `max_depth` - Levels of replies to include (capped at `MAX_THREAD_DEPTH`)
+/// * `max_replies` - Maximum number of replies per message (capped at `MAX_THREAD_REPLIES`)
+///
+/// # Returns
+/// * `Option<ThreadNode>` - The message with its replies, oldest first, or None if not found
+#[query]
+fn fetch_thread(
+    msg_type: String,
+    msg_id: String,
+    max_depth: u32,
+    max_replies: usize,
+) -> Option<ThreadNode> {
+    engagement::thread(
+        &msg_type,
+        &msg_id,
+        max_depth.min(MAX_THREAD_DEPTH),
+        max_replies.min(MAX_THREAD_REPLIES),
+    )
+}
+
+/// Query function to retrieve the comment and reaction counts of a message
+///
+/// # Arguments
+/// * `msg_type` - The type/category of the message
+/// * `msg_id` - The ID of the message
+/// * `user_id` - Optional principal whose own reaction to include
+#[query]
+fn get_reaction_summary(
+    msg_type: String,
+    msg_id: String,
+    user_id: Option<Principal>,
+) -> ReactionSummary {
+    let user_reaction =
+        user_id.and_then(|user_id| engagement::reaction_of(&msg_type, &msg_id, user_id));
+    ReactionSummary {
+        engagement: engagement::get(&msg_type, &msg_id),
+        user_reaction,
+    }
+}
+
+/// Serve public JSON listings and the Atom feed of posts over HTTP
+///
+/// Responses rendered since the last write are served with an `IC-Certificate`
+/// header; other requests are upgraded to `http_request_update`.
+#[query]
+fn http_request(request: HttpRequest) -> HttpResponse {
+    http_gateway::handle_query(&request)
+}
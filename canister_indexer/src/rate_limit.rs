@@ -0,0 +1,246 @@
+use candid::{CandidType, Principal};
+use canister_types::message::Message;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::indexer_error::IndexerError;
+
+const NANOS_PER_MINUTE: u64 = 60 * 1_000_000_000;
+pub const NANOS_PER_DAY: u64 = 24 * 60 * NANOS_PER_MINUTE;
+
+/// Token bucket: `burst` messages at once, refilled at `per_minute` messages per minute
+#[derive(CandidType, Clone, Copy, Deserialize, Serialize, Debug, PartialEq, Eq)]
+pub struct RateLimit {
+    pub burst: u64,
+    pub per_minute: u64,
+}
+
+impl RateLimit {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.burst == 0 {
+            return Err("burst must be greater than 0".to_string());
+        }
+        if self.per_minute == 0 || self.per_minute > NANOS_PER_MINUTE {
+            return Err(format!("per_minute must be between 1 and {}", NANOS_PER_MINUTE));
+        }
+        Ok(())
+    }
+
+    /// Time one token takes to refill, in nanoseconds
+    fn interval(&self) -> u64 {
+        NANOS_PER_MINUTE / self.per_minute
+    }
+
+    /// Take `count` tokens from a bucket
+    ///
+    /// A bucket is kept as the time at which it is full again, which is in
+    /// the past for a full bucket.
+    ///
+    /// # Returns
+    /// The new time the bucket is full again, or the time at which the
+    /// tokens will be available
+    pub fn take(&self, full_at: u64, now: u64, count: u64) -> Result<u64, u64> {
+        let interval = self.interval();
+        let new_full_at = full_at.max(now).saturating_add(count.saturating_mul(interval));
+        let window = self.burst.saturating_mul(interval);
+        if new_full_at - now <= window {
+            Ok(new_full_at)
+        } else {
+            Err(new_full_at - window)
+        }
+    }
+
+    /// Tokens left in a bucket
+    pub fn available(&self, full_at: u64, now: u64) -> u64 {
+        let interval = self.interval();
+        let window = self.burst.saturating_mul(interval);
+        window.saturating_sub(full_at.saturating_sub(now)) / interval
+    }
+}
+
+/// Bucket applied to the interactions of every caller unless configured otherwise
+pub const DEFAULT_INTERACTION_LIMIT: RateLimit = RateLimit { burst: 60, per_minute: 60 };
+
+/// Limits applied to the writes of registered publishers and to the
+/// interactions of every caller
+///
+/// Admins and controllers are not limited.
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug, PartialEq, Eq)]
+pub struct RateLimitConfig {
+    /// Bucket of each caller, across payload types
+    pub per_caller: Option<RateLimit>,
+    /// Buckets of each caller for a payload type, keyed by payload type
+    pub per_payload_type: BTreeMap<String, RateLimit>,
+    /// Messages a caller may submit per UTC day
+    pub daily_quota: Option<u64>,
+    /// Payload bytes a caller may submit per UTC day
+    pub daily_payload_quota: Option<u64>,
+    /// Maximum payload size of any message, on top of the limit of its payload type
+    pub max_payload_size: Option<u64>,
+    /// Bucket of each caller for follows, reactions, reports and
+    /// subscriptions, None for no limit
+    ///
+    /// Only read from the global limits, namespaces share it.
+    pub per_interaction: Option<RateLimit>,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            per_caller: None,
+            per_payload_type: BTreeMap::new(),
+            daily_quota: None,
+            daily_payload_quota: None,
+            max_payload_size: None,
+            per_interaction: Some(DEFAULT_INTERACTION_LIMIT),
+        }
+    }
+}
+
+impl RateLimitConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(limit) = &self.per_caller {
+            limit.validate().map_err(|err| format!("per_caller: {}", err))?;
+        }
+        for (payload_type, limit) in &self.per_payload_type {
+            limit.validate().map_err(|err| format!("{}: {}", payload_type, err))?;
+        }
+        if let Some(limit) = &self.per_interaction {
+            limit.validate().map_err(|err| format!("per_interaction: {}", err))?;
+        }
+        Ok(())
+    }
+
+    /// Reject a message whose payload exceeds `max_payload_size`
+    pub fn check_payload_size(&self, msg: &Message) -> Result<(), IndexerError> {
+        let size = msg.payload.len() as u64;
+        match self.max_payload_size {
+            Some(max_size) if size > max_size => {
+                Err(IndexerError::PayloadTooLarge { size, max_size })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Current usage of a caller against the configured limits
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug)]
+pub struct RateLimitUsage {
+    pub principal: Principal,
+    /// Whether the principal is exempt from the limits
+    pub exempt: bool,
+    /// Messages that may be submitted now under the caller bucket, None if unlimited
+    pub available: Option<u64>,
+    /// Messages that may be submitted now per limited payload type
+    pub available_by_payload_type: Vec<(String, u64)>,
+    pub messages_today: u64,
+    pub payload_bytes_today: u64,
+    pub daily_quota: Option<u64>,
+    pub daily_payload_quota: Option<u64>,
+    /// Time at which the daily counts are reset
+    pub quota_resets_at: u64,
+    /// Interactions that may be made now, None if unlimited
+    pub interactions_available: Option<u64>,
+}
+
+/// Rejection of a call that exceeds a limit, with the time left until it fits
+pub fn rate_limited(limit: &str, now: u64, retry_at: u64) -> IndexerError {
+    IndexerError::RateLimited {
+        limit: limit.to_string(),
+        retry_after_secs: retry_at.saturating_sub(now).div_ceil(1_000_000_000),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: u64 = 1_000_000_000;
+    /// Two messages at once, one more every second
+    const LIMIT: RateLimit = RateLimit {
+        burst: 2,
+        per_minute: 60,
+    };
+    const NOW: u64 = 1_000 * SECOND;
+
+    /// A full bucket gives out `burst` tokens, then the time the next one is available
+    #[test]
+    fn take_spends_burst_then_reports_retry_time() {
+        assert_eq!(LIMIT.take(0, NOW, 1), Ok(NOW + SECOND));
+        assert_eq!(LIMIT.take(0, NOW, 2), Ok(NOW + 2 * SECOND));
+        assert_eq!(LIMIT.take(0, NOW, 3), Err(NOW + SECOND));
+
+        let full_at = LIMIT.take(0, NOW, 2).unwrap();
+        assert_eq!(LIMIT.take(full_at, NOW, 1), Err(NOW + SECOND));
+        assert_eq!(LIMIT.take(full_at, NOW + SECOND, 1), Ok(NOW + 3 * SECOND));
+    }
+
+    #[test]
+    fn available_refills_over_time() {
+        assert_eq!(LIMIT.available(0, NOW), 2);
+        assert_eq!(LIMIT.available(NOW + SECOND, NOW), 1);
+        assert_eq!(LIMIT.available(NOW + 2 * SECOND, NOW), 0);
+        assert_eq!(LIMIT.available(NOW + 2 * SECOND, NOW + SECOND), 1);
+    }
+
+    #[test]
+    fn validate_rejects_empty_and_too_fast_buckets() {
+        assert!(LIMIT.validate().is_ok());
+        assert!(RateLimit { burst: 0, ..LIMIT }.validate().is_err());
+        assert!(RateLimit {
+            per_minute: 0,
+            ..LIMIT
+        }
+        .validate()
+        .is_err());
+        assert!(RateLimit {
+            per_minute: NANOS_PER_MINUTE + 1,
+            ..LIMIT
+        }
+        .validate()
+        .is_err());
+    }
+
+    /// Interactions are limited unless configured otherwise, and invalid
+    /// buckets are reported with the limit they belong to
+    #[test]
+    fn config_defaults_and_validation() {
+        let config = RateLimitConfig::default();
+        assert_eq!(config.per_interaction, Some(DEFAULT_INTERACTION_LIMIT));
+        assert!(config.validate().is_ok());
+
+        let invalid = RateLimit { burst: 0, ..LIMIT };
+        let config = RateLimitConfig {
+            per_payload_type: BTreeMap::from([("MsgUserPost".to_string(), invalid)]),
+            ..RateLimitConfig::default()
+        };
+        assert!(config.validate().unwrap_err().starts_with("MsgUserPost: "));
+
+        let config = RateLimitConfig {
+            per_interaction: Some(invalid),
+            ..RateLimitConfig::default()
+        };
+        assert!(config
+            .validate()
+            .unwrap_err()
+            .starts_with("per_interaction: "));
+    }
+
+    #[test]
+    fn rate_limited_rounds_retry_delay_up() {
+        assert_eq!(
+            rate_limited("per_caller", NOW, NOW + SECOND + 1),
+            IndexerError::RateLimited {
+                limit: "per_caller".to_string(),
+                retry_after_secs: 2
+            }
+        );
+        assert_eq!(
+            rate_limited("per_caller", NOW, NOW),
+            IndexerError::RateLimited {
+                limit: "per_caller".to_string(),
+                retry_after_secs: 0
+            }
+        );
+    }
+}
@@ -0,0 +1,301 @@
+use candid::{CandidType, Principal};
+use canister_types::message::{Message, MessageType};
+use ic_cdk::call::Call;
+use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf;
+use sha2::{Digest, Sha256};
+
+use crate::{data_storage, indexer_error::IndexerError, TRUSTED_KEY_TTL_SECS};
+
+/// Method of the user canister returning the profile of a user
+const USER_INFO_METHOD: &str = "fetch_user_info";
+/// Prefix of the signed digest, so that a message signature cannot be
+/// replayed as a signature of any other kind of data
+pub const SIGNATURE_DOMAIN: &[u8] = b"canister_indexer:msg_signature:v1";
+
+/// Key type a message signature is made with
+#[derive(CandidType, Clone, Copy, Deserialize, Serialize, Debug, PartialEq, Eq)]
+pub enum SignatureScheme {
+    /// ECDSA on secp256k1 with the signed digest as prehash, checked against
+    /// `trusted_ecdsa_pub_key` (SEC1 encoded); the signature is the 64-byte
+    /// concatenation of r and s
+    Ecdsa,
+    /// Ed25519 over the signed digest, checked against `trusted_eddsa_pub_key`
+    Eddsa,
+}
+
+/// Detached signature of the author over the signed digest of a message, see `signed_digest`
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug)]
+pub struct MsgSignature {
+    pub scheme: SignatureScheme,
+    pub signature: ByteBuf,
+}
+
+/// Proof that a stored message was signed by its author
+///
+/// Dropped whenever the message is rewritten without a signature or removed.
+/// Kept when the payload is re-encoded to a newer schema version, with the
+/// digest of the message as it was signed.
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug)]
+pub struct MsgVerification {
+    /// The author, `caller` of the message
+    pub signer: Principal,
+    pub signature: MsgSignature,
+    pub verified_at: u64,
+    /// Digest the signature was checked against, see `signed_digest`, if the
+    /// stored payload was re-encoded since; None while the stored message is
+    /// the signed one
+    #[serde(default)]
+    pub signed_digest: Option<ByteBuf>,
+}
+
+/// Trusted keys of a user as registered in the user canister
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug)]
+pub struct TrustedKeys {
+    pub trusted_ecdsa_pub_key: Option<ByteBuf>,
+    pub trusted_eddsa_pub_key: Option<ByteBuf>,
+    /// Time the keys were fetched from the user canister
+    pub fetched_at: u64,
+}
+
+impl TrustedKeys {
+    fn key(&self, scheme: SignatureScheme) -> Option<&[u8]> {
+        match scheme {
+            SignatureScheme::Ecdsa => self.trusted_ecdsa_pub_key.as_deref(),
+            SignatureScheme::Eddsa => self.trusted_eddsa_pub_key.as_deref(),
+        }
+    }
+
+    fn is_fresh(&self, now: u64) -> bool {
+        now.saturating_sub(self.fetched_at) < TRUSTED_KEY_TTL_SECS * 1_000_000_000
+    }
+}
+
+/// The fields of the user canister's `UserInfo` read by the indexer
+#[derive(CandidType, Deserialize)]
+struct UserKeys {
+    trusted_ecdsa_pub_key: Option<ByteBuf>,
+    trusted_eddsa_pub_key: Option<ByteBuf>,
+}
+
+/// Digest signed by the author of a message
+///
+/// SHA-256 over `SIGNATURE_DOMAIN` followed by the payload type, message id,
+/// message type, timestamp, resource and payload, so that a signature only
+/// covers the exact message it was made for. Strings and byte fields are
+/// prefixed with their length as a big-endian u64, the message type is its
+/// name, e.g. `Create`, and the timestamp is a big-endian u64. The resource
+/// is a 0 byte if absent, else a 1 byte followed by the principal bytes, the
+/// resource type and the big-endian resource id.
+///
+/// The timestamp orders the signed versions of a message: a signed version
+/// older than the stored one is rejected, so replaying an old signed Update
+/// cannot roll back a later edit.
+pub fn signed_digest(msg: &Message) -> [u8; 32] {
+    fn field(hasher: &mut Sha256, bytes: &[u8]) {
+        hasher.update((bytes.len() as u64).to_be_bytes());
+        hasher.update(bytes);
+    }
+
+    let msg_type = match msg.msg_type {
+        MessageType::Create => "Create",
+        MessageType::Update => "Update",
+        MessageType::Replace => "Replace",
+        MessageType::Delete => "Delete",
+    };
+
+    let mut hasher = Sha256::new();
+    hasher.update(SIGNATURE_DOMAIN);
+    field(&mut hasher, msg.payload_type.as_bytes());
+    field(&mut hasher, msg.msg_id.as_bytes());
+    field(&mut hasher, msg_type.as_bytes());
+    hasher.update(msg.timestamp.to_be_bytes());
+    match &msg.msg_resource {
+        Some(resource) => {
+            hasher.update([1]);
+            field(&mut hasher, resource.canister_id.as_slice());
+            field(&mut hasher, resource.resource_type.as_bytes());
+            hasher.update(resource.resource_id.to_be_bytes());
+        }
+        None => hasher.update([0]),
+    }
+    field(&mut hasher, &msg.payload);
+    hasher.finalize().into()
+}
+
+/// Check a signature over a signed digest against a public key
+pub fn verify_signature(
+    scheme: SignatureScheme,
+    public_key: &[u8],
+    digest: &[u8; 32],
+    signature: &[u8],
+) -> Result<(), String> {
+    match scheme {
+        SignatureScheme::Ecdsa => {
+            use k256::ecdsa::{signature::hazmat::PrehashVerifier, Signature, VerifyingKey};
+
+            let key = VerifyingKey::from_sec1_bytes(public_key)
+                .map_err(|_| "Invalid ECDSA public key".to_string())?;
+            let signature =
+                Signature::from_slice(signature).map_err(|_| "Malformed ECDSA signature".to_string())?;
+            key.verify_prehash(digest, &signature)
+                .map_err(|_| "ECDSA signature does not match the message".to_string())
+        }
+        SignatureScheme::Eddsa => {
+            use ed25519_dalek::{Signature, VerifyingKey};
+
+            let key: &[u8; 32] = public_key
+                .try_into()
+                .map_err(|_| "Invalid Ed25519 public key".to_string())?;
+            let key = VerifyingKey::from_bytes(key)
+                .map_err(|_| "Invalid Ed25519 public key".to_string())?;
+            let signature = Signature::from_slice(signature)
+                .map_err(|_| "Malformed Ed25519 signature".to_string())?;
+            key.verify_strict(digest, &signature)
+                .map_err(|_| "Ed25519 signature does not match the message".to_string())
+        }
+    }
+}
+
+/// Fetch the trusted keys of a user from the user canister and cache them
+async fn fetch_trusted_keys(user: Principal) -> Result<TrustedKeys, IndexerError> {
+    let user_canister = data_storage::state::with(|processor| processor.user_canister)
+        .ok_or_else(|| IndexerError::KeyLookupFailed("No user canister is configured".to_string()))?;
+
+    let user_keys: Option<UserKeys> = Call::bounded_wait(user_canister, USER_INFO_METHOD)
+        .with_arg(user)
+        .await
+        .map_err(|err| IndexerError::KeyLookupFailed(format!("{} failed: {}", USER_INFO_METHOD, err)))?
+        .candid()
+        .map_err(|err| IndexerError::KeyLookupFailed(format!("{} failed: {}", USER_INFO_METHOD, err)))?;
+
+    // Unknown users are cached without keys, like users that registered none
+    let (trusted_ecdsa_pub_key, trusted_eddsa_pub_key) = match user_keys {
+        Some(user_keys) => (user_keys.trusted_ecdsa_pub_key, user_keys.trusted_eddsa_pub_key),
+        None => (None, None),
+    };
+    let keys = TrustedKeys {
+        trusted_ecdsa_pub_key,
+        trusted_eddsa_pub_key,
+        fetched_at: ic_cdk::api::time(),
+    };
+    data_storage::signatures::cache_keys(user, keys.clone());
+    Ok(keys)
+}
+
+/// Verify the signature of a message against the trusted key of its author
+///
+/// Cached keys are used for `TRUSTED_KEY_TTL_SECS`. A signature that does not
+/// match a cached key is checked once more against freshly fetched keys, so
+/// that rotated keys are picked up immediately.
+pub async fn verify_message(
+    msg: &Message,
+    signature: MsgSignature,
+) -> Result<MsgVerification, IndexerError> {
+    let author = msg.caller;
+    let now = ic_cdk::api::time();
+    let (keys, cached) = match data_storage::signatures::cached_keys(&author) {
+        Some(keys) if keys.is_fresh(now) => (keys, true),
+        _ => (fetch_trusted_keys(author).await?, false),
+    };
+
+    let digest = signed_digest(msg);
+    let check = |keys: &TrustedKeys| {
+        let key = keys.key(signature.scheme).ok_or_else(|| {
+            format!("{} has no trusted {:?} key", author, signature.scheme)
+        })?;
+        verify_signature(signature.scheme, key, &digest, &signature.signature)
+    };
+
+    let checked = match check(&keys) {
+        Err(_) if cached => check(&fetch_trusted_keys(author).await?),
+        checked => checked,
+    };
+    checked.map_err(IndexerError::InvalidSignature)?;
+
+    Ok(MsgVerification {
+        signer: author,
+        signature,
+        verified_at: ic_cdk::api::time(),
+        signed_digest: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{post, principal};
+    use canister_types::message::MessageSource;
+
+    fn message() -> Message {
+        Message {
+            msg_resource: Some(MessageSource {
+                canister_id: Principal::from_slice(&[3; 10]),
+                resource_type: "game".to_string(),
+                resource_id: 42,
+            }),
+            ..post("post-1", MessageType::Create, principal(1))
+        }
+    }
+
+    /// Fields are length-prefixed, so moving bytes between fields changes the digest
+    #[test]
+    fn digest_separates_fields() {
+        let mut shifted = message();
+        shifted.payload_type = "MsgUserPos".to_string();
+        shifted.msg_id = "tpost-1".to_string();
+        assert_ne!(signed_digest(&shifted), signed_digest(&message()));
+    }
+
+    #[test]
+    fn ecdsa_signature_verifies_only_its_message() {
+        use k256::ecdsa::{signature::hazmat::PrehashSigner, Signature, SigningKey};
+
+        let key = SigningKey::from_slice(&[7; 32]).unwrap();
+        let public_key = key.verifying_key().to_encoded_point(true);
+        let digest = signed_digest(&message());
+        let signature: Signature = key.sign_prehash(&digest).unwrap();
+        let signature = signature.to_bytes();
+
+        let verify = |digest: &[u8; 32]| {
+            verify_signature(
+                SignatureScheme::Ecdsa,
+                public_key.as_bytes(),
+                digest,
+                &signature,
+            )
+        };
+        assert_eq!(verify(&digest), Ok(()));
+
+        let mut other = message();
+        other.msg_id = "post-2".to_string();
+        assert!(verify(&signed_digest(&other)).is_err());
+        assert!(verify_signature(SignatureScheme::Ecdsa, &[4; 33], &digest, &signature).is_err());
+    }
+
+    #[test]
+    fn eddsa_signature_verifies_only_its_message() {
+        use ed25519_dalek::{Signer, SigningKey};
+
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let public_key = key.verifying_key().to_bytes();
+        let digest = signed_digest(&message());
+        let signature = key.sign(&digest).to_bytes();
+
+        let verify = |digest: &[u8; 32]| {
+            verify_signature(SignatureScheme::Eddsa, &public_key, digest, &signature)
+        };
+        assert_eq!(verify(&digest), Ok(()));
+
+        let mut other = message();
+        other.msg_type = MessageType::Delete;
+        assert!(verify(&signed_digest(&other)).is_err());
+        assert!(verify_signature(
+            SignatureScheme::Eddsa,
+            &public_key[1..],
+            &digest,
+            &signature
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,25 @@
+//! Stable memory encoding of stored types, shared with `canister_archive`
+
+/// Implement `Storable` for a type by encoding it as unbounded CBOR
+macro_rules! cbor_storable {
+    ($type:ty) => {
+        impl ::ic_stable_structures::Storable for $type {
+            const BOUND: ::ic_stable_structures::storable::Bound =
+                ::ic_stable_structures::storable::Bound::Unbounded;
+
+            fn to_bytes(&self) -> ::std::borrow::Cow<[u8]> {
+                let mut buffer = vec![];
+                ::ciborium::into_writer(self, &mut buffer)
+                    .expect(concat!("failed to encode ", stringify!($type), " data"));
+                ::std::borrow::Cow::Owned(buffer)
+            }
+
+            fn from_bytes(bytes: ::std::borrow::Cow<'_, [u8]>) -> Self {
+                ::ciborium::from_reader(&bytes[..])
+                    .expect(concat!("failed to decode ", stringify!($type), " data"))
+            }
+        }
+    };
+}
+
+pub(crate) use cbor_storable;
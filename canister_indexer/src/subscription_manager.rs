@@ -0,0 +1,82 @@
+use candid::Principal;
+use futures::future::join_all;
+use ic_cdk::call::Call;
+use std::time::Duration;
+
+use crate::{
+    data_storage::{scheduler, subscriptions, MsgEvent, SubscriptionStatus},
+    jobs::Job,
+    SUBSCRIBER_CALL_TIMEOUT_SECS, SUBSCRIPTION_BATCH_SIZE, SUBSCRIPTION_SCAN_SIZE,
+};
+
+/// Method called on subscriber canisters, with the Candid signature
+/// `on_indexer_events : (vec MsgEvent) -> ()`
+pub const SUBSCRIBER_METHOD: &str = "on_indexer_events";
+
+/// Schedule an immediate delivery run
+///
+/// Called after every change to the stored messages. Runs are coalesced by
+/// the scheduler, so calling this repeatedly is cheap.
+pub fn schedule_delivery() {
+    scheduler::schedule(Job::Delivery, Duration::ZERO);
+}
+
+/// Deliver pending events to every due subscriber
+///
+/// Each subscriber receives at most `SUBSCRIPTION_BATCH_SIZE` events per run
+/// and its cursor only advances once the call succeeded. The subscribers are
+/// called in parallel with bounded-wait calls, so a slow or unresponsive
+/// subscriber fails after `SUBSCRIBER_CALL_TIMEOUT_SECS` without holding up
+/// the others. A timed-out batch may still have been processed, so events
+/// are delivered at least once and subscribers should skip the sequence
+/// numbers they already saw. Failed deliveries are retried with exponential
+/// backoff until the subscription is dead-lettered. Runs as the `Delivery`
+/// job of the scheduler.
+pub async fn deliver_events() {
+    let now = ic_cdk::api::time();
+    let due = subscriptions::list().into_iter().filter(|subscription| {
+        subscription.status == SubscriptionStatus::Active && subscription.next_attempt_at <= now
+    });
+
+    let mut deliveries = Vec::new();
+    for subscription in due {
+        let (events, cursor) = subscriptions::pending_batch(
+            &subscription,
+            SUBSCRIPTION_SCAN_SIZE,
+            SUBSCRIPTION_BATCH_SIZE,
+        );
+        if events.is_empty() {
+            if cursor != subscription.cursor {
+                subscriptions::record_success(&subscription.subscriber, cursor, 0);
+            }
+            continue;
+        }
+        deliveries.push(deliver(subscription.subscriber, events, cursor));
+    }
+    join_all(deliveries).await;
+
+    subscriptions::prune_events();
+
+    // Come back for subscribers with remaining events or pending retries
+    if let Some(due_at) = subscriptions::next_due() {
+        let now = ic_cdk::api::time();
+        scheduler::schedule(Job::Delivery, Duration::from_nanos(due_at.saturating_sub(now)));
+    }
+}
+
+/// Deliver a batch of events to a subscriber and record the outcome
+async fn deliver(subscriber: Principal, events: Vec<MsgEvent>, cursor: u64) {
+    let count = events.len() as u64;
+    let result = Call::bounded_wait(subscriber, SUBSCRIBER_METHOD)
+        .change_timeout(SUBSCRIBER_CALL_TIMEOUT_SECS)
+        .with_arg(events)
+        .await;
+    match result {
+        Ok(_) => subscriptions::record_success(&subscriber, cursor, count),
+        Err(err) => {
+            let error = format!("{} failed: {}", SUBSCRIBER_METHOD, err);
+            ic_cdk::println!("deliver_events: delivery to {} failed: {}", subscriber, error);
+            subscriptions::record_failure(&subscriber, error, ic_cdk::api::time());
+        }
+    }
+}
@@ -0,0 +1,95 @@
+//! Setup shared by the unit tests
+//!
+//! Unit tests run outside a canister, so they only reach code that does not
+//! call the system API; the certified data is the one exception, see
+//! `certification::publish`.
+
+use candid::{Encode, Principal};
+use canister_types::message::{Message, MessageType};
+
+use crate::{
+    certification,
+    data_storage::{self, PublisherRights},
+    discussion::MsgComment,
+};
+
+/// A principal made of one repeated byte
+pub fn principal(byte: u8) -> Principal {
+    Principal::from_slice(&[byte; 29])
+}
+
+/// Start from an empty indexer, whatever an earlier test on this thread left
+pub fn reset() {
+    data_storage::reset();
+    certification::reset();
+}
+
+/// Start from an empty indexer with a controller that may publish any
+/// message, with plain byte payloads
+pub fn controller() -> Principal {
+    reset();
+    let controller = principal(7);
+    data_storage::state::with_mut(|processor| {
+        processor.controller = controller;
+        for config in processor.payload_types.values_mut() {
+            config.require_candid = false;
+        }
+    });
+    controller
+}
+
+/// Register a publisher of every message type of `MsgUserPost`
+pub fn publisher(byte: u8) -> Principal {
+    let publisher = principal(byte);
+    let rights = PublisherRights {
+        payload_types: ["MsgUserPost".to_string()].into(),
+        msg_types: [
+            MessageType::Create,
+            MessageType::Update,
+            MessageType::Replace,
+            MessageType::Delete,
+        ]
+        .into(),
+    };
+    data_storage::state::with_mut(|processor| processor.publishers.insert(publisher, rights));
+    publisher
+}
+
+/// A `MsgUserPost` message sent at timestamp 1
+pub fn post(msg_id: &str, msg_type: MessageType, caller: Principal) -> Message {
+    Message {
+        payload_type: "MsgUserPost".to_string(),
+        msg_id: msg_id.to_string(),
+        msg_type,
+        msg_resource: None,
+        timestamp: 1,
+        caller,
+        payload: b"hello".to_vec().into(),
+    }
+}
+
+/// A comment created at `timestamp` in reply to `parent`
+pub fn comment(msg_id: &str, parent: &Message, timestamp: u64, caller: Principal) -> Message {
+    let payload = Encode!(&MsgComment {
+        parent_payload_type: parent.payload_type.clone(),
+        parent_msg_id: parent.msg_id.clone(),
+        text: "nice".to_string(),
+        edited_at: None,
+    })
+    .unwrap();
+    Message {
+        payload_type: crate::discussion::comment_payload_type(&parent.payload_type),
+        msg_id: msg_id.to_string(),
+        msg_type: MessageType::Create,
+        msg_resource: None,
+        timestamp,
+        caller,
+        payload: payload.into(),
+    }
+}
+
+/// Store a message in the live store as created by `creator`
+pub fn store(message: &Message, creator: Principal) {
+    let payload_type = message.payload_type.clone();
+    data_storage::message::create_message(&payload_type, message.clone(), creator);
+}
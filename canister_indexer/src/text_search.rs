@@ -0,0 +1,296 @@
+use candid::{types::value::IDLValue, CandidType, IDLArgs, Principal};
+use canister_types::message::Message;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Maximum number of distinct terms indexed per message
+pub const MAX_TERMS_PER_MESSAGE: usize = 256;
+/// Maximum number of terms in a search query
+pub const MAX_QUERY_TERMS: usize = 8;
+/// Maximum number of ranked matches a search can page through
+pub const MAX_SEARCH_RESULTS: usize = 1000;
+/// Maximum number of messages matching every search term that are ranked
+pub const MAX_SEARCH_CANDIDATES: usize = 10_000;
+/// Maximum number of postings read per search term, bounding the work of a search
+pub const MAX_SEARCH_SCAN: usize = 100_000;
+
+/// Terms longer than this many characters are truncated
+const MAX_TERM_CHARS: usize = 64;
+/// Score multiplier of hashtag and mention terms
+const TAG_WEIGHT: u32 = 3;
+
+/// Full-text search over the messages of searchable payload types
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug)]
+pub struct SearchQuery {
+    /// Words, `#hashtags` and `@mentions` that all must occur in a match
+    pub text: String,
+    /// Only search messages of this payload type
+    pub payload_type: Option<String>,
+    /// Key of the namespace searched when no payload type is given, None for
+    /// the global messages
+    pub namespace: Option<String>,
+    pub max_count: usize,
+    /// `next_cursor` of the previous page, or None for the first page
+    pub cursor: Option<String>,
+}
+
+/// A message matching a search, with its relevance score
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug)]
+pub struct SearchHit {
+    pub message: Message,
+    pub principal: Principal,
+    pub score: u32,
+}
+
+/// One page of search results
+#[derive(CandidType, Clone, Deserialize, Serialize, Debug)]
+pub struct SearchPage {
+    /// Matches of this page, best first
+    pub hits: Vec<SearchHit>,
+    /// Cursor to pass back to fetch the next page, if any
+    pub next_cursor: Option<String>,
+}
+
+impl SearchQuery {
+    /// Offset into the ranked matches encoded in the cursor
+    pub fn offset(&self) -> Result<usize, String> {
+        match &self.cursor {
+            Some(cursor) => cursor
+                .parse()
+                .map_err(|_| format!("Invalid cursor: {}", cursor)),
+            None => Ok(0),
+        }
+    }
+
+    /// Distinct terms of the query text
+    pub fn terms(&self) -> Result<Vec<String>, String> {
+        let mut terms = tokenize(&self.text);
+        terms.sort();
+        terms.dedup();
+
+        if terms.is_empty() {
+            return Err("Search text contains no searchable terms".to_string());
+        }
+        if terms.len() > MAX_QUERY_TERMS {
+            return Err(format!("At most {} search terms are allowed", MAX_QUERY_TERMS));
+        }
+        Ok(terms)
+    }
+}
+
+/// Score contribution of one occurrence of a term
+pub fn term_weight(term: &str) -> u32 {
+    if is_tag(term) {
+        TAG_WEIGHT
+    } else {
+        1
+    }
+}
+
+/// Whether a term is a hashtag or a mention
+fn is_tag(term: &str) -> bool {
+    term.starts_with('#') || term.starts_with('@')
+}
+
+/// Text values of a Candid payload, in field order
+///
+/// Payloads that are not valid Candid have no text.
+pub fn payload_texts(payload: &[u8]) -> Vec<String> {
+    let mut texts = Vec::new();
+    if let Ok(args) = IDLArgs::from_bytes(payload) {
+        for value in &args.args {
+            collect_text(value, &mut texts);
+        }
+    }
+    texts
+}
+
+/// Count the terms occurring in the text fields of a Candid payload
+///
+/// Every text value of the decoded payload is indexed, whatever the field.
+pub fn term_frequencies(payload: &[u8]) -> BTreeMap<String, u32> {
+    let mut frequencies = BTreeMap::new();
+    for term in payload_texts(payload).iter().flat_map(|text| tokenize(text)) {
+        if frequencies.len() < MAX_TERMS_PER_MESSAGE || frequencies.contains_key(&term) {
+            *frequencies.entry(term).or_insert(0) += 1;
+        }
+    }
+    frequencies
+}
+
+/// Collect the text values nested in a Candid value
+fn collect_text(value: &IDLValue, texts: &mut Vec<String>) {
+    match value {
+        IDLValue::Text(text) => texts.push(text.clone()),
+        IDLValue::Opt(inner) => collect_text(inner, texts),
+        IDLValue::Vec(values) => values.iter().for_each(|value| collect_text(value, texts)),
+        IDLValue::Record(fields) => fields.iter().for_each(|field| collect_text(&field.val, texts)),
+        IDLValue::Variant(variant) => collect_text(&variant.0.val, texts),
+        _ => {}
+    }
+}
+
+/// Split text into lowercase search terms
+///
+/// * Latin and other alphabetic scripts are split into words
+/// * CJK runs, which have no word separators, are split into overlapping
+///   character bigrams; a lone CJK character is kept as is
+/// * `#hashtag` and `@mention` are kept with their prefix, and their words
+///   are indexed as plain terms as well
+pub fn tokenize(text: &str) -> Vec<String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut terms = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if is_cjk(c) {
+            let start = i;
+            while i < chars.len() && is_cjk(chars[i]) {
+                i += 1;
+            }
+            push_cjk_terms(&chars[start..i], &mut terms);
+        } else if is_word_char(c) {
+            let start = i;
+            while i < chars.len() && is_word_char(chars[i]) {
+                i += 1;
+            }
+            terms.push(normalize(&chars[start..i]));
+        } else if (c == '#' || c == '@')
+            && chars.get(i + 1).is_some_and(|&next| is_word_char(next) || is_cjk(next))
+        {
+            let start = i + 1;
+            i = start;
+            while i < chars.len() && (is_word_char(chars[i]) || is_cjk(chars[i])) {
+                i += 1;
+            }
+            let tag = normalize(&chars[start..i]);
+            terms.push(format!("{}{}", c, tag));
+            terms.extend(tokenize(&tag));
+        } else {
+            i += 1;
+        }
+    }
+    terms
+}
+
+/// Lowercase and truncate a term
+fn normalize(chars: &[char]) -> String {
+    chars
+        .iter()
+        .take(MAX_TERM_CHARS)
+        .flat_map(|c| c.to_lowercase())
+        .collect()
+}
+
+fn push_cjk_terms(run: &[char], terms: &mut Vec<String>) {
+    if run.len() == 1 {
+        terms.push(run[0].to_string());
+    } else {
+        terms.extend(run.windows(2).map(|pair| pair.iter().collect::<String>()));
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    (c.is_alphanumeric() && !is_cjk(c)) || c == '_'
+}
+
+/// Whether a character is indexed as CJK bigrams rather than as part of a word
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30FF}'     // Hiragana, Katakana
+        | '\u{3400}'..='\u{4DBF}'   // CJK Extension A
+        | '\u{4E00}'..='\u{9FFF}'   // CJK Unified Ideographs
+        | '\u{AC00}'..='\u{D7AF}'   // Hangul Syllables
+        | '\u{F900}'..='\u{FAFF}'   // CJK Compatibility Ideographs
+        | '\u{20000}'..='\u{2FA1F}' // CJK Extensions B-F and supplement
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candid::Encode;
+
+    fn query(text: &str, cursor: Option<&str>) -> SearchQuery {
+        SearchQuery {
+            text: text.to_string(),
+            payload_type: None,
+            namespace: None,
+            max_count: 10,
+            cursor: cursor.map(str::to_string),
+        }
+    }
+
+    /// Words are lowercased and tags are kept with their prefix next to their words
+    #[test]
+    fn tokenize_splits_words_and_tags() {
+        assert_eq!(
+            tokenize("Hello, World! #Rust_Lang @Alice"),
+            [
+                "hello",
+                "world",
+                "#rust_lang",
+                "rust_lang",
+                "@alice",
+                "alice"
+            ]
+        );
+        assert_eq!(tokenize("# @ -- !"), Vec::<String>::new());
+    }
+
+    /// CJK runs are split into bigrams, a lone CJK character is kept
+    #[test]
+    fn tokenize_splits_cjk_into_bigrams() {
+        assert_eq!(tokenize("東京都"), ["東京", "京都"]);
+        assert_eq!(tokenize("猫 cat"), ["猫", "cat"]);
+        assert_eq!(tokenize("#東京"), ["#東京", "東京"]);
+    }
+
+    #[test]
+    fn tokenize_truncates_long_terms() {
+        let terms = tokenize(&"a".repeat(MAX_TERM_CHARS + 10));
+        assert_eq!(terms, ["a".repeat(MAX_TERM_CHARS)]);
+    }
+
+    /// Every text value of a Candid payload is counted, whatever the field
+    #[test]
+    fn term_frequencies_count_text_fields() {
+        let payload = Encode!(
+            &"Hello hello".to_string(),
+            &Some("#hello world".to_string()),
+            &7u64
+        )
+        .unwrap();
+        let frequencies = term_frequencies(&payload);
+
+        assert_eq!(frequencies.get("hello"), Some(&3));
+        assert_eq!(frequencies.get("#hello"), Some(&1));
+        assert_eq!(frequencies.get("world"), Some(&1));
+        assert_eq!(frequencies.len(), 3);
+        assert!(term_frequencies(b"not candid").is_empty());
+    }
+
+    #[test]
+    fn query_terms_are_deduplicated_and_bounded() {
+        assert_eq!(
+            query("b a B", None).terms(),
+            Ok(vec!["a".to_string(), "b".to_string()])
+        );
+        assert!(query("!!", None).terms().is_err());
+        assert!(query("a b c d e f g h i", None).terms().is_err());
+    }
+
+    #[test]
+    fn query_offset_comes_from_cursor() {
+        assert_eq!(query("a", None).offset(), Ok(0));
+        assert_eq!(query("a", Some("20")).offset(), Ok(20));
+        assert!(query("a", Some("next")).offset().is_err());
+    }
+
+    #[test]
+    fn tags_weigh_more_than_words() {
+        assert!(term_weight("#rust") > term_weight("rust"));
+        assert_eq!(term_weight("@alice"), term_weight("#rust"));
+    }
+}
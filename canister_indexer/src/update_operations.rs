@@ -1,7 +1,17 @@
+use candid::Principal;
 use canister_types::message::Message;
 use ic_cdk::update;
 
-use crate::data_storage;
+use crate::{
+    access_control::{anonymous_guard, subscriber_guard},
+    data_storage::{
+        self, engagement, moderation, social, subscriptions, MsgReceipt, SubscriptionFilter,
+    },
+    discussion,
+    http_gateway::{self, HttpRequest, HttpResponse},
+    indexer_error::IndexerError,
+    signatures::{self, MsgSignature},
+};
 
 // Type alias for the result type used in this module
 type Result_0<T, E> = Result<T, E>;
@@ -16,92 +26,412 @@ type Result_0<T, E> = Result<T, E>;
 /// * `msg` - The message to be processed
 /// 
 /// # Returns
-/// * `Result_0<String, String>` - Success with message ID or error with description
+/// * `Result_0<MsgReceipt, IndexerError>` - The message ID with its outcome
+///   (created/updated/deleted/unchanged) or a typed error
 /// 
 /// # Errors
 /// * Returns error if message processing fails
-/// * Returns error if caller validation fails
+/// * Returns `DuplicateMessage` if a Create reuses the ID of a different message
+/// * Returns `PublisherNotRegistered` if the caller is not a registered publisher
+/// * Returns `NotMessageOwner` if a Delete or Update targets another principal's message
+/// * Returns `RateLimited` with the delay after which the caller may retry if
+///   a rate limit or daily quota is exceeded
 #[update]
-async fn process_single_msg(msg: Message) -> Result_0<String, String> {
+async fn process_single_msg(msg: Message) -> Result_0<MsgReceipt, IndexerError> {
     // Validate message structure before processing
-    if msg.msg_id.is_empty() {
-        return Err("Message ID cannot be empty".to_string());
-    }
-    
-    if msg.payload_type.is_empty() {
-        return Err("Payload type cannot be empty".to_string());
-    }
+    validate_structure(&msg)?;
     
     // Get the caller principal for authentication
     let caller = ic_cdk::caller();
+    data_storage::rate_limits::admit(caller, std::slice::from_ref(&msg))?;
     
     // Process the message through the data storage layer
     data_storage::message::process_message(msg, caller).await
 }
 
+/// Process a message carrying its author's detached signature over the message
+///
+/// The signature is verified against the trusted key the author (`caller`
+/// of the message) registered in the user canister, so that a message
+/// relayed by a publisher can be proven to come from its author. The stored
+/// message is then marked as verified, see `fetch_msg_verification`.
+///
+/// # Arguments
+/// * `msg` - The message to be processed
+/// * `signature` - Signature of the author over the digest of `msg`, see
+///   `signatures::signed_digest`
+///
+/// # Errors
+/// * Returns the errors of `process_single_msg`
+/// * Returns `InvalidSignature` if the author has no key of the signature's
+///   scheme or the signature does not match the message
+/// * Returns `KeyLookupFailed` if the user canister could not be queried
+#[update]
+async fn process_signed_msg(
+    msg: Message,
+    signature: MsgSignature,
+) -> Result_0<MsgReceipt, IndexerError> {
+    validate_structure(&msg)?;
+
+    let caller = ic_cdk::caller();
+    data_storage::rate_limits::admit(caller, std::slice::from_ref(&msg))?;
+    // Only accepted messages may make the indexer call the user canister
+    data_storage::message::check_message(&msg, caller)?;
+
+    let verification = signatures::verify_message(&msg, signature).await?;
+    data_storage::message::process_verified_message(msg, caller, Some(verification))
+}
+
 /// Process multiple messages in batch asynchronously
 /// 
-/// This function handles batch processing of messages with improved error handling.
-/// It processes each message individually and returns the count of successfully
-/// processed messages. If any message fails, the entire operation fails.
+/// By default every message is processed on its own and the result of each
+/// message is reported at its position in the batch, so callers can retry
+/// exactly the messages that failed. In atomic mode every message is first
+/// validated, decoded and checked against the stored data as the earlier
+/// messages of the batch leave it, so a batch may create a message and then
+/// update, delete or reply to it. The batch is only applied if all of them
+/// pass, otherwise nothing is stored.
 /// 
 /// # Arguments
 /// * `messages` - Vector of messages to be processed
+/// * `atomic` - Whether to apply the batch all-or-nothing (defaults to false)
 /// 
 /// # Returns
-/// * `Result_0<usize, String>` - Success with count of processed messages or error
+/// * `Result_0<Vec<Result_0<MsgReceipt, IndexerError>>, IndexerError>` - The outcome
+///   or typed error of each message, in batch order
 /// 
 /// # Errors
-/// * Returns error if any message processing fails
-/// * Returns error if input validation fails
-/// * Returns error if caller validation fails
+/// * Returns error if the batch is empty or too large
+/// * Returns `RateLimited` if the batch does not fit the caller's rate limits
+///   or daily quotas; no message is processed in that case
+/// * In atomic mode, returns `BatchItemFailed` with the index of the first
+///   message that would fail; no message is stored in that case
 #[update]
-async fn process_multiple_msgs(messages: Vec<Message>) -> Result_0<usize, String> {
+async fn process_multiple_msgs(
+    messages: Vec<Message>,
+    atomic: Option<bool>,
+) -> Result_0<Vec<Result_0<MsgReceipt, IndexerError>>, IndexerError> {
     // Validate input parameters
     if messages.is_empty() {
-        return Err("Cannot process empty message batch".to_string());
+        return Err(IndexerError::InvalidMessage("Cannot process empty message batch".to_string()));
     }
     
     // Check for reasonable batch size to prevent DoS attacks
     const MAX_BATCH_SIZE: usize = 100;
     if messages.len() > MAX_BATCH_SIZE {
-        return Err(format!(
+        return Err(IndexerError::InvalidMessage(format!(
             "Batch size {} exceeds maximum allowed size of {}",
             messages.len(),
             MAX_BATCH_SIZE
-        ));
+        )));
     }
     
-    // Validate each message in the batch
-    for (index, msg) in messages.iter().enumerate() {
-        if msg.msg_id.is_empty() {
-            return Err(format!("Message at index {} has empty ID", index));
-        }
-        
-        if msg.payload_type.is_empty() {
-            return Err(format!("Message at index {} has empty payload type", index));
-        }
-    }
-    
-    let mut processed_count = 0;
     let sender = ic_cdk::caller();
+    // The whole batch is charged against the rate limits of the caller, or rejected
+    data_storage::rate_limits::admit(sender, &messages)?;
+    let atomic = atomic.unwrap_or(false);
+    if atomic {
+        // Processing never yields, so the checked state cannot change before the writes
+        check_batch(&messages, sender)?;
+    }
     
-    // Process each message in the batch
+    // Process each message in the batch, collecting the result of every message
+    let mut results = Vec::with_capacity(messages.len());
     for (index, msg) in messages.into_iter().enumerate() {
-        match data_storage::message::process_message(msg, sender).await {
-            Ok(_) => {
-                processed_count += 1;
-            }
-            Err(error) => {
-                // Return detailed error information including the failed message index
-                return Err(format!(
-                    "Failed to process message at index {}: {}",
-                    index,
-                    error
-                ));
+        let result = match validate_structure(&msg) {
+            Ok(()) => data_storage::message::process_message(msg, sender).await,
+            Err(error) => Err(error),
+        };
+        if atomic {
+            // The check makes the same decisions as the writes, so this only
+            // stops a batch if the two disagree
+            if let Err(error) = result {
+                return Err(IndexerError::BatchItemFailed {
+                    index: index as u64,
+                    error: Box::new(error),
+                });
             }
         }
+        results.push(result);
     }
     
-    Ok(processed_count)
+    Ok(results)
+}
+
+/// Check that a message carries an id and a payload type
+fn validate_structure(msg: &Message) -> Result_0<(), IndexerError> {
+    if msg.msg_id.is_empty() {
+        return Err(IndexerError::InvalidMessage("Message ID cannot be empty".to_string()));
+    }
+    if msg.payload_type.is_empty() {
+        return Err(IndexerError::InvalidMessage("Payload type cannot be empty".to_string()));
+    }
+    Ok(())
+}
+
+/// Check every message of an atomic batch before anything is stored
+///
+/// Each message is checked against the stored data as the earlier messages
+/// of the batch leave it, see `data_storage::message::BatchCheck`.
+fn check_batch(messages: &[Message], caller: Principal) -> Result_0<(), IndexerError> {
+    let mut batch = data_storage::message::BatchCheck::default();
+
+    for (index, msg) in messages.iter().enumerate() {
+        let checked = validate_structure(msg).and_then(|()| batch.check(msg, caller));
+
+        checked.map_err(|error| IndexerError::BatchItemFailed {
+            index: index as u64,
+            error: Box::new(error),
+        })?;
+    }
+    Ok(())
+}
+
+/// Subscribe the calling canister to changes of the stored messages
+///
+/// New, updated and deleted messages matching the filter are delivered in
+/// batches by calling `on_indexer_events : (vec MsgEvent) -> ()` on the
+/// caller. Subscribing again replaces the filter and keeps the delivery
+/// progress. Only canisters allowed by the controller, see
+/// `allow_subscriber`, and admins may subscribe.
+///
+/// # Arguments
+/// * `filter` - Payload types and optional creator or resource to subscribe to
+///
+/// # Errors
+/// * Returns error if no payload type is given or a payload type is not registered
+/// * Returns error if the maximum number of subscriptions is reached
+/// * Returns error if the caller exceeds its interaction rate limit
+#[update(guard = "subscriber_guard")]
+fn subscribe(filter: SubscriptionFilter) -> Result_0<(), String> {
+    if filter.payload_types.is_empty() {
+        return Err("At least one payload type is required".to_string());
+    }
+    data_storage::state::with(|processor| {
+        match filter
+            .payload_types
+            .iter()
+            .find(|payload_type| processor.payload_config(payload_type).is_none())
+        {
+            Some(payload_type) => Err(format!("Unknown payload type: {}", payload_type)),
+            None => Ok(()),
+        }
+    })?;
+
+    let caller = ic_cdk::caller();
+    data_storage::rate_limits::admit_interaction(caller).map_err(|err| err.to_string())?;
+    subscriptions::subscribe(caller, filter)
+}
+
+/// Remove the subscription of the calling canister
+///
+/// # Returns
+/// * `bool` - Whether the caller was subscribed
+#[update(guard = "anonymous_guard")]
+fn unsubscribe() -> bool {
+    subscriptions::unsubscribe(&ic_cdk::caller())
+}
+
+/// Follow another principal, adding its posts and share-plays to the caller's home feed
+///
+/// # Arguments
+/// * `followee` - The principal to follow
+///
+/// # Returns
+/// * `Result_0<bool, String>` - Whether the caller did not follow the principal yet
+///
+/// # Errors
+/// * Returns error if the caller tries to follow itself or the anonymous principal
+/// * Returns error if the caller already follows `MAX_FOLLOWING` principals
+/// * Returns error if the caller exceeds its interaction rate limit
+#[update(guard = "anonymous_guard")]
+fn follow(followee: Principal) -> Result_0<bool, String> {
+    if followee == Principal::anonymous() {
+        return Err("Anonymous principal cannot be followed".to_string());
+    }
+    let caller = ic_cdk::caller();
+    data_storage::rate_limits::admit_interaction(caller).map_err(|err| err.to_string())?;
+    social::follow(caller, followee)
+}
+
+/// Stop following a principal
+///
+/// # Returns
+/// * `bool` - Whether the caller followed the principal
+#[update(guard = "anonymous_guard")]
+fn unfollow(followee: Principal) -> bool {
+    social::unfollow(ic_cdk::caller(), followee)
+}
+
+/// React to a message, replacing any previous reaction of the caller
+///
+/// Every principal keeps at most one reaction per message.
+///
+/// # Arguments
+/// * `msg_type` - The type/category of the message
+/// * `msg_id` - The ID of the message
+/// * `reaction` - A short token such as an emoji, or None to remove the reaction
+///
+/// # Returns
+/// * `Result_0<Option<String>, String>` - The previous reaction of the caller
+///
+/// # Errors
+/// * Returns error if the message does not exist or the reaction is invalid
+/// * Returns error if the caller exceeds its interaction rate limit
+#[update(guard = "anonymous_guard")]
+fn react(
+    msg_type: String,
+    msg_id: String,
+    reaction: Option<String>,
+) -> Result_0<Option<String>, String> {
+    if let Some(reaction) = &reaction {
+        discussion::validate_reaction(reaction)?;
+    }
+    let caller = ic_cdk::caller();
+    data_storage::rate_limits::admit_interaction(caller).map_err(|err| err.to_string())?;
+    engagement::react(&msg_type, &msg_id, caller, reaction)
+}
+
+/// Report a message for review by the moderators
+///
+/// A caller has at most one open report per message; reporting it again
+/// replaces the reason.
+///
+/// # Arguments
+/// * `msg_type` - The type/category of the message
+/// * `msg_id` - The ID of the message
+/// * `reason` - Why the message should be reviewed
+///
+/// # Returns
+/// * `Result_0<bool, String>` - Whether the report is new
+///
+/// # Errors
+/// * Returns error if the message does not exist or the reason is empty or too long
+/// * Returns error if the caller exceeds its interaction rate limit
+#[update(guard = "anonymous_guard")]
+fn report_msg(msg_type: String, msg_id: String, reason: String) -> Result_0<bool, String> {
+    let caller = ic_cdk::caller();
+    data_storage::rate_limits::admit_interaction(caller).map_err(|err| err.to_string())?;
+    moderation::report(&msg_type, &msg_id, caller, reason)
+}
+
+/// Render an HTTP response that is not certified yet, see `http_request`
+///
+/// The HTTP gateway calls as the anonymous principal, so renders are not
+/// charged to the caller but capped per route.
+#[update]
+fn http_request_update(request: HttpRequest) -> HttpResponse {
+    http_gateway::handle_update(&request)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{comment, controller, post, store};
+    use canister_types::message::MessageType;
+
+    fn failed_at(index: u64, error: IndexerError) -> Result_0<(), IndexerError> {
+        Err(IndexerError::BatchItemFailed {
+            index,
+            error: Box::new(error),
+        })
+    }
+
+    /// A structurally invalid message is reported at its position in the batch
+    #[test]
+    fn check_batch_reports_invalid_message_index() {
+        let caller = controller();
+        let batch = [
+            post("1", MessageType::Create, caller),
+            post("", MessageType::Create, caller),
+        ];
+
+        assert_eq!(
+            check_batch(&batch, caller),
+            failed_at(
+                1,
+                IndexerError::InvalidMessage("Message ID cannot be empty".to_string())
+            )
+        );
+    }
+
+    /// Later messages of a batch see the messages created and deleted before them
+    #[test]
+    fn check_batch_follows_earlier_messages() {
+        let caller = controller();
+        let mut changed = post("1", MessageType::Update, caller);
+        changed.payload = b"changed".to_vec().into();
+        let batch = [
+            post("1", MessageType::Create, caller),
+            changed,
+            post("1", MessageType::Delete, caller),
+            post("1", MessageType::Update, caller),
+        ];
+
+        assert_eq!(check_batch(&batch[..3], caller), Ok(()));
+        assert_eq!(
+            check_batch(&batch, caller),
+            failed_at(
+                3,
+                IndexerError::MessageNotFound {
+                    payload_type: "MsgUserPost".to_string(),
+                    msg_id: "1".to_string(),
+                }
+            )
+        );
+    }
+
+    /// A comment may reply to a message created earlier in the batch, and
+    /// deleting a message takes its stored comments with it
+    #[test]
+    fn check_batch_follows_threads() {
+        let caller = controller();
+        let stored = post("1", MessageType::Create, caller);
+        let reply = comment("c1", &stored, 2, caller);
+        store(&stored, caller);
+        store(&reply, caller);
+
+        let created = post("2", MessageType::Create, caller);
+        assert_eq!(
+            check_batch(&[created.clone(), comment("c2", &created, 2, caller)], caller),
+            Ok(())
+        );
+
+        let mut edited = reply.clone();
+        edited.msg_type = MessageType::Update;
+        assert_eq!(check_batch(&[edited.clone()], caller), Ok(()));
+        assert_eq!(
+            check_batch(&[post("1", MessageType::Delete, caller), edited], caller),
+            failed_at(
+                1,
+                IndexerError::MessageNotFound {
+                    payload_type: reply.payload_type,
+                    msg_id: "c1".to_string(),
+                }
+            )
+        );
+    }
+
+    /// Messages are checked against the stored data, and nothing is stored by the check
+    #[test]
+    fn check_batch_checks_stored_data_without_writing() {
+        let caller = controller();
+        let batch = [
+            post("1", MessageType::Create, caller),
+            post("2", MessageType::Update, caller),
+        ];
+
+        assert_eq!(
+            check_batch(&batch, caller),
+            failed_at(
+                1,
+                IndexerError::MessageNotFound {
+                    payload_type: "MsgUserPost".to_string(),
+                    msg_id: "2".to_string(),
+                }
+            )
+        );
+        assert!(data_storage::message::find_any_message("MsgUserPost", "1").is_none());
+        assert_eq!(check_batch(&batch[..1], caller), Ok(()));
+    }
 }